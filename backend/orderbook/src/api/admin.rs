@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use crate::engine::registry::InstrumentRegistry;
+use crate::models::{market_state::MarketState, trade::Trade};
+use crate::utils::error::OrderBookResult;
+
+/// Operator controls for per-symbol trading states.
+pub struct AdminApi {
+    registry: Arc<InstrumentRegistry>,
+}
+
+impl AdminApi {
+    pub fn new(registry: Arc<InstrumentRegistry>) -> Self {
+        Self { registry }
+    }
+
+    /// Sets the trading state of one symbol and returns the previous state.
+    pub fn set_market_state(&self, symbol: &str, state: MarketState) -> OrderBookResult<MarketState> {
+        let previous = self.registry.book(symbol)?.set_market_state(state);
+        log::warn!("admin: {} market state {} -> {}", symbol, previous, state);
+        Ok(previous)
+    }
+
+    pub fn halt(&self, symbol: &str) -> OrderBookResult<MarketState> {
+        self.set_market_state(symbol, MarketState::Halted)
+    }
+
+    pub fn resume(&self, symbol: &str) -> OrderBookResult<MarketState> {
+        self.set_market_state(symbol, MarketState::Open)
+    }
+
+    pub fn cancel_only(&self, symbol: &str) -> OrderBookResult<MarketState> {
+        self.set_market_state(symbol, MarketState::CancelOnly)
+    }
+
+    /// Opens the call phase of an opening or re-opening auction.
+    pub fn start_auction(&self, symbol: &str) -> OrderBookResult<MarketState> {
+        let previous = self.registry.book(symbol)?.start_auction();
+        log::warn!("admin: {} auction call phase started from {}", symbol, previous);
+        Ok(previous)
+    }
+
+    /// Uncrosses the auction and resumes continuous trading.
+    pub async fn uncross(&self, symbol: &str) -> OrderBookResult<Vec<Trade>> {
+        let book = self.registry.book(symbol)?;
+        let trades = book.uncross().await?;
+        log::warn!("admin: {} auction uncrossed", symbol);
+        Ok(trades)
+    }
+
+    /// Halts every listed symbol.
+    pub fn halt_all(&self) {
+        for book in self.registry.books() {
+            let previous = book.set_market_state(MarketState::Halted);
+            log::warn!("admin: {} market state {} -> {}", book.symbol(), previous, MarketState::Halted);
+        }
+    }
+
+    pub fn market_state(&self, symbol: &str) -> OrderBookResult<MarketState> {
+        Ok(self.registry.book(symbol)?.market_state())
+    }
+
+    pub fn market_states(&self) -> Vec<(String, MarketState)> {
+        self.registry
+            .books()
+            .iter()
+            .map(|book| (book.symbol().to_string(), book.market_state()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::instrument::Instrument;
+    use crate::models::order::{Order, OrderType, Side};
+    use crate::utils::error::OrderBookError;
+    use rust_decimal_macros::dec;
+    use tokio::sync::mpsc;
+    use uuid::Uuid;
+
+    fn setup() -> (Arc<InstrumentRegistry>, AdminApi) {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let registry = Arc::new(InstrumentRegistry::new(tx));
+        registry.list(Instrument::new("BTC", "USD")).unwrap();
+        registry.list(Instrument::new("ETH", "USD")).unwrap();
+        let admin = AdminApi::new(registry.clone());
+        (registry, admin)
+    }
+
+    #[tokio::test]
+    async fn test_halt_and_resume() {
+        let (registry, admin) = setup();
+        let order = || Order::new(Uuid::new_v4(), "BTC/USD".to_string(), Side::Buy, OrderType::Limit, dec!(100), dec!(1));
+
+        assert_eq!(admin.halt("BTC/USD").unwrap(), MarketState::Open);
+        assert!(matches!(
+            registry.submit(order()).await,
+            Err(OrderBookError::MarketStateRejected(MarketState::Halted))
+        ));
+
+        admin.resume("BTC/USD").unwrap();
+        assert!(registry.submit(order()).await.is_ok());
+    }
+
+    #[test]
+    fn test_halt_all() {
+        let (_registry, admin) = setup();
+        admin.halt_all();
+
+        assert!(admin
+            .market_states()
+            .iter()
+            .all(|(_, state)| *state == MarketState::Halted));
+        assert!(admin.market_state("SOL/USD").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reopen_with_auction() {
+        let (registry, admin) = setup();
+        let order = |side, price| Order::new(Uuid::new_v4(), "BTC/USD".to_string(), side, OrderType::Limit, price, dec!(1));
+
+        admin.halt("BTC/USD").unwrap();
+        admin.start_auction("BTC/USD").unwrap();
+        registry.submit(order(Side::Buy, dec!(101))).await.unwrap();
+        registry.submit(order(Side::Sell, dec!(100))).await.unwrap();
+
+        let trades = admin.uncross("BTC/USD").await.unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(admin.market_state("BTC/USD").unwrap(), MarketState::Open);
+    }
+}
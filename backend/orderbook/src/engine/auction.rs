@@ -0,0 +1,139 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::models::order::Side;
+
+/// Indicative result of a call auction if it were uncrossed now.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuctionIndicative {
+    pub symbol: String,
+    /// Equilibrium price, `None` while the book does not cross.
+    pub price: Option<Decimal>,
+    pub matched_volume: Decimal,
+    /// Unmatched quantity at the equilibrium price.
+    pub imbalance: Decimal,
+    pub imbalance_side: Option<Side>,
+}
+
+/// Resting interest considered by the auction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuctionLevel {
+    pub price: Decimal,
+    pub quantity: Decimal,
+}
+
+/// Equilibrium price and its volume/surplus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Equilibrium {
+    pub price: Decimal,
+    pub matched_volume: Decimal,
+    /// Buy volume minus sell volume at `price`.
+    pub surplus: Decimal,
+}
+
+/// Finds the single price that uncrosses the book.
+///
+/// Candidates are the limit prices on either side. Among them the price with
+/// the highest executable volume wins; ties are broken by the smallest
+/// surplus, then by market pressure (highest price when every tied candidate
+/// has a buy surplus, lowest when every one has a sell surplus), and finally
+/// by proximity to the reference price.
+pub fn find_equilibrium(
+    bids: &[AuctionLevel],
+    asks: &[AuctionLevel],
+    reference_price: Option<Decimal>,
+) -> Option<Equilibrium> {
+    let mut prices: Vec<Decimal> = bids.iter().chain(asks.iter()).map(|level| level.price).collect();
+    prices.sort();
+    prices.dedup();
+
+    let candidates: Vec<Equilibrium> = prices
+        .into_iter()
+        .map(|price| {
+            let buy: Decimal = bids.iter().filter(|b| b.price >= price).map(|b| b.quantity).sum();
+            let sell: Decimal = asks.iter().filter(|a| a.price <= price).map(|a| a.quantity).sum();
+            Equilibrium {
+                price,
+                matched_volume: buy.min(sell),
+                surplus: buy - sell,
+            }
+        })
+        .filter(|candidate| candidate.matched_volume > Decimal::ZERO)
+        .collect();
+
+    let max_volume = candidates.iter().map(|c| c.matched_volume).max()?;
+    let candidates: Vec<Equilibrium> = candidates
+        .into_iter()
+        .filter(|c| c.matched_volume == max_volume)
+        .collect();
+
+    let min_surplus = candidates.iter().map(|c| c.surplus.abs()).min()?;
+    let candidates: Vec<Equilibrium> = candidates
+        .into_iter()
+        .filter(|c| c.surplus.abs() == min_surplus)
+        .collect();
+
+    if candidates.iter().all(|c| c.surplus > Decimal::ZERO) {
+        return candidates.into_iter().max_by_key(|c| c.price);
+    }
+    if candidates.iter().all(|c| c.surplus < Decimal::ZERO) {
+        return candidates.into_iter().min_by_key(|c| c.price);
+    }
+
+    match reference_price {
+        Some(reference) => candidates
+            .into_iter()
+            .min_by_key(|c| ((c.price - reference).abs(), c.price)),
+        // Without a reference, take the middle of the remaining range.
+        None => candidates.get(candidates.len() / 2).copied(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn level(price: Decimal, quantity: Decimal) -> AuctionLevel {
+        AuctionLevel { price, quantity }
+    }
+
+    #[test]
+    fn test_no_cross() {
+        let bids = [level(dec!(99), dec!(1))];
+        let asks = [level(dec!(100), dec!(1))];
+        assert_eq!(find_equilibrium(&bids, &asks, None), None);
+    }
+
+    #[test]
+    fn test_maximizes_volume() {
+        let bids = [level(dec!(102), dec!(5)), level(dec!(101), dec!(5)), level(dec!(100), dec!(5))];
+        let asks = [level(dec!(99), dec!(4)), level(dec!(100), dec!(4)), level(dec!(101), dec!(4))];
+
+        let eq = find_equilibrium(&bids, &asks, None).unwrap();
+        assert_eq!(eq.price, dec!(101));
+        assert_eq!(eq.matched_volume, dec!(10));
+        assert_eq!(eq.surplus, dec!(-2));
+    }
+
+    #[test]
+    fn test_market_pressure_tie_break() {
+        // Volume 5 at both 100 and 101, buy surplus at both: pick the higher price.
+        let bids = [level(dec!(101), dec!(8))];
+        let asks = [level(dec!(100), dec!(5))];
+
+        let eq = find_equilibrium(&bids, &asks, None).unwrap();
+        assert_eq!(eq.price, dec!(101));
+        assert_eq!(eq.surplus, dec!(3));
+    }
+
+    #[test]
+    fn test_reference_price_tie_break() {
+        // Balanced at both 100 and 101: the reference price decides.
+        let bids = [level(dec!(101), dec!(5))];
+        let asks = [level(dec!(100), dec!(5))];
+
+        assert_eq!(find_equilibrium(&bids, &asks, Some(dec!(99))).unwrap().price, dec!(100));
+        assert_eq!(find_equilibrium(&bids, &asks, Some(dec!(105))).unwrap().price, dec!(101));
+    }
+}
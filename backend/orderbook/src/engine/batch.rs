@@ -0,0 +1,30 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::models::{order::Order, trade::Trade};
+use crate::utils::error::OrderBookResult;
+
+/// One operation of a batch applied to a single order book.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BatchOp {
+    New(Order),
+    Cancel(Uuid),
+    /// Changes price and/or quantity. Reducing quantity keeps time priority;
+    /// any other change re-queues the order.
+    Amend {
+        order_id: Uuid,
+        price: Option<Decimal>,
+        quantity: Option<Decimal>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BatchOutcome {
+    Placed { order_id: Uuid, trades: Vec<Trade> },
+    Cancelled(Order),
+    Amended { order: Order, trades: Vec<Trade> },
+}
+
+/// Result of each operation, in submission order.
+pub type BatchResult = Vec<OrderBookResult<BatchOutcome>>;
@@ -0,0 +1,235 @@
+use chrono::{DateTime, Duration, Utc};
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::models::trade::Trade;
+use crate::utils::clock::{Clock, SystemClock};
+
+type VolumeHistory = VecDeque<(DateTime<Utc>, Decimal)>;
+
+/// Rates applied once a user's rolling volume reaches `min_volume`.
+/// A negative `maker_rate` is a rebate paid to the maker.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeTier {
+    pub min_volume: Decimal,
+    pub maker_rate: Decimal,
+    pub taker_rate: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeSchedule {
+    tiers: Vec<FeeTier>,
+}
+
+impl FeeSchedule {
+    pub fn new(mut tiers: Vec<FeeTier>) -> Self {
+        tiers.sort_by_key(|tier| tier.min_volume);
+        Self { tiers }
+    }
+
+    /// A single tier with the same rates for every user.
+    pub fn flat(maker_rate: Decimal, taker_rate: Decimal) -> Self {
+        Self::new(vec![FeeTier {
+            min_volume: Decimal::ZERO,
+            maker_rate,
+            taker_rate,
+        }])
+    }
+
+    pub fn tiers(&self) -> &[FeeTier] {
+        &self.tiers
+    }
+
+    /// Highest tier whose volume threshold `volume` has reached.
+    pub fn tier_for(&self, volume: Decimal) -> Option<&FeeTier> {
+        self.tiers.iter().rev().find(|tier| volume >= tier.min_volume)
+    }
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        Self::flat(dec!(0.001), dec!(0.002))
+    }
+}
+
+/// Maker/taker fee engine for the order book.
+///
+/// Rates come from the symbol's schedule, tiered by each user's traded
+/// quote volume over a rolling window (30 days by default).
+pub struct FeeEngine {
+    schedules: DashMap<String, FeeSchedule>,
+    default_schedule: FeeSchedule,
+    volumes: Mutex<HashMap<Uuid, VolumeHistory>>,
+    window: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for FeeEngine {
+    fn default() -> Self {
+        Self::new(FeeSchedule::default())
+    }
+}
+
+impl FeeEngine {
+    pub fn new(default_schedule: FeeSchedule) -> Self {
+        Self::with_config(default_schedule, Duration::days(30), Arc::new(SystemClock))
+    }
+
+    pub fn with_config(default_schedule: FeeSchedule, window: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            schedules: DashMap::new(),
+            default_schedule,
+            volumes: Mutex::new(HashMap::new()),
+            window,
+            clock,
+        }
+    }
+
+    pub fn set_schedule(&self, symbol: &str, schedule: FeeSchedule) {
+        self.schedules.insert(symbol.to_string(), schedule);
+    }
+
+    pub fn schedule(&self, symbol: &str) -> FeeSchedule {
+        self.schedules
+            .get(symbol)
+            .map(|schedule| schedule.clone())
+            .unwrap_or_else(|| self.default_schedule.clone())
+    }
+
+    /// Quote volume traded by the user within the rolling window.
+    pub fn rolling_volume(&self, user_id: Uuid) -> Decimal {
+        let cutoff = self.clock.now() - self.window;
+        let mut volumes = self.volumes.lock();
+        match volumes.get_mut(&user_id) {
+            Some(history) => {
+                prune(history, cutoff);
+                history.iter().map(|(_, volume)| *volume).sum()
+            }
+            None => Decimal::ZERO,
+        }
+    }
+
+    /// (maker_rate, taker_rate) for the user on the symbol.
+    pub fn rates_for(&self, symbol: &str, user_id: Uuid) -> (Decimal, Decimal) {
+        let volume = self.rolling_volume(user_id);
+        self.schedule(symbol)
+            .tier_for(volume)
+            .map(|tier| (tier.maker_rate, tier.taker_rate))
+            .unwrap_or((Decimal::ZERO, Decimal::ZERO))
+    }
+
+    /// Stamps maker and taker fees on the trade, charged in the quote asset,
+    /// then credits the trade's volume to both users.
+    pub fn apply(&self, trade: &mut Trade, quote_asset: &str) {
+        self.stamp(trade, quote_asset);
+        self.record_trade(trade);
+    }
+
+    /// Stamps maker and taker fees on the trade without crediting volume,
+    /// for trades that may still fail to settle.
+    pub fn stamp(&self, trade: &mut Trade, quote_asset: &str) {
+        let notional = trade.notional();
+        let (maker_rate, _) = self.rates_for(&trade.symbol, trade.maker_user_id);
+        let (_, taker_rate) = self.rates_for(&trade.symbol, trade.taker_user_id);
+
+        trade.maker_fee = notional * maker_rate;
+        trade.taker_fee = notional * taker_rate;
+        trade.fee_asset = Some(quote_asset.to_string());
+    }
+
+    /// Credits a settled trade's volume to both users.
+    pub fn record_trade(&self, trade: &Trade) {
+        let notional = trade.notional();
+        self.record_volume(trade.maker_user_id, notional);
+        self.record_volume(trade.taker_user_id, notional);
+    }
+
+    pub fn record_volume(&self, user_id: Uuid, volume: Decimal) {
+        let now = self.clock.now();
+        self.volumes
+            .lock()
+            .entry(user_id)
+            .or_default()
+            .push_back((now, volume));
+    }
+}
+
+fn prune(history: &mut VolumeHistory, cutoff: DateTime<Utc>) {
+    while matches!(history.front(), Some((at, _)) if *at <= cutoff) {
+        history.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::order::{Order, OrderType, Side};
+    use crate::utils::clock::ManualClock;
+
+    fn tiered_schedule() -> FeeSchedule {
+        FeeSchedule::new(vec![
+            FeeTier { min_volume: dec!(1000000), maker_rate: dec!(-0.0001), taker_rate: dec!(0.0005) },
+            FeeTier { min_volume: Decimal::ZERO, maker_rate: dec!(0.001), taker_rate: dec!(0.002) },
+        ])
+    }
+
+    fn trade(maker: Uuid, taker: Uuid, quantity: Decimal) -> Trade {
+        let maker_order = Order::new(maker, "BTC/USD".to_string(), Side::Sell, OrderType::Limit, dec!(50000), quantity);
+        let taker_order = Order::new(taker, "BTC/USD".to_string(), Side::Buy, OrderType::Limit, dec!(50000), quantity);
+        Trade::new(&maker_order, &taker_order, quantity)
+    }
+
+    #[test]
+    fn test_fees_stamped_on_trade() {
+        let engine = FeeEngine::new(tiered_schedule());
+        let mut trade = trade(Uuid::new_v4(), Uuid::new_v4(), dec!(1));
+
+        engine.apply(&mut trade, "USD");
+
+        assert_eq!(trade.maker_fee, dec!(50));
+        assert_eq!(trade.taker_fee, dec!(100));
+        assert_eq!(trade.fee_asset.as_deref(), Some("USD"));
+    }
+
+    #[test]
+    fn test_volume_tier_gives_maker_rebate() {
+        let engine = FeeEngine::new(tiered_schedule());
+        let maker = Uuid::new_v4();
+        engine.record_volume(maker, dec!(1000000));
+
+        let mut trade = trade(maker, Uuid::new_v4(), dec!(1));
+        engine.apply(&mut trade, "USD");
+
+        assert_eq!(trade.maker_fee, dec!(-5));
+        assert_eq!(trade.taker_fee, dec!(100));
+    }
+
+    #[test]
+    fn test_rolling_window_expires_volume() {
+        let clock = Arc::new(ManualClock::new(Utc::now()));
+        let engine = FeeEngine::with_config(tiered_schedule(), Duration::days(30), clock.clone());
+        let user_id = Uuid::new_v4();
+
+        engine.record_volume(user_id, dec!(1500000));
+        assert_eq!(engine.rates_for("BTC/USD", user_id), (dec!(-0.0001), dec!(0.0005)));
+
+        clock.advance(Duration::days(31));
+        assert_eq!(engine.rolling_volume(user_id), Decimal::ZERO);
+        assert_eq!(engine.rates_for("BTC/USD", user_id), (dec!(0.001), dec!(0.002)));
+    }
+
+    #[test]
+    fn test_per_symbol_schedule() {
+        let engine = FeeEngine::default();
+        engine.set_schedule("ETH/USD", FeeSchedule::flat(Decimal::ZERO, dec!(0.0003)));
+
+        assert_eq!(engine.rates_for("ETH/USD", Uuid::new_v4()), (Decimal::ZERO, dec!(0.0003)));
+        assert_eq!(engine.rates_for("BTC/USD", Uuid::new_v4()), (dec!(0.001), dec!(0.002)));
+    }
+}
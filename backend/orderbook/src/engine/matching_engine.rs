@@ -6,7 +6,7 @@ use std::cmp::Reverse;
 use uuid::Uuid;
 use dashmap::DashMap;
 
-use crate::models::order::{Order, OrderType, Side};
+use crate::models::order::{Order, Side};
 
 #[derive(Debug)]
 pub struct MatchingEngine {
@@ -15,6 +15,12 @@ pub struct MatchingEngine {
     pub orders: Arc<DashMap<Uuid, Order>>,
 }
 
+impl Default for MatchingEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MatchingEngine {
     pub fn new() -> Self {
         Self {
@@ -25,7 +31,6 @@ impl MatchingEngine {
     }
 
     pub async fn add_order(&self, order: Order) -> Vec<Order> {
-        let order_id = order.id;
         let mut matches = Vec::new();
 
         match order.side {
@@ -92,6 +97,7 @@ impl MatchingEngine {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::order::OrderType;
     use rust_decimal_macros::dec;
     
     #[tokio::test]
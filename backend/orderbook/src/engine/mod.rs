@@ -1,2 +1,10 @@
+pub mod auction;
+pub mod batch;
 pub mod matching_engine;  // This exposes the matching_engine submodule
 pub mod orderbook;  // Add this line to expose the orderbook module
+pub mod fees;
+pub mod order_groups;
+pub mod price_bands;
+pub mod registry;
+pub mod sessions;
+pub mod trailing_stops;
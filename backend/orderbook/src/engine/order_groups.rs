@@ -0,0 +1,473 @@
+use dashmap::DashMap;
+use rust_decimal::Decimal;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::engine::registry::InstrumentRegistry;
+use crate::models::{
+    order::{Order, OrderType, Side},
+    trade::Trade,
+};
+use crate::utils::error::{OrderBookError, OrderBookResult};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupKind {
+    /// Two exits where a fill in one shrinks, and finally cancels, the other.
+    Oco,
+    /// Entry order whose take-profit and stop-loss exits grow as it fills
+    /// and then behave as an OCO pair.
+    Bracket,
+}
+
+/// One exit of a group. Resting legs sit on the book; stop legs wait here
+/// and enter as market orders once a trade reaches `stop_price`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupLeg {
+    pub order: Order,
+    pub stop_price: Option<Decimal>,
+    /// Quantity still to be filled by this leg.
+    pub open: Decimal,
+    pub active: bool,
+}
+
+impl GroupLeg {
+    pub fn resting(order: Order) -> Self {
+        Self {
+            open: order.quantity,
+            order,
+            stop_price: None,
+            active: false,
+        }
+    }
+
+    /// `order` is the market order sent when the stop triggers.
+    pub fn stop(mut order: Order, stop_price: Decimal) -> Self {
+        order.order_type = OrderType::Market;
+        order.price = Decimal::ZERO;
+        Self {
+            open: order.quantity,
+            order,
+            stop_price: Some(stop_price),
+            active: false,
+        }
+    }
+
+    fn is_stop(&self) -> bool {
+        self.stop_price.is_some()
+    }
+
+    fn triggered_by(&self, price: Decimal) -> bool {
+        match (self.stop_price, self.order.side) {
+            (Some(stop), Side::Sell) => price <= stop,
+            (Some(stop), Side::Buy) => price >= stop,
+            (None, _) => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderGroup {
+    pub id: Uuid,
+    pub kind: GroupKind,
+    pub user_id: Uuid,
+    pub symbol: String,
+    pub entry: Option<Uuid>,
+    pub legs: Vec<GroupLeg>,
+}
+
+enum Action {
+    Submit(Order),
+    /// Sets the open quantity of a resting order, cancelling it at zero.
+    Resize { symbol: String, order_id: Uuid, open: Decimal },
+    Cancel { symbol: String, order_id: Uuid },
+}
+
+/// Linked OCO and bracket orders on top of the instrument registry.
+///
+/// The manager only sees the books through submissions and the trade feed:
+/// every trade is passed to `on_trade`, which resizes, activates or cancels
+/// the other members of the filled order's group.
+pub struct OrderGroupManager {
+    registry: Arc<InstrumentRegistry>,
+    groups: DashMap<Uuid, OrderGroup>,
+    /// Group of every entry and resting leg by order id.
+    by_order: DashMap<Uuid, Uuid>,
+}
+
+impl OrderGroupManager {
+    pub fn new(registry: Arc<InstrumentRegistry>) -> Self {
+        Self {
+            registry,
+            groups: DashMap::new(),
+            by_order: DashMap::new(),
+        }
+    }
+
+    /// Places both legs of a one-cancels-other pair.
+    pub async fn submit_oco(&self, first: GroupLeg, second: GroupLeg) -> OrderBookResult<Uuid> {
+        let (user_id, symbol) = (first.order.user_id, first.order.symbol.clone());
+        if second.order.user_id != user_id || second.order.symbol != symbol {
+            return Err(OrderBookError::SymbolMismatch(second.order.symbol));
+        }
+
+        let mut group = OrderGroup {
+            id: Uuid::new_v4(),
+            kind: GroupKind::Oco,
+            user_id,
+            symbol,
+            entry: None,
+            legs: vec![first, second],
+        };
+        let mut actions = Vec::new();
+        for leg in group.legs.iter_mut() {
+            leg.active = true;
+            if !leg.is_stop() {
+                actions.push(Action::Submit(leg.order.clone()));
+            }
+        }
+        let group_id = self.register(group);
+        self.run(actions).await?;
+        Ok(group_id)
+    }
+
+    /// Places the entry; the take-profit limit and stop-loss are sized to
+    /// the entry's fills as they happen.
+    pub async fn submit_bracket(&self, entry: Order, take_profit: Decimal, stop_loss: Decimal) -> OrderBookResult<Uuid> {
+        let exit_side = match entry.side {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        };
+        let exit = |order_type, price| Order::new(entry.user_id, entry.symbol.clone(), exit_side, order_type, price, Decimal::ZERO);
+
+        let group = OrderGroup {
+            id: Uuid::new_v4(),
+            kind: GroupKind::Bracket,
+            user_id: entry.user_id,
+            symbol: entry.symbol.clone(),
+            entry: Some(entry.id),
+            legs: vec![
+                GroupLeg::resting(exit(OrderType::Limit, take_profit)),
+                GroupLeg::stop(exit(OrderType::Market, Decimal::ZERO), stop_loss),
+            ],
+        };
+        let group_id = self.register(group);
+        self.run(vec![Action::Submit(entry)]).await?;
+        Ok(group_id)
+    }
+
+    pub fn group(&self, group_id: Uuid) -> Option<OrderGroup> {
+        self.groups.get(&group_id).map(|group| group.clone())
+    }
+
+    /// Cancels the entry and every leg still working.
+    pub async fn cancel_group(&self, group_id: Uuid) -> OrderBookResult<()> {
+        let (_, group) = self
+            .groups
+            .remove(&group_id)
+            .ok_or(OrderBookError::UnknownOrderGroup(group_id))?;
+        let actions = Self::close(&group);
+        self.forget(&group);
+        self.run(actions).await
+    }
+
+    /// Applies a trade from the book's trade feed to the groups it touches.
+    pub async fn on_trade(&self, trade: &Trade) -> OrderBookResult<()> {
+        let mut actions = Vec::new();
+        for order_id in [trade.order_id, trade.taker_order_id] {
+            let Some(group_id) = self.by_order.get(&order_id).map(|group_id| *group_id) else {
+                continue;
+            };
+            if let Some(mut group) = self.groups.get_mut(&group_id) {
+                actions.extend(Self::apply_fill(&mut group, order_id, trade.quantity));
+            }
+            self.finish_if_done(group_id);
+        }
+
+        let mut result = self.run(actions).await;
+
+        let triggered: Vec<Uuid> = self
+            .groups
+            .iter()
+            .filter(|group| group.symbol == trade.symbol)
+            .filter(|group| group.legs.iter().any(|leg| leg.active && leg.open > Decimal::ZERO && leg.triggered_by(trade.price)))
+            .map(|group| group.id)
+            .collect();
+        for group_id in triggered {
+            if let Some((_, group)) = self.groups.remove(&group_id) {
+                log::info!("order group {} stop triggered at {}", group_id, trade.price);
+                self.forget(&group);
+                result = result.and(self.trigger(group, trade.price).await);
+            }
+        }
+        result
+    }
+
+    /// Feeds every trade from `trade_rx` to `on_trade` until the channel closes.
+    pub fn spawn_listener(self: Arc<Self>, mut trade_rx: UnboundedReceiver<Trade>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            while let Some(trade) = trade_rx.recv().await {
+                if let Err(err) = self.on_trade(&trade).await {
+                    log::warn!("order groups failed to handle trade {}: {}", trade.id, err);
+                }
+            }
+        })
+    }
+
+    fn register(&self, group: OrderGroup) -> Uuid {
+        let group_id = group.id;
+        for order_id in group.entry.iter().chain(group.legs.iter().filter(|leg| !leg.is_stop()).map(|leg| &leg.order.id)) {
+            self.by_order.insert(*order_id, group_id);
+        }
+        self.groups.insert(group_id, group);
+        group_id
+    }
+
+    fn forget(&self, group: &OrderGroup) {
+        for order_id in group.entry.iter().chain(group.legs.iter().map(|leg| &leg.order.id)) {
+            self.by_order.remove(order_id);
+        }
+    }
+
+    fn finish_if_done(&self, group_id: Uuid) {
+        let done = self.groups.get(&group_id).is_some_and(|group| {
+            group.entry.is_none() && group.legs.iter().all(|leg| leg.active && leg.open <= Decimal::ZERO)
+        });
+        if done {
+            if let Some((_, group)) = self.groups.remove(&group_id) {
+                self.forget(&group);
+            }
+        }
+    }
+
+    fn apply_fill(group: &mut OrderGroup, order_id: Uuid, quantity: Decimal) -> Vec<Action> {
+        let mut actions = Vec::new();
+        let symbol = group.symbol.clone();
+
+        if group.entry == Some(order_id) {
+            // Every entry fill adds the same quantity to each exit.
+            for leg in group.legs.iter_mut() {
+                leg.open += quantity;
+                if leg.is_stop() {
+                    leg.active = true;
+                } else if leg.active {
+                    actions.push(Action::Resize { symbol: symbol.clone(), order_id: leg.order.id, open: leg.open });
+                } else {
+                    leg.active = true;
+                    leg.order.quantity = leg.open;
+                    actions.push(Action::Submit(leg.order.clone()));
+                }
+            }
+            return actions;
+        }
+
+        let Some(filled) = group.legs.iter().position(|leg| leg.order.id == order_id) else {
+            return actions;
+        };
+        for (index, leg) in group.legs.iter_mut().enumerate() {
+            leg.open = (leg.open - quantity).max(Decimal::ZERO);
+            if index == filled || leg.is_stop() || !leg.active {
+                continue;
+            }
+            actions.push(Action::Resize { symbol: symbol.clone(), order_id: leg.order.id, open: leg.open });
+        }
+        // A bracket is flat once its exits are done, even if the entry was
+        // only partly filled.
+        if group.legs.iter().all(|leg| leg.open <= Decimal::ZERO) {
+            if let Some(entry) = group.entry.take() {
+                actions.push(Action::Cancel { symbol, order_id: entry });
+            }
+        }
+        actions
+    }
+
+    /// Cancels everything else in the group and sends the triggered stop to
+    /// market. A rejected stop puts the group back, so the protective leg
+    /// fires again on the next trade through it rather than being lost.
+    async fn trigger(&self, group: OrderGroup, price: Decimal) -> OrderBookResult<()> {
+        let closed = self.run(Self::close(&group)).await;
+        let Some(leg) = group
+            .legs
+            .iter()
+            .find(|leg| leg.active && leg.open > Decimal::ZERO && leg.triggered_by(price))
+        else {
+            return closed;
+        };
+        let mut order = leg.order.clone();
+        order.quantity = leg.open;
+        if let Err(err) = self.registry.submit(order).await {
+            log::warn!("order group {} stop rejected, re-arming it: {}", group.id, err);
+            self.register(group);
+            return closed.and(Err(err));
+        }
+        closed
+    }
+
+    fn close(group: &OrderGroup) -> Vec<Action> {
+        let resting = group
+            .legs
+            .iter()
+            .filter(|leg| leg.active && !leg.is_stop())
+            .map(|leg| leg.order.id);
+        group
+            .entry
+            .into_iter()
+            .chain(resting)
+            .map(|order_id| Action::Cancel { symbol: group.symbol.clone(), order_id })
+            .collect()
+    }
+
+    /// Applies every action, carrying on past a failed one so it can't
+    /// strand the rest, and returns the first error.
+    async fn run(&self, actions: Vec<Action>) -> OrderBookResult<()> {
+        let mut result = Ok(());
+        for action in actions {
+            if let Err(err) = self.apply(action).await {
+                log::warn!("order group action failed: {}", err);
+                result = result.and(Err(err));
+            }
+        }
+        result
+    }
+
+    async fn apply(&self, action: Action) -> OrderBookResult<()> {
+        match action {
+            Action::Submit(order) => {
+                self.registry.submit(order).await?;
+            }
+            Action::Resize { symbol, order_id, open } => {
+                let book = self.registry.book(&symbol)?;
+                let Some(order) = book.get_order(order_id) else {
+                    return Ok(());
+                };
+                if open <= Decimal::ZERO {
+                    book.cancel_order(order_id).await?;
+                } else {
+                    book.amend_order(order_id, None, Some(order.filled_quantity + open)).await?;
+                }
+            }
+            Action::Cancel { symbol, order_id } => {
+                self.registry.book(&symbol)?.cancel_order(order_id).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::instrument::Instrument;
+    use crate::models::market_state::MarketState;
+    use rust_decimal_macros::dec;
+    use tokio::sync::mpsc;
+
+    fn setup() -> (Arc<InstrumentRegistry>, UnboundedReceiver<Trade>, OrderGroupManager) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let registry = Arc::new(InstrumentRegistry::new(tx));
+        registry.list(Instrument::new("BTC", "USD")).unwrap();
+        let manager = OrderGroupManager::new(registry.clone());
+        (registry, rx, manager)
+    }
+
+    fn limit(user_id: Uuid, side: Side, price: Decimal, quantity: Decimal) -> Order {
+        Order::new(user_id, "BTC/USD".to_string(), side, OrderType::Limit, price, quantity)
+    }
+
+    /// Feeds the trade channel to the manager, including trades its own
+    /// submissions produce, and returns everything it saw.
+    async fn pump(manager: &OrderGroupManager, trade_rx: &mut UnboundedReceiver<Trade>) -> Vec<Trade> {
+        let mut trades = Vec::new();
+        while let Ok(trade) = trade_rx.try_recv() {
+            manager.on_trade(&trade).await.unwrap();
+            trades.push(trade);
+        }
+        trades
+    }
+
+    #[tokio::test]
+    async fn test_oco_partial_fill_resizes_then_stop_cancels_sibling() {
+        let (registry, mut trade_rx, manager) = setup();
+        let book = registry.book("BTC/USD").unwrap();
+        let user_id = Uuid::new_v4();
+        let take_profit = limit(user_id, Side::Sell, dec!(110), dec!(2));
+        let take_profit_id = take_profit.id;
+        let stop = GroupLeg::stop(limit(user_id, Side::Sell, Decimal::ZERO, dec!(2)), dec!(90));
+        let group_id = manager.submit_oco(GroupLeg::resting(take_profit), stop).await.unwrap();
+
+        registry.submit(limit(Uuid::new_v4(), Side::Buy, dec!(110), dec!(1))).await.unwrap();
+        pump(&manager, &mut trade_rx).await;
+        let group = manager.group(group_id).unwrap();
+        assert_eq!(group.legs[1].open, dec!(1));
+
+        // A trade through the stop sends the remaining quantity to market.
+        registry.submit(limit(Uuid::new_v4(), Side::Buy, dec!(88), dec!(5))).await.unwrap();
+        registry.submit(limit(Uuid::new_v4(), Side::Sell, dec!(88), dec!(1))).await.unwrap();
+        let trades = pump(&manager, &mut trade_rx).await;
+
+        assert!(book.get_order(take_profit_id).is_none());
+        assert!(manager.group(group_id).is_none());
+        assert_eq!(trades.len(), 2);
+        let stop_fill = &trades[1];
+        assert_eq!(stop_fill.taker_user_id, user_id);
+        assert_eq!(stop_fill.quantity, dec!(1));
+    }
+
+    #[tokio::test]
+    async fn test_rejected_stop_stays_armed() {
+        let (registry, mut trade_rx, manager) = setup();
+        let book = registry.book("BTC/USD").unwrap();
+        let user_id = Uuid::new_v4();
+        let take_profit = limit(user_id, Side::Sell, dec!(110), dec!(2));
+        let take_profit_id = take_profit.id;
+        let stop = GroupLeg::stop(limit(user_id, Side::Sell, Decimal::ZERO, dec!(2)), dec!(90));
+        let group_id = manager.submit_oco(GroupLeg::resting(take_profit), stop).await.unwrap();
+        registry.submit(limit(Uuid::new_v4(), Side::Buy, dec!(88), dec!(5))).await.unwrap();
+        registry.submit(limit(Uuid::new_v4(), Side::Sell, dec!(88), dec!(1))).await.unwrap();
+
+        // The call phase refuses the market stop but still takes the cancel.
+        book.start_auction();
+        let trade = trade_rx.try_recv().unwrap();
+        assert!(matches!(
+            manager.on_trade(&trade).await,
+            Err(OrderBookError::MarketStateRejected(MarketState::PreOpen))
+        ));
+        assert!(book.get_order(take_profit_id).is_none());
+        assert!(manager.group(group_id).is_some());
+
+        book.set_market_state(MarketState::Open);
+        registry.submit(limit(Uuid::new_v4(), Side::Sell, dec!(88), dec!(1))).await.unwrap();
+        let trades = pump(&manager, &mut trade_rx).await;
+        assert!(manager.group(group_id).is_none());
+        assert_eq!(trades.last().unwrap().taker_user_id, user_id);
+        assert_eq!(trades.last().unwrap().quantity, dec!(2));
+    }
+
+    #[tokio::test]
+    async fn test_bracket_exits_follow_entry_fills() {
+        let (registry, mut trade_rx, manager) = setup();
+        let book = registry.book("BTC/USD").unwrap();
+        let user_id = Uuid::new_v4();
+        let group_id = manager
+            .submit_bracket(limit(user_id, Side::Buy, dec!(100), dec!(2)), dec!(110), dec!(90))
+            .await
+            .unwrap();
+        let take_profit_id = manager.group(group_id).unwrap().legs[0].order.id;
+        assert!(book.get_order(take_profit_id).is_none());
+
+        registry.submit(limit(Uuid::new_v4(), Side::Sell, dec!(100), dec!(1))).await.unwrap();
+        pump(&manager, &mut trade_rx).await;
+        assert_eq!(book.get_order(take_profit_id).unwrap().quantity, dec!(1));
+
+        registry.submit(limit(Uuid::new_v4(), Side::Sell, dec!(100), dec!(1))).await.unwrap();
+        pump(&manager, &mut trade_rx).await;
+        assert_eq!(book.get_order(take_profit_id).unwrap().quantity, dec!(2));
+        assert_eq!(manager.group(group_id).unwrap().legs[1].open, dec!(2));
+
+        registry.submit(limit(Uuid::new_v4(), Side::Buy, dec!(110), dec!(2))).await.unwrap();
+        pump(&manager, &mut trade_rx).await;
+        assert!(manager.group(group_id).is_none());
+        assert!(book.open_orders_for_user(user_id).is_empty());
+    }
+}
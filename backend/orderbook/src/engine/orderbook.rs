@@ -1,190 +1,2883 @@
-use std::collections::HashMap;
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::sync::Arc;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use priority_queue::PriorityQueue;
 use rust_decimal::Decimal;
-use rust_decimal_macros::dec;
 use uuid::Uuid;
 use std::cmp::Reverse;
 use chrono::{DateTime, Utc};
 use tokio::sync::mpsc::UnboundedSender;
 
+use crate::engine::batch::{BatchOp, BatchOutcome, BatchResult};
+use crate::engine::auction::{find_equilibrium, AuctionIndicative, AuctionLevel};
+use crate::engine::fees::FeeEngine;
+use crate::engine::price_bands::{PriceBandConfig, VolatilityMonitor};
+use crate::ledger::Ledger;
 use crate::models::{
-    order::{Order, OrderType, Side},
+    execution_report::{ExecutionKind, ExecutionReport},
+    instrument::Instrument,
+    market_state::MarketState,
+    order::{Order, OrderType, Peg, PegReference, Side},
     trade::Trade,
 };
+use crate::risk::{RiskContext, RiskEngine};
+use crate::utils::clock::{Clock, SystemClock};
 use crate::utils::error::{OrderBookError, OrderBookResult};
 
+// Best bid is the highest price; best ask the lowest. Ties go to the earliest order.
+type BuyQueue = PriorityQueue<Uuid, (Decimal, Reverse<DateTime<Utc>>)>;
+type SellQueue = PriorityQueue<Uuid, Reverse<(Decimal, DateTime<Utc>)>>;
+type ExpiryQueue = BinaryHeap<Reverse<(DateTime<Utc>, Uuid)>>;
+
+/// Selects resting orders for a mass cancel; `None` fields match everything.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CancelFilter {
+    pub user_id: Option<Uuid>,
+    pub side: Option<Side>,
+}
+
+impl CancelFilter {
+    pub fn user(user_id: Uuid) -> Self {
+        Self {
+            user_id: Some(user_id),
+            side: None,
+        }
+    }
+
+    pub fn side(side: Side) -> Self {
+        Self {
+            user_id: None,
+            side: Some(side),
+        }
+    }
+
+    pub fn matches(&self, order: &Order) -> bool {
+        self.user_id.is_none_or(|user_id| order.user_id == user_id)
+            && self.side.is_none_or(|side| order.side == side)
+    }
+}
+
+struct ClientOrder {
+    request: Order,
+    trades: Vec<Trade>,
+}
+
+/// An operation applied to the book whose trades are not settled yet.
+struct PendingOp {
+    outcome: BatchOutcome,
+    /// Recorded under its client order id once the trades settle.
+    request: Option<Order>,
+    /// A retried client order, whose trades settled the first time.
+    replayed: bool,
+}
+
+impl PendingOp {
+    /// Trades still to settle; none for a replay, which settled before.
+    fn unsettled_trades(&mut self) -> &mut [Trade] {
+        match &mut self.outcome {
+            _ if self.replayed => &mut [],
+            BatchOutcome::Placed { trades, .. } | BatchOutcome::Amended { trades, .. } => trades,
+            BatchOutcome::Cancelled(_) => &mut [],
+        }
+    }
+
+    fn into_trades(self) -> Vec<Trade> {
+        match self.outcome {
+            BatchOutcome::Placed { trades, .. } | BatchOutcome::Amended { trades, .. } => trades,
+            BatchOutcome::Cancelled(_) => Vec::new(),
+        }
+    }
+}
+
+/// Orders as they were before an operation first touched them, put back
+/// when an all-or-nothing batch fails part way or trades fail to settle.
+/// `None` marks an order the operation added. Expiry entries need no undo:
+/// the heap tolerates entries for orders that are gone.
+#[derive(Default)]
+struct UndoLog {
+    orders: HashMap<Uuid, Option<Order>>,
+    /// The pegged list before the operation first changed it.
+    pegged: Option<Vec<Uuid>>,
+}
+
+/// Keeps the book's undo log recording until dropped.
+struct UndoScope<'a> {
+    book: &'a OrderBook,
+}
+
+impl UndoScope<'_> {
+    /// Puts every order touched since the scope began back as it was.
+    fn rollback(self) {
+        let log = self.book.undo.lock().take();
+        if let Some(log) = log {
+            self.book.restore(log);
+        }
+    }
+}
+
+impl Drop for UndoScope<'_> {
+    fn drop(&mut self) {
+        self.book.undo.lock().take();
+    }
+}
+
 pub struct OrderBook {
     symbol: String,
-    buy_orders: Arc<RwLock<PriorityQueue<Uuid, Reverse<(Decimal, DateTime<Utc>)>>>>,
-    sell_orders: Arc<RwLock<PriorityQueue<Uuid, (Decimal, DateTime<Utc>)>>>,
+    instrument: RwLock<Instrument>,
+    state: RwLock<MarketState>,
+    /// Serialises order entry so a batch is applied without other
+    /// submissions interleaving.
+    sequencer: Mutex<()>,
+    /// Accepted submissions by (user, client order id), kept so retries
+    /// replay the original trades.
+    client_orders: RwLock<HashMap<(Uuid, String), ClientOrder>>,
+    /// Good-till-date orders by expiry time, earliest first. Entries for
+    /// orders that have since left the book are skipped when popped.
+    expiries: Mutex<ExpiryQueue>,
+    /// Pegged orders in arrival order; midpoint pegs live only here and in
+    /// `orders`, never in the visible queues.
+    pegged: RwLock<Vec<Uuid>>,
+    /// Unpegged best bid and offer the pegs were last priced from.
+    peg_quotes: Mutex<(Option<Decimal>, Option<Decimal>)>,
+    /// Orders touched inside the current undo scope; `None` outside one.
+    undo: Mutex<Option<UndoLog>>,
+    buy_orders: Arc<RwLock<BuyQueue>>,
+    sell_orders: Arc<RwLock<SellQueue>>,
     orders: Arc<RwLock<HashMap<Uuid, Order>>>,
+    last_trade_price: RwLock<Option<Decimal>>,
+    reference_price: RwLock<Option<Decimal>>,
+    price_bands: Option<PriceBandConfig>,
+    volatility: Mutex<VolatilityMonitor>,
+    clock: Arc<dyn Clock>,
     trade_tx: UnboundedSender<Trade>,
+    auction_tx: Option<UnboundedSender<AuctionIndicative>>,
+    execution_tx: Option<UnboundedSender<ExecutionReport>>,
+    ledger: Option<Arc<Ledger>>,
+    fee_engine: Option<Arc<FeeEngine>>,
+    risk_engine: Option<Arc<RiskEngine>>,
 }
 
 impl OrderBook {
-    pub fn new(symbol: String, trade_tx: UnboundedSender<Trade>) -> Self {
+    pub fn new(symbol: String, trade_tx: UnboundedSender<Trade>) -> OrderBookResult<Self> {
+        Ok(Self::with_instrument(Instrument::from_symbol(&symbol)?, trade_tx))
+    }
+
+    pub fn with_instrument(instrument: Instrument, trade_tx: UnboundedSender<Trade>) -> Self {
         Self {
-            symbol,
+            symbol: instrument.symbol.clone(),
+            instrument: RwLock::new(instrument),
+            state: RwLock::new(MarketState::Open),
+            sequencer: Mutex::new(()),
+            client_orders: RwLock::new(HashMap::new()),
+            expiries: Mutex::new(BinaryHeap::new()),
+            pegged: RwLock::new(Vec::new()),
+            peg_quotes: Mutex::new((None, None)),
+            undo: Mutex::new(None),
             buy_orders: Arc::new(RwLock::new(PriorityQueue::new())),
             sell_orders: Arc::new(RwLock::new(PriorityQueue::new())),
             orders: Arc::new(RwLock::new(HashMap::new())),
+            last_trade_price: RwLock::new(None),
+            reference_price: RwLock::new(None),
+            price_bands: None,
+            volatility: Mutex::new(VolatilityMonitor::new()),
+            clock: Arc::new(SystemClock),
             trade_tx,
+            auction_tx: None,
+            execution_tx: None,
+            ledger: None,
+            fee_engine: None,
+            risk_engine: None,
+        }
+    }
+
+    /// Settles every trade of this book on the given ledger.
+    pub fn with_ledger(mut self, ledger: Arc<Ledger>) -> Self {
+        self.ledger = Some(ledger);
+        self
+    }
+
+    /// Stamps maker and taker fees on every trade of this book.
+    pub fn with_fee_engine(mut self, fee_engine: Arc<FeeEngine>) -> Self {
+        self.fee_engine = Some(fee_engine);
+        self
+    }
+
+    /// Runs pre-trade risk checks on every inbound order.
+    pub fn with_risk_engine(mut self, risk_engine: Arc<RiskEngine>) -> Self {
+        self.risk_engine = Some(risk_engine);
+        self
+    }
+
+    /// Enables price collars and the volatility circuit breaker.
+    pub fn with_price_bands(mut self, config: PriceBandConfig) -> Self {
+        self.price_bands = Some(config);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Publishes the indicative auction price on every change during the call phase.
+    pub fn with_auction_publisher(mut self, auction_tx: UnboundedSender<AuctionIndicative>) -> Self {
+        self.auction_tx = Some(auction_tx);
+        self
+    }
+
+    /// Publishes a report whenever the engine takes an order off the book
+    /// on its own, e.g. on expiry.
+    pub fn with_execution_publisher(mut self, execution_tx: UnboundedSender<ExecutionReport>) -> Self {
+        self.execution_tx = Some(execution_tx);
+        self
+    }
+
+    pub fn symbol(&self) -> &str {
+        &self.symbol
+    }
+
+    pub fn instrument(&self) -> Instrument {
+        self.instrument.read().clone()
+    }
+
+    /// Replaces the trading rules; resting orders are left untouched.
+    pub fn update_instrument(&self, instrument: Instrument) -> OrderBookResult<()> {
+        if instrument.symbol != self.symbol {
+            return Err(OrderBookError::SymbolMismatch(instrument.symbol));
+        }
+        instrument.validate()?;
+        *self.instrument.write() = instrument;
+        Ok(())
+    }
+
+    pub fn market_state(&self) -> MarketState {
+        *self.state.read()
+    }
+
+    /// Switches the trading phase and returns the previous one.
+    ///
+    /// Matching holds the state lock for its whole run, so the switch waits
+    /// for an in-flight order to finish and every later order sees the new
+    /// state.
+    pub fn set_market_state(&self, new_state: MarketState) -> MarketState {
+        let mut state = self.state.write();
+        let previous = *state;
+        *state = new_state;
+        if previous != new_state {
+            log::info!("{} market state {} -> {}", self.symbol, previous, new_state);
+        }
+        previous
+    }
+
+    pub async fn process_order(&self, order: Order) -> OrderBookResult<Vec<Trade>> {
+        let instrument = self.instrument();
+        let (trades, replayed, state) = {
+            let _sequence = self.sequencer.lock();
+            let state = self.state.read();
+            self.expire_due();
+            let undo = self.settlement_undo();
+            let mut pending = self.enter_order(&instrument, order, *state, None)?;
+            self.settle_or_restore(&instrument, &mut pending, undo)?;
+            let replayed = pending.replayed;
+            let trades = pending.into_trades();
+            self.refresh_pegs(&instrument, *state);
+            (trades, replayed, *state)
+        };
+
+        if state == MarketState::PreOpen {
+            self.publish_indicative();
+        }
+
+        // A replay's trades went through the volatility monitor the first time.
+        if !replayed {
+            self.check_volatility(&trades);
+        }
+        Ok(trades)
+    }
+
+    /// Applies new, cancel and amend operations back-to-back with no other
+    /// submission on this book in between.
+    ///
+    /// With `all_or_nothing` every operation is validated first (trading
+    /// rules, market state, bands, risk and that referenced orders exist)
+    /// and the whole batch is rejected if any fails, naming the offending
+    /// index. The operations are then applied with settlement held back;
+    /// if one still fails against the book the earlier ones left behind,
+    /// e.g. cancelling an order an earlier one filled, the book is rolled
+    /// back and nothing settles. Otherwise each operation succeeds or fails
+    /// on its own.
+    pub async fn process_batch(&self, ops: Vec<BatchOp>, all_or_nothing: bool) -> OrderBookResult<BatchResult> {
+        let instrument = self.instrument();
+        let mut all_trades = Vec::new();
+        let (results, state) = {
+            let _sequence = self.sequencer.lock();
+            let state = self.state.read();
+            self.expire_due();
+            let results = if all_or_nothing {
+                self.validate_batch(&instrument, &ops, *state)?;
+                let (results, trades) = self.apply_atomic(&instrument, ops, *state)?;
+                all_trades = trades;
+                results
+            } else {
+                ops.into_iter()
+                    .map(|op| {
+                        let undo = self.settlement_undo();
+                        let mut pending = self.apply_batch_op(&instrument, op, *state, None)?;
+                        self.settle_or_restore(&instrument, &mut pending, undo)?;
+                        all_trades.extend_from_slice(pending.unsettled_trades());
+                        Ok(pending.outcome)
+                    })
+                    .collect()
+            };
+            self.refresh_pegs(&instrument, *state);
+            (results, *state)
+        };
+
+        if state == MarketState::PreOpen {
+            self.publish_indicative();
+        }
+        self.check_volatility(&all_trades);
+        Ok(results)
+    }
+
+    /// Changes the price and/or quantity of a resting order.
+    ///
+    /// Reducing the quantity at the same price keeps the order's place in
+    /// the queue; any other change cancels and re-enters it at the back of
+    /// its new level, matching if it now crosses.
+    pub async fn amend_order(
+        &self,
+        order_id: Uuid,
+        price: Option<Decimal>,
+        quantity: Option<Decimal>,
+    ) -> OrderBookResult<(Order, Vec<Trade>)> {
+        let instrument = self.instrument();
+        let (result, state) = {
+            let _sequence = self.sequencer.lock();
+            let state = self.state.read();
+            self.expire_due();
+            let undo = self.settlement_undo();
+            let (order, mut trades) = self.amend(&instrument, order_id, price, quantity, *state, None)?;
+            self.settle_trades(&instrument, &mut trades, undo)?;
+            self.refresh_pegs(&instrument, *state);
+            ((order, trades), *state)
+        };
+
+        if state == MarketState::PreOpen {
+            self.publish_indicative();
+        }
+        self.check_volatility(&result.1);
+        Ok(result)
+    }
+
+    /// Flattens the user's position with a reduce-only order for all of it:
+    /// a limit at `price`, or a market order when `price` is `None`.
+    pub async fn close_position(&self, user_id: Uuid, price: Option<Decimal>) -> OrderBookResult<Vec<Trade>> {
+        let position = self.position(user_id)?;
+        let side = if position > Decimal::ZERO { Side::Sell } else { Side::Buy };
+        let (order_type, price) = match price {
+            Some(price) => (OrderType::Limit, price),
+            None => (OrderType::Market, Decimal::ZERO),
+        };
+        let order = Order::new(user_id, self.symbol.clone(), side, order_type, price, position.abs()).reduce_only();
+        self.process_order(order).await
+    }
+
+    pub fn last_trade_price(&self) -> Option<Decimal> {
+        *self.last_trade_price.read()
+    }
+
+    /// Price that bands are measured from: an explicitly set reference
+    /// (e.g. an index or TWAP) or else the last trade.
+    pub fn reference_price(&self) -> Option<Decimal> {
+        self.reference_price.read().or_else(|| self.last_trade_price())
+    }
+
+    pub fn set_reference_price(&self, price: Option<Decimal>) {
+        *self.reference_price.write() = price;
+    }
+
+    /// Enters the call phase: orders accumulate without matching until `uncross`.
+    pub fn start_auction(&self) -> MarketState {
+        let previous = self.set_market_state(MarketState::PreOpen);
+        self.publish_indicative();
+        previous
+    }
+
+    /// Price, volume and imbalance the auction would uncross at right now.
+    pub fn indicative(&self) -> AuctionIndicative {
+        let orders = self.orders.read();
+        let (bids, asks) = auction_levels(&orders);
+        let equilibrium = find_equilibrium(&bids, &asks, self.last_trade_price());
+
+        match equilibrium {
+            Some(eq) => AuctionIndicative {
+                symbol: self.symbol.clone(),
+                price: Some(eq.price),
+                matched_volume: eq.matched_volume,
+                imbalance: eq.surplus.abs(),
+                imbalance_side: if eq.surplus > Decimal::ZERO {
+                    Some(Side::Buy)
+                } else if eq.surplus < Decimal::ZERO {
+                    Some(Side::Sell)
+                } else {
+                    None
+                },
+            },
+            None => AuctionIndicative {
+                symbol: self.symbol.clone(),
+                price: None,
+                matched_volume: Decimal::ZERO,
+                imbalance: Decimal::ZERO,
+                imbalance_side: None,
+            },
+        }
+    }
+
+    /// Ends the call phase: executes all crossing interest at the single
+    /// equilibrium price and reopens continuous trading. If the auction
+    /// trades can't settle the book is put back and the call phase goes on.
+    pub async fn uncross(&self) -> OrderBookResult<Vec<Trade>> {
+        let instrument = self.instrument();
+        // Order entry waits until the auction trades are settled and the
+        // pegs repriced at the reopen.
+        let _sequence = self.sequencer.lock();
+        let trades = {
+            let mut state = self.state.write();
+            if *state != MarketState::PreOpen {
+                return Err(OrderBookError::MarketStateRejected(*state));
+            }
+            let undo = self.settlement_undo();
+            let mut trades = self.uncross_orders();
+            self.settle_trades(&instrument, &mut trades, undo)?;
+            self.volatility.lock().reset();
+            *state = MarketState::Open;
+            log::info!("{} uncrossed with {} trades, market state {} -> {}", self.symbol, trades.len(), MarketState::PreOpen, MarketState::Open);
+            trades
+        };
+
+        self.refresh_pegs(&instrument, MarketState::Open);
+        Ok(trades)
+    }
+
+    pub async fn cancel_order(&self, order_id: Uuid) -> OrderBookResult<Option<Order>> {
+        let _sequence = self.sequencer.lock();
+        let state = self.state.read();
+        if !state.accepts_cancels() {
+            return Err(OrderBookError::MarketStateRejected(*state));
+        }
+        let cancelled = self.remove_order(order_id);
+        self.refresh_pegs(&self.instrument(), *state);
+        Ok(cancelled)
+    }
+
+    /// Cancels every resting order matching the filter in one step.
+    pub async fn mass_cancel(&self, filter: CancelFilter) -> OrderBookResult<Vec<Order>> {
+        let _sequence = self.sequencer.lock();
+        let state = self.state.read();
+        if !state.accepts_cancels() {
+            return Err(OrderBookError::MarketStateRejected(*state));
+        }
+
+        let order_ids: Vec<Uuid> = self
+            .orders
+            .read()
+            .values()
+            .filter(|order| filter.matches(order))
+            .map(|order| order.id)
+            .collect();
+        let cancelled: Vec<Order> = order_ids
+            .into_iter()
+            .filter_map(|order_id| self.remove_order(order_id))
+            .collect();
+
+        if !cancelled.is_empty() {
+            log::info!("{} mass cancel {:?} removed {} orders", self.symbol, filter, cancelled.len());
         }
+        self.refresh_pegs(&self.instrument(), *state);
+        Ok(cancelled)
+    }
+
+    /// Removes resting good-till-date orders whose expiry has passed and
+    /// publishes an `Expired` report for each. Orders are also expired
+    /// before every submission, so a stale order never trades.
+    pub fn expire_orders(&self) -> Vec<Order> {
+        let _sequence = self.sequencer.lock();
+        let expired = self.expire_due();
+        self.refresh_pegs(&self.instrument(), self.market_state());
+        expired
+    }
+
+    /// Earliest pending expiry, for scheduling the next `expire_orders`.
+    pub fn next_expiry(&self) -> Option<DateTime<Utc>> {
+        self.expiries.lock().peek().map(|Reverse((at, _))| *at)
+    }
+
+    pub fn get_order(&self, order_id: Uuid) -> Option<Order> {
+        self.orders.read().get(&order_id).cloned()
+    }
+
+    /// Server id of the order accepted under a client order id, whether or
+    /// not it is still resting.
+    pub fn order_id_for_client_id(&self, user_id: Uuid, client_order_id: &str) -> Option<Uuid> {
+        self.client_orders
+            .read()
+            .get(&(user_id, client_order_id.to_string()))
+            .map(|client_order| client_order.request.id)
+    }
+
+    pub fn get_order_by_client_id(&self, user_id: Uuid, client_order_id: &str) -> Option<Order> {
+        self.get_order(self.order_id_for_client_id(user_id, client_order_id)?)
+    }
+
+    pub async fn cancel_by_client_id(&self, user_id: Uuid, client_order_id: &str) -> OrderBookResult<Option<Order>> {
+        let order_id = self
+            .order_id_for_client_id(user_id, client_order_id)
+            .ok_or(OrderBookError::OrderNotFound)?;
+        self.cancel_order(order_id).await
+    }
+
+    pub fn open_orders_for_user(&self, user_id: Uuid) -> Vec<Order> {
+        self.orders
+            .read()
+            .values()
+            .filter(|order| order.user_id == user_id)
+            .cloned()
+            .collect()
+    }
+
+    pub fn best_bid(&self) -> Option<Decimal> {
+        self.buy_orders.read().peek().map(|(_, (price, _))| *price)
+    }
+
+    pub fn best_ask(&self) -> Option<Decimal> {
+        self.sell_orders.read().peek().map(|(_, Reverse((price, _)))| *price)
     }
 
-    pub async fn process_order(&self, mut order: Order) -> OrderBookResult<Vec<Trade>> {
+    /// `fills` holds position changes not settled yet, which reduce-only
+    /// makers must count.
+    fn execute_order(&self, mut order: Order, state: MarketState, fills: &HashMap<Uuid, Decimal>) -> OrderBookResult<Vec<Trade>> {
+        if !state.accepts_orders() {
+            return Err(OrderBookError::MarketStateRejected(state));
+        }
+        if order.is_expired(self.clock.now()) {
+            return Err(OrderBookError::OrderExpired);
+        }
+
         let mut trades: Vec<Trade> = Vec::new();
 
+        if order.peg.is_some() {
+            // Pegs only make sense against a live two-sided market.
+            if !state.matches_orders() || order.order_type != OrderType::Limit {
+                return Err(OrderBookError::MarketStateRejected(state));
+            }
+            if order.is_hidden() {
+                if let Some(mid) = self.midpoint() {
+                    trades.extend(self.match_midpoint(&mut order, mid, fills));
+                }
+            }
+            if !order.is_filled() {
+                self.add_order_to_book(order);
+            }
+            return Ok(trades);
+        }
+
+        if order.order_type == OrderType::Limit {
+            self.check_limit_band(&order)?;
+        }
+
+        if !state.matches_orders() {
+            // Call phase: only limit orders can rest without a price to match at.
+            if order.order_type == OrderType::Market {
+                return Err(OrderBookError::MarketStateRejected(state));
+            }
+            self.add_order_to_book(order);
+            return Ok(trades);
+        }
+
         match order.order_type {
             OrderType::Market | OrderType::Limit => {
+                // Hidden midpoint liquidity improves on the touch, so it goes first.
+                if let Some(mid) = self.midpoint().filter(|mid| crosses_midpoint(&order, *mid)) {
+                    trades.extend(self.match_midpoint(&mut order, mid, fills));
+                }
+                let price_limit = self.market_price_limit(&order);
                 match order.side {
                     Side::Buy => {
-                        trades.extend(self.match_buy_order(&mut order).await?);
+                        trades.extend(self.match_buy_order(&mut order, price_limit, fills));
                     }
                     Side::Sell => {
-                        trades.extend(self.match_sell_order(&mut order).await?);
+                        trades.extend(self.match_sell_order(&mut order, price_limit, fills));
                     }
                 }
 
                 if !order.is_filled() && order.order_type == OrderType::Limit {
-                    self.add_order_to_book(order).await?;
+                    self.add_order_to_book(order);
                 }
             }
         }
 
-        // Broadcast trades
-        for trade in &trades {
-            let _ = self.trade_tx.send(trade.clone());
-        }
-
         Ok(trades)
     }
 
-    pub async fn cancel_order(&self, order_id: Uuid) -> OrderBookResult<Option<Order>> {
-        let mut orders = self.orders.write();
-        if let Some(order) = orders.remove(&order_id) {
-            match order.side {
-                Side::Buy => {
-                    let mut buy_orders = self.buy_orders.write();
-                    buy_orders.remove(&order_id);
+    /// `pending_orders` counts orders of the same user accepted earlier in a
+    /// batch but not yet on the book.
+    fn check_risk(&self, order: &Order, pending_orders: usize) -> OrderBookResult<()> {
+        let Some(risk_engine) = &self.risk_engine else {
+            return Ok(());
+        };
+        let context = RiskContext {
+            open_orders: self.orders.read().values().filter(|o| o.user_id == order.user_id).count() + pending_orders,
+            position: self
+                .ledger
+                .as_ref()
+                .map(|ledger| ledger.position(order.user_id, &self.symbol))
+                .unwrap_or(Decimal::ZERO),
+            reference_price: self.reference_price(),
+            now: risk_engine.now(),
+        };
+        risk_engine.check(order, &context)?;
+        Ok(())
+    }
+
+    /// Dry run of a batch against the current book, tracking orders the
+    /// batch itself adds and cancels.
+    fn validate_batch(&self, instrument: &Instrument, ops: &[BatchOp], state: MarketState) -> OrderBookResult<()> {
+        let mut added: HashMap<Uuid, Order> = HashMap::new();
+        let mut cancelled: HashSet<Uuid> = HashSet::new();
+        let mut client_order_ids: HashSet<(Uuid, &str)> = HashSet::new();
+        let reject = |index: usize, err: OrderBookError| OrderBookError::BatchRejected {
+            index,
+            reason: Box::new(err),
+        };
+
+        for (index, op) in ops.iter().enumerate() {
+            let lookup = |order_id: &Uuid| {
+                if cancelled.contains(order_id) {
+                    return None;
                 }
-                Side::Sell => {
-                    let mut sell_orders = self.sell_orders.write();
-                    sell_orders.remove(&order_id);
+                added.get(order_id).cloned().or_else(|| self.get_order(*order_id))
+            };
+            match op {
+                BatchOp::New(order) => {
+                    if let Some(client_order_id) = &order.client_order_id {
+                        if !client_order_ids.insert((order.user_id, client_order_id)) {
+                            let err = OrderBookError::DuplicateClientOrderId(client_order_id.clone());
+                            return Err(reject(index, err));
+                        }
+                    }
+                    let pending = added.values().filter(|o| o.user_id == order.user_id).count();
+                    self.validate_new(instrument, order, state, pending)
+                        .map_err(|err| reject(index, err))?;
+                    added.insert(order.id, order.clone());
+                }
+                BatchOp::Cancel(order_id) => {
+                    if !state.accepts_cancels() {
+                        return Err(reject(index, OrderBookError::MarketStateRejected(state)));
+                    }
+                    if lookup(order_id).is_none() {
+                        return Err(reject(index, OrderBookError::OrderNotFound));
+                    }
+                    cancelled.insert(*order_id);
+                }
+                BatchOp::Amend { order_id, price, quantity } => {
+                    let existing = lookup(order_id).ok_or_else(|| reject(index, OrderBookError::OrderNotFound))?;
+                    let amended = amended_order(&existing, *price, *quantity).map_err(|err| reject(index, err))?;
+                    if requeues(&existing, &amended) {
+                        self.check_entry(instrument, amended.clone(), state, 0)
+                            .map_err(|err| reject(index, err))?;
+                    } else if !state.accepts_orders() {
+                        return Err(reject(index, OrderBookError::MarketStateRejected(state)));
+                    } else {
+                        instrument.validate_order(&amended).map_err(|err| reject(index, err))?;
+                    }
+                    if added.contains_key(order_id) {
+                        added.insert(*order_id, amended);
+                    }
                 }
             }
-            Ok(Some(order))
-        } else {
-            Ok(None)
         }
+        Ok(())
     }
 
-    async fn match_buy_order(&self, order: &mut Order) -> OrderBookResult<Vec<Trade>> {
-        let mut trades = Vec::new();
-        let mut sell_orders = self.sell_orders.write();
-        let mut orders = self.orders.write();
+    fn validate_new(&self, instrument: &Instrument, order: &Order, state: MarketState, pending_orders: usize) -> OrderBookResult<()> {
+        if self.client_order(order)?.is_some() {
+            return Ok(());
+        }
+        self.check_entry(instrument, order.clone(), state, pending_orders)?;
+        Ok(())
+    }
 
-        while let Some((sell_order_id, (price, _))) = sell_orders.peek() {
-            // Check if the buy price is acceptable
-            if order.order_type == OrderType::Limit && order.price < *price {
-                break;
-            }
+    /// Runs every check an order entering the book must pass and returns
+    /// it priced and capped as it would enter. Requeued amends go through
+    /// this too, so they can't escape the checks a new order faces.
+    fn check_entry(&self, instrument: &Instrument, order: Order, state: MarketState, pending_orders: usize) -> OrderBookResult<Order> {
+        if !state.accepts_orders() || (!state.matches_orders() && order.order_type == OrderType::Market) {
+            return Err(OrderBookError::MarketStateRejected(state));
+        }
+        if order.is_expired(self.clock.now()) {
+            return Err(OrderBookError::OrderExpired);
+        }
+        let order = self.price_peg(instrument, order)?;
+        let order = self.cap_reduce_only(instrument, order, &HashMap::new())?;
+        instrument.validate_order(&order)?;
+        if order.order_type == OrderType::Limit && order.peg.is_none() {
+            self.check_limit_band(&order)?;
+        }
+        self.check_risk(&order, pending_orders)?;
+        Ok(order)
+    }
 
-            if let Some(mut sell_order) = orders.get_mut(sell_order_id) {
-                let match_quantity = order.remaining_quantity().min(sell_order.remaining_quantity());
-                let match_price = *price;
+    /// Validates and matches a new order, leaving its trades unsettled; a
+    /// retried client order id returns the id and trades of the original
+    /// submission instead.
+    ///
+    /// Inside an all-or-nothing batch, which was validated up front,
+    /// `batch_fills` holds the position changes of the batch's earlier
+    /// operations; without it the order is checked here.
+    fn enter_order(
+        &self,
+        instrument: &Instrument,
+        order: Order,
+        state: MarketState,
+        batch_fills: Option<&HashMap<Uuid, Decimal>>,
+    ) -> OrderBookResult<PendingOp> {
+        if let Some((order_id, trades)) = self.client_order(&order)? {
+            log::info!("{} replayed client order {:?} as {}", self.symbol, order.client_order_id, order_id);
+            return Ok(PendingOp {
+                outcome: BatchOutcome::Placed { order_id, trades },
+                request: None,
+                replayed: true,
+            });
+        }
+        let no_fills = HashMap::new();
+        let fills = batch_fills.unwrap_or(&no_fills);
+        let order = self.price_peg(instrument, order)?;
+        let order = self.cap_reduce_only(instrument, order, fills)?;
+        if batch_fills.is_none() {
+            instrument.validate_order(&order)?;
+            self.check_risk(&order, 0)?;
+        }
 
-                // Update order quantities
-                order.filled_quantity += match_quantity;
-                sell_order.filled_quantity += match_quantity;
+        let order_id = order.id;
+        let request = order.client_order_id.is_some().then(|| order.clone());
+        let trades = self.execute_order(order, state, fills)?;
+        Ok(PendingOp {
+            outcome: BatchOutcome::Placed { order_id, trades },
+            request,
+            replayed: false,
+        })
+    }
 
-                // Create trade
-                let trade = Trade::new(&sell_order, order, match_quantity);
-                trades.push(trade);
+    /// Settles the trades of an applied operation, undoing its changes to
+    /// the book if they can't be posted to the ledger.
+    fn settle_or_restore(&self, instrument: &Instrument, pending: &mut PendingOp, undo: Option<UndoScope<'_>>) -> OrderBookResult<()> {
+        let trades = pending.unsettled_trades();
+        self.stamp_fees(instrument, trades);
+        if let Err(err) = self.post_trades(instrument, trades) {
+            self.restore_after_settlement(undo, &err);
+            return Err(err);
+        }
+        drop(undo);
+        self.finish_op(pending);
+        Ok(())
+    }
+
+    /// Publishes the posted trades of an operation and records its client
+    /// order id.
+    fn finish_op(&self, pending: &mut PendingOp) {
+        self.publish_trades(pending.unsettled_trades());
+        if let Some(request) = pending.request.take() {
+            let client_order_id = request.client_order_id.clone().unwrap_or_default();
+            let trades = pending.unsettled_trades().to_vec();
+            self.client_orders
+                .write()
+                .insert((request.user_id, client_order_id), ClientOrder { request, trades });
+        }
+    }
 
-                // Remove filled sell order
-                if sell_order.is_filled() {
-                    sell_orders.pop();
+    /// Applies a validated batch, rolling the book back if an operation
+    /// fails, and settles the trades only once every operation applied.
+    /// The trades of all the operations post to the ledger as one unit and
+    /// are returned alongside the results, leaving out replays.
+    fn apply_atomic(&self, instrument: &Instrument, ops: Vec<BatchOp>, state: MarketState) -> OrderBookResult<(BatchResult, Vec<Trade>)> {
+        let undo = self.begin_undo();
+        let mut applied = Vec::with_capacity(ops.len());
+        let mut fills = HashMap::new();
+        for (index, op) in ops.into_iter().enumerate() {
+            match self.apply_batch_op(instrument, op, state, Some(&fills)) {
+                Ok(mut pending) => {
+                    record_trade_fills(&mut fills, pending.unsettled_trades());
+                    applied.push(pending);
+                }
+                Err(err) => {
+                    undo.rollback();
+                    log::info!("{} rolled back batch failing at operation {}: {}", self.symbol, index, err);
+                    return Err(OrderBookError::BatchRejected {
+                        index,
+                        reason: Box::new(err),
+                    });
                 }
             }
+        }
 
-            if order.is_filled() {
-                break;
-            }
+        let mut trades = Vec::new();
+        for pending in &mut applied {
+            self.stamp_fees(instrument, pending.unsettled_trades());
+            trades.extend_from_slice(pending.unsettled_trades());
         }
+        if let Err(err) = self.post_trades(instrument, &trades) {
+            self.restore_after_settlement(Some(undo), &err);
+            return Err(err);
+        }
+        drop(undo);
 
-        Ok(trades)
+        let mut results = Vec::with_capacity(applied.len());
+        for mut pending in applied {
+            self.finish_op(&mut pending);
+            results.push(Ok(pending.outcome));
+        }
+        Ok((results, trades))
     }
 
-    async fn match_sell_order(&self, order: &mut Order) -> OrderBookResult<Vec<Trade>> {
-        let mut trades = Vec::new();
-        let mut buy_orders = self.buy_orders.write();
-        let mut orders = self.orders.write();
+    /// An undo scope for trades that may fail to settle, which only a
+    /// ledger can make them do.
+    fn settlement_undo(&self) -> Option<UndoScope<'_>> {
+        self.ledger.as_ref().map(|_| self.begin_undo())
+    }
 
-        while let Some((buy_order_id, Reverse((price, _)))) = buy_orders.peek() {
-            // Check if the sell price is acceptable
-            if order.order_type == OrderType::Limit && order.price > *price {
-                break;
-            }
+    fn restore_after_settlement(&self, undo: Option<UndoScope<'_>>, err: &OrderBookError) {
+        if let Some(undo) = undo {
+            undo.rollback();
+            log::info!("{} rolled back trades that failed to settle: {}", self.symbol, err);
+        }
+    }
 
-            if let Some(mut buy_order) = orders.get_mut(buy_order_id) {
-                let match_quantity = order.remaining_quantity().min(buy_order.remaining_quantity());
-                let match_price = *price;
+    /// Starts recording the orders later changes touch, so they can be
+    /// undone without copying the book.
+    fn begin_undo(&self) -> UndoScope<'_> {
+        *self.undo.lock() = Some(UndoLog::default());
+        UndoScope { book: self }
+    }
 
-                // Update order quantities
-                order.filled_quantity += match_quantity;
-                buy_order.filled_quantity += match_quantity;
+    /// Notes `order_id` as it is in `orders` the first time the current
+    /// undo scope sees it change.
+    fn record_undo(&self, orders: &HashMap<Uuid, Order>, order_id: Uuid) {
+        if let Some(log) = self.undo.lock().as_mut() {
+            log.orders.entry(order_id).or_insert_with(|| orders.get(&order_id).cloned());
+        }
+    }
 
-                // Create trade
-                let trade = Trade::new(&buy_order, order, match_quantity);
-                trades.push(trade);
+    fn record_pegged_undo(&self, pegged: &[Uuid]) {
+        if let Some(log) = self.undo.lock().as_mut() {
+            log.pegged.get_or_insert_with(|| pegged.to_vec());
+        }
+    }
 
-                // Remove filled buy order
-                if buy_order.is_filled() {
-                    buy_orders.pop();
+    fn restore(&self, log: UndoLog) {
+        let mut orders = self.orders.write();
+        let mut buy_orders = self.buy_orders.write();
+        let mut sell_orders = self.sell_orders.write();
+        for (order_id, previous) in log.orders {
+            orders.remove(&order_id);
+            buy_orders.remove(&order_id);
+            sell_orders.remove(&order_id);
+            let Some(order) = previous else {
+                continue;
+            };
+            if !order.is_hidden() {
+                match order.side {
+                    Side::Buy => {
+                        buy_orders.push(order_id, (order.price, Reverse(order.timestamp)));
+                    }
+                    Side::Sell => {
+                        sell_orders.push(order_id, Reverse((order.price, order.timestamp)));
+                    }
                 }
             }
+            orders.insert(order_id, order);
+        }
+        if let Some(pegged) = log.pegged {
+            *self.pegged.write() = pegged;
+        }
+    }
 
-            if order.is_filled() {
-                break;
+    /// Original order id and trades when `order` retries an accepted client
+    /// order id; an error when the id was used for a different order.
+    fn client_order(&self, order: &Order) -> OrderBookResult<Option<(Uuid, Vec<Trade>)>> {
+        let Some(client_order_id) = &order.client_order_id else {
+            return Ok(None);
+        };
+        let client_orders = self.client_orders.read();
+        match client_orders.get(&(order.user_id, client_order_id.clone())) {
+            Some(previous) if previous.request.same_request(order) => {
+                Ok(Some((previous.request.id, previous.trades.clone())))
             }
+            Some(_) => Err(OrderBookError::DuplicateClientOrderId(client_order_id.clone())),
+            None => Ok(None),
         }
+    }
 
-        Ok(trades)
+    /// Applies one operation to the book, leaving its trades unsettled.
+    fn apply_batch_op(
+        &self,
+        instrument: &Instrument,
+        op: BatchOp,
+        state: MarketState,
+        batch_fills: Option<&HashMap<Uuid, Decimal>>,
+    ) -> OrderBookResult<PendingOp> {
+        let outcome = match op {
+            BatchOp::New(order) => return self.enter_order(instrument, order, state, batch_fills),
+            BatchOp::Cancel(order_id) => {
+                if !state.accepts_cancels() {
+                    return Err(OrderBookError::MarketStateRejected(state));
+                }
+                self.remove_order(order_id)
+                    .map(BatchOutcome::Cancelled)
+                    .ok_or(OrderBookError::OrderNotFound)?
+            }
+            BatchOp::Amend { order_id, price, quantity } => {
+                let (order, trades) = self.amend(instrument, order_id, price, quantity, state, batch_fills)?;
+                BatchOutcome::Amended { order, trades }
+            }
+        };
+        Ok(PendingOp {
+            outcome,
+            request: None,
+            replayed: false,
+        })
     }
 
-    async fn add_order_to_book(&self, order: Order) -> OrderBookResult<()> {
-        let order_id = order.id;
-        let price = order.price;
-        let timestamp = order.timestamp;
+    /// Amends a resting order; trades of a re-entered order are returned
+    /// unsettled. `batch_fills` is as for `enter_order`.
+    fn amend(
+        &self,
+        instrument: &Instrument,
+        order_id: Uuid,
+        price: Option<Decimal>,
+        quantity: Option<Decimal>,
+        state: MarketState,
+        batch_fills: Option<&HashMap<Uuid, Decimal>>,
+    ) -> OrderBookResult<(Order, Vec<Trade>)> {
+        if !state.accepts_orders() {
+            return Err(OrderBookError::MarketStateRejected(state));
+        }
+        let existing = self.get_order(order_id).ok_or(OrderBookError::OrderNotFound)?;
+        let mut amended = amended_order(&existing, price, quantity)?;
+        amended.updated_at = self.clock.now();
 
-        match order.side {
-            Side::Buy => {
-                let mut buy_orders = self.buy_orders.write();
-                buy_orders.push(order_id, Reverse((price, timestamp)));
+        if !requeues(&existing, &amended) {
+            instrument.validate_order(&amended)?;
+            let mut orders = self.orders.write();
+            self.record_undo(&orders, order_id);
+            orders.insert(order_id, amended.clone());
+            drop(orders);
+            return Ok((amended, Vec::new()));
+        }
+
+        let no_fills = HashMap::new();
+        let fills = batch_fills.unwrap_or(&no_fills);
+        amended = match batch_fills {
+            Some(fills) => {
+                let amended = self.price_peg(instrument, amended)?;
+                self.cap_reduce_only(instrument, amended, fills)?
             }
-            Side::Sell => {
-                let mut sell_orders = self.sell_orders.write();
-                sell_orders.push(order_id, (price, timestamp));
+            None => self.check_entry(instrument, amended, state, 0)?,
+        };
+        amended.timestamp = amended.updated_at;
+        self.remove_order(order_id);
+        let trades = match self.execute_order(amended.clone(), state, fills) {
+            Ok(trades) => trades,
+            Err(err) => {
+                self.add_order_to_book(existing);
+                return Err(err);
             }
-        }
+        };
+        amended.filled_quantity += trades.iter().map(|trade| trade.quantity).sum::<Decimal>();
+        Ok((amended, trades))
+    }
 
-        self.orders.write().insert(order_id, order);
+    fn check_limit_band(&self, order: &Order) -> OrderBookResult<()> {
+        let (Some(config), Some(reference)) = (&self.price_bands, self.reference_price()) else {
+            return Ok(());
+        };
+        let (lower, upper) = config.limit_bounds(reference);
+        if order.price < lower || order.price > upper {
+            return Err(OrderBookError::PriceOutsideBand { lower, upper });
+        }
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tokio::sync::mpsc;
+    /// Worst price a market order may trade at. Without a reference price the
+    /// collar is measured from the touch at arrival.
+    fn market_price_limit(&self, order: &Order) -> Option<Decimal> {
+        if order.order_type != OrderType::Market {
+            return None;
+        }
+        let config = self.price_bands.as_ref()?;
+        let reference = self.reference_price().or(match order.side {
+            Side::Buy => self.best_ask(),
+            Side::Sell => self.best_bid(),
+        })?;
+        let (lower, upper) = config.market_bounds(reference);
+        Some(match order.side {
+            Side::Buy => upper,
+            Side::Sell => lower,
+        })
+    }
 
-    #[tokio::test]
-    async fn test_order_book_creation() {
-        let (tx, _) = mpsc::unbounded_channel();
-        let order_book = OrderBook::new("BTC/USD".to_string(), tx);
+    /// Moves the book into a volatility auction when trades moved the price
+    /// too far within the configured window.
+    fn check_volatility(&self, trades: &[Trade]) {
+        let Some(config) = &self.price_bands else {
+            return;
+        };
+        let now = self.clock.now();
+        let mut tripped = false;
+        {
+            let mut volatility = self.volatility.lock();
+            for trade in trades {
+                tripped |= volatility.record(config, now, trade.price);
+            }
+        }
+
+        if tripped && self.market_state() == MarketState::Open {
+            log::warn!("{} volatility circuit breaker tripped at {:?}", self.symbol, self.last_trade_price());
+            self.start_auction();
+        }
+    }
+
+    fn publish_execution(&self, order: &Order, kind: ExecutionKind) {
+        if let Some(execution_tx) = &self.execution_tx {
+            let _ = execution_tx.send(ExecutionReport::new(order, kind, self.clock.now()));
+        }
+    }
+
+    fn publish_indicative(&self) {
+        if let Some(auction_tx) = &self.auction_tx {
+            let _ = auction_tx.send(self.indicative());
+        }
+    }
+
+    fn uncross_orders(&self) -> Vec<Trade> {
+        let mut orders = self.orders.write();
+        let (bids, asks) = auction_levels(&orders);
+        let Some(eq) = find_equilibrium(&bids, &asks, self.last_trade_price()) else {
+            return Vec::new();
+        };
+
+        let mut buys: Vec<Order> = orders
+            .values()
+            .filter(|o| o.side == Side::Buy && o.price >= eq.price)
+            .cloned()
+            .collect();
+        buys.sort_by(|a, b| b.price.cmp(&a.price).then(a.timestamp.cmp(&b.timestamp)));
+        let mut sells: Vec<Order> = orders
+            .values()
+            .filter(|o| o.side == Side::Sell && o.price <= eq.price)
+            .cloned()
+            .collect();
+        sells.sort_by(|a, b| a.price.cmp(&b.price).then(a.timestamp.cmp(&b.timestamp)));
+
+        let mut trades = Vec::new();
+        let mut remaining = eq.matched_volume;
+        let (mut bi, mut si) = (0, 0);
+        while remaining > Decimal::ZERO && bi < buys.len() && si < sells.len() {
+            let quantity = remaining
+                .min(buys[bi].remaining_quantity())
+                .min(sells[si].remaining_quantity());
+            buys[bi].filled_quantity += quantity;
+            sells[si].filled_quantity += quantity;
+            remaining -= quantity;
+
+            // The earlier order is treated as the maker.
+            let (buy, sell) = (&buys[bi], &sells[si]);
+            let mut trade = if buy.timestamp <= sell.timestamp {
+                Trade::new(buy, sell, quantity)
+            } else {
+                Trade::new(sell, buy, quantity)
+            };
+            trade.price = eq.price;
+            trades.push(trade);
+
+            if buys[bi].is_filled() {
+                bi += 1;
+            }
+            if sells[si].is_filled() {
+                si += 1;
+            }
+        }
+
+        let mut buy_orders = self.buy_orders.write();
+        let mut sell_orders = self.sell_orders.write();
+        for order in buys.into_iter().chain(sells) {
+            self.record_undo(&orders, order.id);
+            if order.is_filled() {
+                buy_orders.remove(&order.id);
+                sell_orders.remove(&order.id);
+                orders.remove(&order.id);
+            } else {
+                orders.insert(order.id, order);
+            }
+        }
+
+        trades
+    }
+
+    /// Applies fees, settles on the ledger and broadcasts the trades,
+    /// undoing the book changes recorded in `undo` if they can't settle.
+    fn settle_trades(&self, instrument: &Instrument, trades: &mut [Trade], undo: Option<UndoScope<'_>>) -> OrderBookResult<()> {
+        self.stamp_fees(instrument, trades);
+        if let Err(err) = self.post_trades(instrument, trades) {
+            self.restore_after_settlement(undo, &err);
+            return Err(err);
+        }
+        drop(undo);
+        self.publish_trades(trades);
+        Ok(())
+    }
+
+    fn stamp_fees(&self, instrument: &Instrument, trades: &mut [Trade]) {
+        if let Some(fee_engine) = &self.fee_engine {
+            for trade in trades.iter_mut() {
+                fee_engine.stamp(trade, &instrument.quote_asset);
+            }
+        }
+    }
+
+    /// Settles the trades and their fees on the ledger, all or none.
+    fn post_trades(&self, instrument: &Instrument, trades: &[Trade]) -> OrderBookResult<()> {
+        if let (Some(ledger), false) = (&self.ledger, trades.is_empty()) {
+            ledger.settle_trades(trades, &instrument.base_asset, &instrument.quote_asset)?;
+        }
+        Ok(())
+    }
+
+    /// Credits fee volume, updates the last price and broadcasts trades
+    /// that have settled.
+    fn publish_trades(&self, trades: &[Trade]) {
+        if let Some(trade) = trades.last() {
+            *self.last_trade_price.write() = Some(trade.price);
+        }
+        if let Some(fee_engine) = &self.fee_engine {
+            for trade in trades {
+                fee_engine.record_trade(trade);
+            }
+        }
+
+        // Broadcast trades
+        for trade in trades {
+            let _ = self.trade_tx.send(trade.clone());
+        }
+
+        self.enforce_reduce_only(trades);
+    }
+
+    fn position(&self, user_id: Uuid) -> OrderBookResult<Decimal> {
+        // Reduce-only needs positions, which only the ledger tracks.
+        let ledger = self.ledger.as_ref().ok_or(OrderBookError::ReduceOnlyRejected)?;
+        Ok(ledger.position(user_id, &self.symbol))
+    }
+
+    /// Quantity a reduce-only order may still trade before the owner's
+    /// position would flip, counting fills not yet settled in `pending`.
+    fn reduce_only_capacity(&self, order: &Order, pending: &HashMap<Uuid, Decimal>) -> Decimal {
+        let position = self
+            .ledger
+            .as_ref()
+            .map(|ledger| ledger.position(order.user_id, &self.symbol))
+            .unwrap_or(Decimal::ZERO)
+            + pending.get(&order.user_id).copied().unwrap_or(Decimal::ZERO);
+        match order.side {
+            Side::Sell => position.max(Decimal::ZERO),
+            Side::Buy => (-position).max(Decimal::ZERO),
+        }
+    }
+
+    /// Caps a new or requeued reduce-only order to the position left after
+    /// the owner's other resting reduce-only orders on the same side and
+    /// the unsettled `fills`.
+    fn cap_reduce_only(&self, instrument: &Instrument, mut order: Order, fills: &HashMap<Uuid, Decimal>) -> OrderBookResult<Order> {
+        if !order.reduce_only {
+            return Ok(order);
+        }
+        self.position(order.user_id)?;
+        let resting: Decimal = self
+            .orders
+            .read()
+            .values()
+            .filter(|o| o.reduce_only && o.id != order.id && o.user_id == order.user_id && o.side == order.side)
+            .map(|o| o.remaining_quantity())
+            .sum();
+        let capacity = self.reduce_only_capacity(&order, fills) - resting;
+        let capacity = (capacity / instrument.lot_size).floor() * instrument.lot_size;
+        if capacity <= Decimal::ZERO {
+            return Err(OrderBookError::ReduceOnlyRejected);
+        }
+        order.quantity = order.quantity.min(order.filled_quantity + capacity);
+        Ok(order)
+    }
+
+    /// Quantity a resting maker may fill against the taker. Reduce-only
+    /// makers are cut to what keeps their owner from flipping; the maker's
+    /// quantity is shrunk to match so it leaves the book once filled.
+    fn maker_fill(&self, maker: &mut Order, quantity: Decimal, pending: &HashMap<Uuid, Decimal>) -> Decimal {
+        if !maker.reduce_only {
+            return quantity;
+        }
+        let capacity = self.reduce_only_capacity(maker, pending);
+        if capacity < quantity {
+            maker.quantity = maker.filled_quantity + capacity;
+            return capacity;
+        }
+        quantity
+    }
+
+    /// Shrinks or cancels resting reduce-only orders of the traders whose
+    /// positions just changed, oldest order keeping its size first.
+    fn enforce_reduce_only(&self, trades: &[Trade]) {
+        if self.ledger.is_none() {
+            return;
+        }
+        let users: HashSet<Uuid> = trades.iter().flat_map(|t| [t.maker_user_id, t.taker_user_id]).collect();
+        let mut affected: Vec<Order> = self
+            .orders
+            .read()
+            .values()
+            .filter(|order| order.reduce_only && users.contains(&order.user_id))
+            .cloned()
+            .collect();
+        if affected.is_empty() {
+            return;
+        }
+        affected.sort_by_key(|order| order.timestamp);
+
+        let now = self.clock.now();
+        let mut allowance: HashMap<(Uuid, Side), Decimal> = HashMap::new();
+        for order in affected {
+            let left = allowance
+                .entry((order.user_id, order.side))
+                .or_insert_with(|| self.reduce_only_capacity(&order, &HashMap::new()));
+            let keep = order.remaining_quantity().min(*left);
+            *left -= keep;
+            if keep == order.remaining_quantity() {
+                continue;
+            }
+
+            if keep.is_zero() {
+                if let Some(cancelled) = self.remove_order(order.id) {
+                    self.publish_execution(&cancelled, ExecutionKind::Cancelled);
+                }
+                continue;
+            }
+            let restated = self.orders.write().get_mut(&order.id).map(|resting| {
+                resting.quantity = resting.filled_quantity + keep;
+                resting.updated_at = now;
+                resting.clone()
+            });
+            if let Some(restated) = restated {
+                self.publish_execution(&restated, ExecutionKind::Restated);
+            }
+        }
+    }
+
+    /// Best unpegged bid and offer; pegs are priced from these so they never
+    /// chase each other.
+    fn unpegged_quotes(&self) -> (Option<Decimal>, Option<Decimal>) {
+        let orders = self.orders.read();
+        let mut bid: Option<Decimal> = None;
+        let mut ask: Option<Decimal> = None;
+        for order in orders.values().filter(|order| order.peg.is_none()) {
+            match order.side {
+                Side::Buy => bid = Some(bid.map_or(order.price, |bid| bid.max(order.price))),
+                Side::Sell => ask = Some(ask.map_or(order.price, |ask| ask.min(order.price))),
+            }
+        }
+        (bid, ask)
+    }
+
+    /// Midpoint of the unpegged quotes; skipped while no pegs rest, since
+    /// only hidden pegs trade there.
+    fn midpoint(&self) -> Option<Decimal> {
+        if self.pegged.read().is_empty() {
+            return None;
+        }
+        match self.unpegged_quotes() {
+            (Some(bid), Some(ask)) => Some((bid + ask) / Decimal::TWO),
+            _ => None,
+        }
+    }
+
+    /// Sets the price of a pegged order from the current quotes.
+    fn price_peg(&self, instrument: &Instrument, mut order: Order) -> OrderBookResult<Order> {
+        let Some(peg) = order.peg else {
+            return Ok(order);
+        };
+        let quotes = self.unpegged_quotes();
+        order.price = peg_price(&peg, order.side, quotes, instrument.tick_size).ok_or(OrderBookError::NoPegReference)?;
+        Ok(order)
+    }
+
+    /// Reprices resting pegs after the unpegged top of book moved, then
+    /// crosses midpoint pegs that the new mid brought within their limits.
+    ///
+    /// Runs after the operation that moved the quotes has settled, so a
+    /// crossing that fails to settle is undone and logged rather than
+    /// failing that operation.
+    fn refresh_pegs(&self, instrument: &Instrument, state: MarketState) {
+        {
+            // Pegs filled by regular matching leave their ids behind.
+            let mut pegged = self.pegged.write();
+            let orders = self.orders.read();
+            self.record_pegged_undo(&pegged);
+            pegged.retain(|order_id| orders.contains_key(order_id));
+            if pegged.is_empty() {
+                return;
+            }
+        }
+        let quotes = self.unpegged_quotes();
+        {
+            let mut last = self.peg_quotes.lock();
+            if *last == quotes {
+                return;
+            }
+            *last = quotes;
+        }
+
+        let pegged: Vec<Uuid> = self.pegged.read().clone();
+        {
+            let mut orders = self.orders.write();
+            let mut buy_orders = self.buy_orders.write();
+            let mut sell_orders = self.sell_orders.write();
+            for order_id in &pegged {
+                self.record_undo(&orders, *order_id);
+                let Some(order) = orders.get_mut(order_id) else {
+                    continue;
+                };
+                let Some(peg) = order.peg else {
+                    continue;
+                };
+                let Some(price) = peg_price(&peg, order.side, quotes, instrument.tick_size) else {
+                    continue;
+                };
+                if price == order.price {
+                    continue;
+                }
+                order.price = price;
+                if order.is_hidden() {
+                    continue;
+                }
+                match order.side {
+                    Side::Buy => {
+                        buy_orders.change_priority(order_id, (price, Reverse(order.timestamp)));
+                    }
+                    Side::Sell => {
+                        sell_orders.change_priority(order_id, Reverse((price, order.timestamp)));
+                    }
+                }
+            }
+        }
+
+        let (Some(mid), true) = (self.midpoint(), state.matches_orders()) else {
+            return;
+        };
+        let undo = self.settlement_undo();
+        let mut trades = Vec::new();
+        for order_id in pegged {
+            let Some(mut buy) = self.get_order(order_id) else {
+                continue;
+            };
+            if buy.side != Side::Buy || !buy.is_hidden() || !crosses_midpoint(&buy, mid) {
+                continue;
+            }
+            let filled = self.match_midpoint(&mut buy, mid, &HashMap::new());
+            if filled.is_empty() {
+                continue;
+            }
+            trades.extend(filled);
+            if buy.is_filled() {
+                self.remove_order(order_id);
+            } else {
+                let mut orders = self.orders.write();
+                self.record_undo(&orders, order_id);
+                orders.insert(order_id, buy);
+            }
+        }
+        if let Err(err) = self.settle_trades(instrument, &mut trades, undo) {
+            log::warn!("{} midpoint peg crossing failed to settle: {}", self.symbol, err);
+        }
+    }
+
+    /// Fills `order` against opposite hidden midpoint pegs at `mid`, oldest
+    /// first. The caller checks that `order` itself accepts `mid`.
+    fn match_midpoint(&self, order: &mut Order, mid: Decimal, fills: &HashMap<Uuid, Decimal>) -> Vec<Trade> {
+        let mut trades = Vec::new();
+        let mut filled = Vec::new();
+        let mut pending = fills.clone();
+        {
+            let pegged = self.pegged.read();
+            let mut orders = self.orders.write();
+            for resting_id in pegged.iter() {
+                if order.is_filled() {
+                    break;
+                }
+                self.record_undo(&orders, *resting_id);
+                let Some(resting) = orders.get_mut(resting_id) else {
+                    continue;
+                };
+                if resting.side == order.side || !resting.is_hidden() || !crosses_midpoint(resting, mid) {
+                    continue;
+                }
+
+                let quantity = self.maker_fill(resting, order.remaining_quantity().min(resting.remaining_quantity()), &pending);
+                order.filled_quantity += quantity;
+                resting.filled_quantity += quantity;
+                record_fill(&mut pending, resting, order, quantity);
+                if quantity.is_zero() {
+                    self.publish_execution(resting, ExecutionKind::Cancelled);
+                    filled.push(*resting_id);
+                    continue;
+                }
+                let mut trade = Trade::new(resting, order, quantity);
+                trade.price = mid;
+                trades.push(trade);
+                if resting.is_filled() {
+                    filled.push(*resting_id);
+                }
+            }
+        }
+        for order_id in filled {
+            self.remove_order(order_id);
+        }
+        trades
+    }
+
+    fn expire_due(&self) -> Vec<Order> {
+        let now = self.clock.now();
+        let mut due = Vec::new();
+        {
+            let mut expiries = self.expiries.lock();
+            while let Some(Reverse((at, order_id))) = expiries.peek().copied() {
+                if at > now {
+                    break;
+                }
+                expiries.pop();
+                due.push((at, order_id));
+            }
+        }
+
+        let mut expired = Vec::new();
+        for (at, order_id) in due {
+            if self.get_order(order_id).is_none_or(|order| order.expires_at != Some(at)) {
+                continue;
+            }
+            if let Some(order) = self.remove_order(order_id) {
+                self.publish_execution(&order, ExecutionKind::Expired);
+                expired.push(order);
+            }
+        }
+        if !expired.is_empty() {
+            log::info!("{} expired {} good-till-date orders", self.symbol, expired.len());
+        }
+        expired
+    }
+
+    fn remove_order(&self, order_id: Uuid) -> Option<Order> {
+        let mut orders = self.orders.write();
+        self.record_undo(&orders, order_id);
+        let order = orders.remove(&order_id)?;
+        if order.peg.is_some() {
+            let mut pegged = self.pegged.write();
+            self.record_pegged_undo(&pegged);
+            pegged.retain(|id| *id != order_id);
+        }
+        match order.side {
+            Side::Buy => {
+                let mut buy_orders = self.buy_orders.write();
+                buy_orders.remove(&order_id);
+            }
+            Side::Sell => {
+                let mut sell_orders = self.sell_orders.write();
+                sell_orders.remove(&order_id);
+            }
+        }
+        Some(order)
+    }
+
+    fn match_buy_order(&self, order: &mut Order, price_limit: Option<Decimal>, fills: &HashMap<Uuid, Decimal>) -> Vec<Trade> {
+        let mut trades = Vec::new();
+        let mut pending = fills.clone();
+        let mut sell_orders = self.sell_orders.write();
+        let mut orders = self.orders.write();
+
+        while let Some((sell_order_id, Reverse((price, _)))) = sell_orders.peek() {
+            // Check if the buy price is acceptable
+            if order.order_type == OrderType::Limit && order.price < *price {
+                break;
+            }
+            if price_limit.is_some_and(|limit| *price > limit) {
+                break;
+            }
+
+            let sell_order_id = *sell_order_id;
+            self.record_undo(&orders, sell_order_id);
+            let Some(sell_order) = orders.get_mut(&sell_order_id) else {
+                sell_orders.pop();
+                continue;
+            };
+
+            let match_quantity = self.maker_fill(sell_order, order.remaining_quantity().min(sell_order.remaining_quantity()), &pending);
+            if match_quantity.is_zero() {
+                // Reduce-only maker whose owner is already flat.
+                sell_orders.pop();
+                if let Some(cancelled) = orders.remove(&sell_order_id) {
+                    self.publish_execution(&cancelled, ExecutionKind::Cancelled);
+                }
+                continue;
+            }
+            // Update order quantities
+            order.filled_quantity += match_quantity;
+            sell_order.filled_quantity += match_quantity;
+            record_fill(&mut pending, sell_order, order, match_quantity);
+
+            // Create trade
+            let trade = Trade::new(sell_order, order, match_quantity);
+            trades.push(trade);
+
+            // Remove filled sell order
+            if sell_order.is_filled() {
+                sell_orders.pop();
+                orders.remove(&sell_order_id);
+            }
+
+            if order.is_filled() {
+                break;
+            }
+        }
+
+        trades
+    }
+
+    fn match_sell_order(&self, order: &mut Order, price_limit: Option<Decimal>, fills: &HashMap<Uuid, Decimal>) -> Vec<Trade> {
+        let mut trades = Vec::new();
+        let mut pending = fills.clone();
+        let mut buy_orders = self.buy_orders.write();
+        let mut orders = self.orders.write();
+
+        while let Some((buy_order_id, (price, _))) = buy_orders.peek() {
+            // Check if the sell price is acceptable
+            if order.order_type == OrderType::Limit && order.price > *price {
+                break;
+            }
+            if price_limit.is_some_and(|limit| *price < limit) {
+                break;
+            }
+
+            let buy_order_id = *buy_order_id;
+            self.record_undo(&orders, buy_order_id);
+            let Some(buy_order) = orders.get_mut(&buy_order_id) else {
+                buy_orders.pop();
+                continue;
+            };
+
+            let match_quantity = self.maker_fill(buy_order, order.remaining_quantity().min(buy_order.remaining_quantity()), &pending);
+            if match_quantity.is_zero() {
+                // Reduce-only maker whose owner is already flat.
+                buy_orders.pop();
+                if let Some(cancelled) = orders.remove(&buy_order_id) {
+                    self.publish_execution(&cancelled, ExecutionKind::Cancelled);
+                }
+                continue;
+            }
+            // Update order quantities
+            order.filled_quantity += match_quantity;
+            buy_order.filled_quantity += match_quantity;
+            record_fill(&mut pending, buy_order, order, match_quantity);
+
+            // Create trade
+            let trade = Trade::new(buy_order, order, match_quantity);
+            trades.push(trade);
+
+            // Remove filled buy order
+            if buy_order.is_filled() {
+                buy_orders.pop();
+                orders.remove(&buy_order_id);
+            }
+
+            if order.is_filled() {
+                break;
+            }
+        }
+
+        trades
+    }
+
+    fn add_order_to_book(&self, order: Order) {
+        let order_id = order.id;
+        let price = order.price;
+        let timestamp = order.timestamp;
+        self.record_undo(&self.orders.read(), order_id);
+        if let Some(expires_at) = order.expires_at {
+            self.expiries.lock().push(Reverse((expires_at, order_id)));
+        }
+        if order.peg.is_some() {
+            let mut pegged = self.pegged.write();
+            self.record_pegged_undo(&pegged);
+            pegged.push(order_id);
+        }
+        if order.is_hidden() {
+            self.orders.write().insert(order_id, order);
+            return;
+        }
+
+        match order.side {
+            Side::Buy => {
+                let mut buy_orders = self.buy_orders.write();
+                buy_orders.push(order_id, (price, Reverse(timestamp)));
+            }
+            Side::Sell => {
+                let mut sell_orders = self.sell_orders.write();
+                sell_orders.push(order_id, Reverse((price, timestamp)));
+            }
+        }
+
+        self.orders.write().insert(order_id, order);
+    }
+}
+
+/// Tracks position changes of fills matched but not yet settled.
+fn record_fill(pending: &mut HashMap<Uuid, Decimal>, maker: &Order, taker: &Order, quantity: Decimal) {
+    for order in [maker, taker] {
+        let signed = match order.side {
+            Side::Buy => quantity,
+            Side::Sell => -quantity,
+        };
+        *pending.entry(order.user_id).or_default() += signed;
+    }
+}
+
+/// Adds the position changes of trades not settled yet to `fills`.
+fn record_trade_fills(fills: &mut HashMap<Uuid, Decimal>, trades: &[Trade]) {
+    for trade in trades {
+        *fills.entry(trade.buyer_id()).or_default() += trade.quantity;
+        *fills.entry(trade.seller_id()).or_default() -= trade.quantity;
+    }
+}
+
+/// Current price of a peg, or `None` while its reference side is empty.
+///
+/// Primary pegs are rounded passively onto the tick grid and kept a tick
+/// away from the opposite quote so repricing never takes liquidity.
+fn peg_price(peg: &Peg, side: Side, quotes: (Option<Decimal>, Option<Decimal>), tick_size: Decimal) -> Option<Decimal> {
+    let (bid, ask) = quotes;
+    let price = match peg.reference {
+        PegReference::Midpoint => (bid? + ask?) / Decimal::TWO,
+        PegReference::BestBid | PegReference::BestOffer => {
+            let reference = if peg.reference == PegReference::BestBid { bid? } else { ask? };
+            let raw = reference + peg.offset;
+            match side {
+                Side::Buy => {
+                    let price = (raw / tick_size).floor() * tick_size;
+                    ask.map_or(price, |ask| price.min(ask - tick_size))
+                }
+                Side::Sell => {
+                    let price = (raw / tick_size).ceil() * tick_size;
+                    bid.map_or(price, |bid| price.max(bid + tick_size))
+                }
+            }
+        }
+    };
+    Some(match (side, peg.limit) {
+        (Side::Buy, Some(limit)) => price.min(limit),
+        (Side::Sell, Some(limit)) => price.max(limit),
+        (_, None) => price,
+    })
+}
+
+/// Whether `order` may trade at the midpoint: within a limit order's price
+/// or a midpoint peg's cap.
+fn crosses_midpoint(order: &Order, mid: Decimal) -> bool {
+    let limit = match (order.peg, order.order_type) {
+        (Some(peg), _) => match peg.limit {
+            Some(limit) => limit,
+            None => return true,
+        },
+        (None, OrderType::Market) => return true,
+        (None, OrderType::Limit) => order.price,
+    };
+    match order.side {
+        Side::Buy => mid <= limit,
+        Side::Sell => mid >= limit,
+    }
+}
+
+/// Applies an amendment; the new quantity must leave something to fill.
+fn amended_order(existing: &Order, price: Option<Decimal>, quantity: Option<Decimal>) -> OrderBookResult<Order> {
+    let mut amended = existing.clone();
+    if let Some(price) = price {
+        if existing.peg.is_some() {
+            return Err(OrderBookError::InvalidPrice);
+        }
+        amended.price = price;
+    }
+    if let Some(quantity) = quantity {
+        if quantity <= existing.filled_quantity {
+            return Err(OrderBookError::InsufficientQuantity);
+        }
+        amended.quantity = quantity;
+    }
+    Ok(amended)
+}
+
+/// Whether an amendment loses time priority: anything but a pure size cut.
+fn requeues(existing: &Order, amended: &Order) -> bool {
+    amended.price != existing.price || amended.quantity > existing.quantity
+}
+
+fn auction_levels(orders: &HashMap<Uuid, Order>) -> (Vec<AuctionLevel>, Vec<AuctionLevel>) {
+    let mut bids = Vec::new();
+    let mut asks = Vec::new();
+    for order in orders.values() {
+        let level = AuctionLevel {
+            price: order.price,
+            quantity: order.remaining_quantity(),
+        };
+        match order.side {
+            Side::Buy => bids.push(level),
+            Side::Sell => asks.push(level),
+        }
+    }
+    (bids, asks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::fees::FeeSchedule;
+    use crate::risk::{RiskLimits, RiskViolation};
+    use crate::utils::clock::ManualClock;
+    use crate::ledger::{LedgerAccount, LedgerError};
+    use rust_decimal_macros::dec;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Barrier;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_order_book_creation() {
+        let (tx, _) = mpsc::unbounded_channel();
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap();
         assert_eq!(order_book.symbol, "BTC/USD");
     }
+
+    #[tokio::test]
+    async fn test_trades_settle_on_ledger() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let ledger = Arc::new(Ledger::new());
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap().with_ledger(ledger.clone());
+        let buyer = Uuid::new_v4();
+        let seller = Uuid::new_v4();
+        ledger.deposit(buyer, "USD", dec!(100000)).unwrap();
+        ledger.deposit(seller, "BTC", dec!(1)).unwrap();
+
+        let sell = Order::new(seller, "BTC/USD".to_string(), Side::Sell, OrderType::Limit, dec!(50000), dec!(1));
+        let buy = Order::new(buyer, "BTC/USD".to_string(), Side::Buy, OrderType::Limit, dec!(50000), dec!(1));
+        order_book.process_order(sell).await.unwrap();
+        let trades = order_book.process_order(buy).await.unwrap();
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(ledger.account(buyer).get_balance("BTC"), dec!(1));
+        assert_eq!(ledger.account(seller).get_balance("USD"), dec!(50000));
+        assert_eq!(ledger.position(buyer, "BTC/USD"), dec!(1));
+        ledger.check_invariants().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_trades_carry_fees() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let ledger = Arc::new(Ledger::new());
+        let fee_engine = Arc::new(FeeEngine::new(FeeSchedule::flat(dec!(-0.0001), dec!(0.0005))));
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap()
+            .with_ledger(ledger.clone())
+            .with_fee_engine(fee_engine);
+        let maker = Uuid::new_v4();
+        let taker = Uuid::new_v4();
+        ledger.deposit(maker, "BTC", dec!(1)).unwrap();
+        ledger.deposit(taker, "USD", dec!(50025)).unwrap();
+
+        let sell = Order::new(maker, "BTC/USD".to_string(), Side::Sell, OrderType::Limit, dec!(50000), dec!(1));
+        let buy = Order::new(taker, "BTC/USD".to_string(), Side::Buy, OrderType::Limit, dec!(50000), dec!(1));
+        order_book.process_order(sell).await.unwrap();
+        let trades = order_book.process_order(buy).await.unwrap();
+
+        assert_eq!(trades[0].maker_fee, dec!(-5));
+        assert_eq!(trades[0].taker_fee, dec!(25));
+        assert_eq!(trades[0].fee_asset.as_deref(), Some("USD"));
+        assert_eq!(ledger.account(maker).get_balance("USD"), dec!(50005));
+        assert_eq!(ledger.account(taker).get_balance("USD"), dec!(0));
+        assert_eq!(ledger.balance(&LedgerAccount::FeeRevenue, "USD"), dec!(20));
+        ledger.check_invariants().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unfunded_trades_roll_back() {
+        let (tx, mut trade_rx) = mpsc::unbounded_channel();
+        let ledger = Arc::new(Ledger::new());
+        let fee_engine = Arc::new(FeeEngine::new(FeeSchedule::flat(dec!(0), dec!(0.0005))));
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap()
+            .with_ledger(ledger.clone())
+            .with_fee_engine(fee_engine.clone());
+        let (maker, taker) = (Uuid::new_v4(), Uuid::new_v4());
+        ledger.deposit(maker, "BTC", dec!(2)).unwrap();
+        // Covers the notional but not the taker fee.
+        ledger.deposit(taker, "USD", dec!(100)).unwrap();
+        let ask = limit(maker, Side::Sell, dec!(100), dec!(1));
+        let ask_id = ask.id;
+        order_book.process_order(ask).await.unwrap();
+
+        assert!(matches!(
+            order_book.process_order(limit(taker, Side::Buy, dec!(100), dec!(1))).await,
+            Err(OrderBookError::Settlement(LedgerError::InsufficientBalance { .. }))
+        ));
+        assert_eq!(order_book.get_order(ask_id).unwrap().remaining_quantity(), dec!(1));
+        assert_eq!(order_book.best_bid(), None);
+        assert_eq!(order_book.last_trade_price(), None);
+        assert!(trade_rx.try_recv().is_err());
+        assert!(fee_engine.rolling_volume(taker).is_zero());
+        assert_eq!(ledger.account(taker).get_balance("USD"), dec!(100));
+
+        // An unfunded seller's amend that would cross is undone too.
+        let bid = limit(taker, Side::Buy, dec!(90), dec!(1));
+        let bid_id = bid.id;
+        order_book.process_order(bid).await.unwrap();
+        let unfunded = limit(Uuid::new_v4(), Side::Sell, dec!(95), dec!(1));
+        let unfunded_id = unfunded.id;
+        order_book.process_order(unfunded).await.unwrap();
+        assert!(order_book.amend_order(unfunded_id, Some(dec!(90)), None).await.is_err());
+        assert_eq!(order_book.get_order(unfunded_id).unwrap().price, dec!(95));
+        assert_eq!(order_book.get_order(bid_id).unwrap().remaining_quantity(), dec!(1));
+        ledger.check_invariants().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_orders_validated_against_instrument() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let instrument = Instrument {
+            tick_size: dec!(0.5),
+            lot_size: dec!(0.01),
+            min_quantity: dec!(0.01),
+            ..Instrument::new("BTC", "USD")
+        };
+        let order_book = OrderBook::with_instrument(instrument, tx);
+
+        let off_tick = Order::new(Uuid::new_v4(), "BTC/USD".to_string(), Side::Buy, OrderType::Limit, dec!(50000.2), dec!(1));
+        assert!(matches!(
+            order_book.process_order(off_tick).await,
+            Err(OrderBookError::InvalidTickSize(_))
+        ));
+
+        let wrong_symbol = Order::new(Uuid::new_v4(), "ETH/USD".to_string(), Side::Buy, OrderType::Limit, dec!(3000), dec!(1));
+        assert!(matches!(
+            order_book.process_order(wrong_symbol).await,
+            Err(OrderBookError::SymbolMismatch(_))
+        ));
+
+        let valid = Order::new(Uuid::new_v4(), "BTC/USD".to_string(), Side::Buy, OrderType::Limit, dec!(50000.5), dec!(1));
+        assert!(order_book.process_order(valid).await.is_ok());
+    }
+
+    fn limit(user_id: Uuid, side: Side, price: Decimal, quantity: Decimal) -> Order {
+        Order::new(user_id, "BTC/USD".to_string(), side, OrderType::Limit, price, quantity)
+    }
+
+    #[tokio::test]
+    async fn test_price_time_priority() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap();
+        let cheap = limit(Uuid::new_v4(), Side::Sell, dec!(50000), dec!(1));
+        let expensive = limit(Uuid::new_v4(), Side::Sell, dec!(50100), dec!(1));
+        order_book.process_order(expensive.clone()).await.unwrap();
+        order_book.process_order(cheap.clone()).await.unwrap();
+        order_book.process_order(limit(Uuid::new_v4(), Side::Buy, dec!(49000), dec!(1))).await.unwrap();
+        order_book.process_order(limit(Uuid::new_v4(), Side::Buy, dec!(49500), dec!(1))).await.unwrap();
+
+        assert_eq!(order_book.best_ask(), Some(dec!(50000)));
+        assert_eq!(order_book.best_bid(), Some(dec!(49500)));
+
+        let trades = order_book
+            .process_order(limit(Uuid::new_v4(), Side::Buy, dec!(50100), dec!(1.5)))
+            .await
+            .unwrap();
+        assert_eq!(trades[0].order_id, cheap.id);
+        assert_eq!(trades[1].order_id, expensive.id);
+        assert!(order_book.get_order(cheap.id).is_none());
+        assert_eq!(order_book.get_order(expensive.id).unwrap().remaining_quantity(), dec!(0.5));
+    }
+
+    #[tokio::test]
+    async fn test_halted_market_rejects_orders_and_cancels() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap();
+        let resting = limit(Uuid::new_v4(), Side::Sell, dec!(50000), dec!(1));
+        order_book.process_order(resting.clone()).await.unwrap();
+
+        assert_eq!(order_book.set_market_state(MarketState::Halted), MarketState::Open);
+        assert!(matches!(
+            order_book.process_order(limit(Uuid::new_v4(), Side::Buy, dec!(50000), dec!(1))).await,
+            Err(OrderBookError::MarketStateRejected(MarketState::Halted))
+        ));
+        assert!(order_book.cancel_order(resting.id).await.is_err());
+
+        order_book.set_market_state(MarketState::CancelOnly);
+        assert!(order_book.process_order(limit(Uuid::new_v4(), Side::Buy, dec!(50000), dec!(1))).await.is_err());
+        assert!(order_book.cancel_order(resting.id).await.unwrap().is_some());
+    }
+
+    /// Risk check that parks the next order it sees until released, holding
+    /// a match in flight.
+    #[derive(Clone)]
+    struct Gate(Arc<GateState>);
+
+    struct GateState {
+        armed: AtomicBool,
+        entered: Barrier,
+        released: Barrier,
+    }
+
+    impl Gate {
+        fn new() -> Self {
+            Self(Arc::new(GateState {
+                armed: AtomicBool::new(false),
+                entered: Barrier::new(2),
+                released: Barrier::new(2),
+            }))
+        }
+
+        fn arm(&self) {
+            self.0.armed.store(true, Ordering::SeqCst);
+        }
+
+        fn entered(&self) {
+            self.0.entered.wait();
+        }
+
+        fn release(&self) {
+            self.0.released.wait();
+        }
+    }
+
+    impl crate::risk::RiskCheck for Gate {
+        fn name(&self) -> &'static str {
+            "gate"
+        }
+
+        fn check(&self, _: &Order, _: &RiskLimits, _: &RiskContext) -> Result<(), RiskViolation> {
+            if self.0.armed.swap(false, Ordering::SeqCst) {
+                self.entered();
+                self.release();
+            }
+            Ok(())
+        }
+    }
+
+    fn gated_book(gate: &Gate) -> Arc<OrderBook> {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let risk_engine = RiskEngine::with_checks(RiskLimits::default(), vec![Box::new(gate.clone())]);
+        Arc::new(OrderBook::new("BTC/USD".to_string(), tx).unwrap().with_risk_engine(Arc::new(risk_engine)))
+    }
+
+    /// Halts `book` from another thread while the gated order is matching,
+    /// checking that the halt waits for it.
+    fn halt_during(book: &Arc<OrderBook>, gate: &Gate) -> std::thread::JoinHandle<()> {
+        gate.entered();
+        let halted = Arc::new(AtomicBool::new(false));
+        let halt = {
+            let (book, halted) = (book.clone(), halted.clone());
+            std::thread::spawn(move || {
+                book.set_market_state(MarketState::Halted);
+                halted.store(true, Ordering::SeqCst);
+            })
+        };
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!halted.load(Ordering::SeqCst));
+        gate.release();
+        halt
+    }
+
+    #[test]
+    fn test_halt_waits_for_in_flight_match() {
+        let gate = Gate::new();
+        let order_book = gated_book(&gate);
+        futures::executor::block_on(order_book.process_order(limit(Uuid::new_v4(), Side::Sell, dec!(100), dec!(2))))
+            .unwrap();
+
+        gate.arm();
+        let taker = {
+            let order_book = order_book.clone();
+            std::thread::spawn(move || {
+                futures::executor::block_on(order_book.process_order(limit(Uuid::new_v4(), Side::Buy, dec!(100), dec!(1))))
+            })
+        };
+        halt_during(&order_book, &gate).join().unwrap();
+
+        // The order that was matching when the halt arrived completes.
+        assert_eq!(taker.join().unwrap().unwrap().len(), 1);
+        assert_eq!(order_book.market_state(), MarketState::Halted);
+        assert!(matches!(
+            futures::executor::block_on(order_book.process_order(limit(Uuid::new_v4(), Side::Buy, dec!(100), dec!(1)))),
+            Err(OrderBookError::MarketStateRejected(MarketState::Halted))
+        ));
+    }
+
+    #[test]
+    fn test_halt_waits_for_in_flight_batch_and_amend() {
+        let gate = Gate::new();
+        let order_book = gated_book(&gate);
+        futures::executor::block_on(order_book.process_order(limit(Uuid::new_v4(), Side::Sell, dec!(100), dec!(3))))
+            .unwrap();
+        let bid = limit(Uuid::new_v4(), Side::Buy, dec!(90), dec!(1));
+        let bid_id = bid.id;
+        futures::executor::block_on(order_book.process_order(bid)).unwrap();
+
+        gate.arm();
+        let batch = {
+            let order_book = order_book.clone();
+            let ops = vec![BatchOp::New(limit(Uuid::new_v4(), Side::Buy, dec!(100), dec!(1)))];
+            std::thread::spawn(move || futures::executor::block_on(order_book.process_batch(ops, false)))
+        };
+        halt_during(&order_book, &gate).join().unwrap();
+        let results = batch.join().unwrap().unwrap();
+        assert!(matches!(&results[0], Ok(BatchOutcome::Placed { trades, .. }) if trades.len() == 1));
+
+        order_book.set_market_state(MarketState::Open);
+        gate.arm();
+        let amend = {
+            let order_book = order_book.clone();
+            std::thread::spawn(move || futures::executor::block_on(order_book.amend_order(bid_id, Some(dec!(100)), None)))
+        };
+        halt_during(&order_book, &gate).join().unwrap();
+        assert_eq!(amend.join().unwrap().unwrap().1.len(), 1);
+        assert_eq!(order_book.market_state(), MarketState::Halted);
+    }
+
+    #[tokio::test]
+    async fn test_pre_open_accumulates_without_matching() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap();
+        order_book.set_market_state(MarketState::PreOpen);
+
+        order_book.process_order(limit(Uuid::new_v4(), Side::Sell, dec!(50000), dec!(1))).await.unwrap();
+        let trades = order_book
+            .process_order(limit(Uuid::new_v4(), Side::Buy, dec!(50100), dec!(1)))
+            .await
+            .unwrap();
+
+        assert!(trades.is_empty());
+        assert_eq!(order_book.best_bid(), Some(dec!(50100)));
+        assert_eq!(order_book.best_ask(), Some(dec!(50000)));
+        let market = Order::new(Uuid::new_v4(), "BTC/USD".to_string(), Side::Buy, OrderType::Market, Decimal::ZERO, dec!(1));
+        assert!(order_book.process_order(market).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_auction_uncrosses_at_single_price() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let (auction_tx, mut auction_rx) = mpsc::unbounded_channel();
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap().with_auction_publisher(auction_tx);
+        order_book.start_auction();
+
+        order_book.process_order(limit(Uuid::new_v4(), Side::Buy, dec!(102), dec!(5))).await.unwrap();
+        order_book.process_order(limit(Uuid::new_v4(), Side::Buy, dec!(101), dec!(5))).await.unwrap();
+        order_book.process_order(limit(Uuid::new_v4(), Side::Sell, dec!(99), dec!(4))).await.unwrap();
+        order_book.process_order(limit(Uuid::new_v4(), Side::Sell, dec!(100), dec!(4))).await.unwrap();
+
+        let mut last = None;
+        while let Ok(indicative) = auction_rx.try_recv() {
+            last = Some(indicative);
+        }
+        let last = last.unwrap();
+        assert_eq!(last.price, Some(dec!(101)));
+        assert_eq!(last.matched_volume, dec!(8));
+        assert_eq!(last.imbalance, dec!(2));
+        assert_eq!(last.imbalance_side, Some(Side::Buy));
+
+        let trades = order_book.uncross().await.unwrap();
+        assert!(trades.iter().all(|t| t.price == dec!(101)));
+        assert_eq!(trades.iter().map(|t| t.quantity).sum::<Decimal>(), dec!(8));
+        assert_eq!(order_book.market_state(), MarketState::Open);
+        assert_eq!(order_book.best_bid(), Some(dec!(101)));
+        assert_eq!(order_book.best_ask(), None);
+        assert_eq!(order_book.last_trade_price(), Some(dec!(101)));
+    }
+
+    #[tokio::test]
+    async fn test_unfunded_uncross_stays_in_call_phase() {
+        let (tx, mut trade_rx) = mpsc::unbounded_channel();
+        let ledger = Arc::new(Ledger::new());
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap().with_ledger(ledger.clone());
+        let seller = Uuid::new_v4();
+        ledger.deposit(seller, "BTC", dec!(1)).unwrap();
+        order_book.start_auction();
+        let bid = limit(Uuid::new_v4(), Side::Buy, dec!(101), dec!(1));
+        let ask = limit(seller, Side::Sell, dec!(99), dec!(1));
+        let (bid_id, ask_id) = (bid.id, ask.id);
+        order_book.process_order(bid).await.unwrap();
+        order_book.process_order(ask).await.unwrap();
+
+        assert!(matches!(
+            order_book.uncross().await,
+            Err(OrderBookError::Settlement(LedgerError::InsufficientBalance { .. }))
+        ));
+        assert_eq!(order_book.market_state(), MarketState::PreOpen);
+        assert_eq!(order_book.get_order(bid_id).unwrap().remaining_quantity(), dec!(1));
+        assert_eq!(order_book.get_order(ask_id).unwrap().remaining_quantity(), dec!(1));
+        assert_eq!(order_book.best_bid(), Some(dec!(101)));
+        assert!(trade_rx.try_recv().is_err());
+        ledger.check_invariants().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_uncross_requires_call_phase() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap();
+        assert!(matches!(
+            order_book.uncross().await,
+            Err(OrderBookError::MarketStateRejected(MarketState::Open))
+        ));
+    }
+
+    fn market(user_id: Uuid, side: Side, quantity: Decimal) -> Order {
+        Order::new(user_id, "BTC/USD".to_string(), side, OrderType::Market, Decimal::ZERO, quantity)
+    }
+
+    #[tokio::test]
+    async fn test_limit_orders_outside_band_rejected() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap().with_price_bands(PriceBandConfig::default());
+        order_book.set_reference_price(Some(dec!(100)));
+
+        assert!(matches!(
+            order_book.process_order(limit(Uuid::new_v4(), Side::Buy, dec!(111), dec!(1))).await,
+            Err(OrderBookError::PriceOutsideBand { .. })
+        ));
+        assert!(order_book.process_order(limit(Uuid::new_v4(), Side::Buy, dec!(109), dec!(1))).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_amend_outside_band_rejected() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap().with_price_bands(PriceBandConfig::default());
+        order_book.set_reference_price(Some(dec!(100)));
+        let user_id = Uuid::new_v4();
+        let bid = limit(user_id, Side::Buy, dec!(95), dec!(1)).with_client_order_id("bid");
+        let bid_id = bid.id;
+        order_book.process_order(bid).await.unwrap();
+
+        assert!(matches!(
+            order_book.amend_order(bid_id, Some(dec!(111)), None).await,
+            Err(OrderBookError::PriceOutsideBand { .. })
+        ));
+        let ops = vec![BatchOp::Amend { order_id: bid_id, price: Some(dec!(89)), quantity: None }];
+        assert!(matches!(
+            order_book.process_batch(ops, true).await,
+            Err(OrderBookError::BatchRejected { index: 0, .. })
+        ));
+        assert_eq!(order_book.get_order(bid_id).unwrap().price, dec!(95));
+
+        // An amend inside the band keeps its client order id.
+        let ops = vec![BatchOp::Amend { order_id: bid_id, price: Some(dec!(96)), quantity: None }];
+        order_book.process_batch(ops, true).await.unwrap();
+        assert_eq!(order_book.get_order_by_client_id(user_id, "bid").unwrap().price, dec!(96));
+    }
+
+    #[tokio::test]
+    async fn test_market_order_stops_at_band() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap().with_price_bands(PriceBandConfig::default());
+        order_book.process_order(limit(Uuid::new_v4(), Side::Sell, dec!(100), dec!(1))).await.unwrap();
+        order_book.process_order(limit(Uuid::new_v4(), Side::Sell, dec!(104), dec!(1))).await.unwrap();
+        order_book.process_order(limit(Uuid::new_v4(), Side::Sell, dec!(150), dec!(1))).await.unwrap();
+
+        let trades = order_book.process_order(market(Uuid::new_v4(), Side::Buy, dec!(3))).await.unwrap();
+
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades.iter().map(|t| t.quantity).sum::<Decimal>(), dec!(2));
+        assert_eq!(order_book.best_ask(), Some(dec!(150)));
+    }
+
+    #[tokio::test]
+    async fn test_volatility_halt_starts_auction() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let clock = Arc::new(ManualClock::new(Utc::now()));
+        let config = PriceBandConfig {
+            limit_band: dec!(0.5),
+            market_band: dec!(0.5),
+            ..PriceBandConfig::default()
+        };
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap()
+            .with_price_bands(config)
+            .with_clock(clock.clone());
+
+        order_book.process_order(limit(Uuid::new_v4(), Side::Sell, dec!(100), dec!(1))).await.unwrap();
+        order_book.process_order(limit(Uuid::new_v4(), Side::Buy, dec!(100), dec!(1))).await.unwrap();
+        assert_eq!(order_book.market_state(), MarketState::Open);
+
+        clock.advance(chrono::Duration::minutes(1));
+        order_book.process_order(limit(Uuid::new_v4(), Side::Sell, dec!(115), dec!(1))).await.unwrap();
+        order_book.process_order(limit(Uuid::new_v4(), Side::Buy, dec!(115), dec!(1))).await.unwrap();
+
+        assert_eq!(order_book.market_state(), MarketState::PreOpen);
+    }
+
+    #[tokio::test]
+    async fn test_replayed_trades_skip_volatility_check() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let clock = Arc::new(ManualClock::new(Utc::now()));
+        let config = PriceBandConfig {
+            limit_band: dec!(0.5),
+            market_band: dec!(0.5),
+            ..PriceBandConfig::default()
+        };
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap()
+            .with_price_bands(config)
+            .with_clock(clock.clone());
+        let user_id = Uuid::new_v4();
+        let request = || limit(user_id, Side::Buy, dec!(100), dec!(1)).with_client_order_id("first");
+
+        order_book.process_order(limit(Uuid::new_v4(), Side::Sell, dec!(100), dec!(1))).await.unwrap();
+        order_book.process_order(request()).await.unwrap();
+        clock.advance(chrono::Duration::minutes(6));
+        order_book.process_order(limit(Uuid::new_v4(), Side::Sell, dec!(112), dec!(1))).await.unwrap();
+        order_book.process_order(limit(Uuid::new_v4(), Side::Buy, dec!(112), dec!(1))).await.unwrap();
+
+        // Replaying the trade at 100 would look like a 10.7% drop.
+        let trades = order_book.process_order(request()).await.unwrap();
+        assert_eq!(trades[0].price, dec!(100));
+        let results = order_book.process_batch(vec![BatchOp::New(request())], true).await.unwrap();
+        assert!(results[0].is_ok());
+        assert_eq!(order_book.market_state(), MarketState::Open);
+    }
+
+    #[tokio::test]
+    async fn test_risk_engine_rejects_before_matching() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let ledger = Arc::new(Ledger::new());
+        let risk_engine = Arc::new(RiskEngine::default());
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap()
+            .with_ledger(ledger.clone())
+            .with_risk_engine(risk_engine.clone());
+        let user_id = Uuid::new_v4();
+        let mut limits = RiskLimits {
+            max_open_orders: Some(1),
+            ..RiskLimits::default()
+        };
+        limits.position_limits.insert("BTC/USD".to_string(), dec!(2));
+        risk_engine.set_limits(user_id, limits);
+
+        let seller = Uuid::new_v4();
+        ledger.deposit(user_id, "USD", dec!(1000)).unwrap();
+        ledger.deposit(seller, "BTC", dec!(1)).unwrap();
+
+        order_book.process_order(limit(user_id, Side::Buy, dec!(100), dec!(1))).await.unwrap();
+        assert!(matches!(
+            order_book.process_order(limit(user_id, Side::Buy, dec!(99), dec!(1))).await,
+            Err(OrderBookError::RiskRejected(RiskViolation::TooManyOpenOrders { limit: 1 }))
+        ));
+
+        order_book.process_order(limit(seller, Side::Sell, dec!(100), dec!(1))).await.unwrap();
+        assert_eq!(ledger.position(user_id, "BTC/USD"), dec!(1));
+        assert!(matches!(
+            order_book.process_order(limit(user_id, Side::Buy, dec!(100), dec!(1.5))).await,
+            Err(OrderBookError::RiskRejected(RiskViolation::PositionLimitExceeded { .. }))
+        ));
+        assert_eq!(risk_engine.rejections().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_mass_cancel_by_user_and_side() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap();
+        let maker = Uuid::new_v4();
+        let other = Uuid::new_v4();
+        for price in [dec!(99), dec!(98)] {
+            order_book.process_order(limit(maker, Side::Buy, price, dec!(1))).await.unwrap();
+        }
+        order_book.process_order(limit(maker, Side::Sell, dec!(101), dec!(1))).await.unwrap();
+        order_book.process_order(limit(other, Side::Sell, dec!(102), dec!(1))).await.unwrap();
+
+        let cancelled = order_book
+            .mass_cancel(CancelFilter { user_id: Some(maker), side: Some(Side::Buy) })
+            .await
+            .unwrap();
+        assert_eq!(cancelled.len(), 2);
+        assert_eq!(order_book.best_bid(), None);
+
+        let cancelled = order_book.mass_cancel(CancelFilter::side(Side::Sell)).await.unwrap();
+        assert_eq!(cancelled.len(), 2);
+        assert_eq!(order_book.best_ask(), None);
+    }
+
+    #[tokio::test]
+    async fn test_atomic_batch_rejects_whole_ladder() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let instrument = Instrument {
+            tick_size: dec!(1),
+            ..Instrument::new("BTC", "USD")
+        };
+        let order_book = OrderBook::with_instrument(instrument, tx);
+        let maker = Uuid::new_v4();
+        order_book.process_order(limit(maker, Side::Buy, dec!(99), dec!(1))).await.unwrap();
+        let resting = order_book.open_orders_for_user(maker)[0].id;
+
+        let ops = vec![
+            BatchOp::Cancel(resting),
+            BatchOp::New(limit(maker, Side::Buy, dec!(100), dec!(1))),
+            BatchOp::New(limit(maker, Side::Sell, dec!(101.5), dec!(1))),
+        ];
+        assert!(matches!(
+            order_book.process_batch(ops, true).await,
+            Err(OrderBookError::BatchRejected { index: 2, .. })
+        ));
+        assert_eq!(order_book.open_orders_for_user(maker).len(), 1);
+        assert_eq!(order_book.best_bid(), Some(dec!(99)));
+
+        // Cancelling the same order twice is caught before anything applies.
+        let ops = vec![BatchOp::Cancel(resting), BatchOp::Cancel(resting)];
+        assert!(matches!(
+            order_book.process_batch(ops, true).await,
+            Err(OrderBookError::BatchRejected { index: 1, .. })
+        ));
+        assert!(order_book.get_order(resting).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_atomic_batch_rolls_back_when_apply_fails() {
+        let (tx, mut trade_rx) = mpsc::unbounded_channel();
+        let ledger = Arc::new(Ledger::new());
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap().with_ledger(ledger.clone());
+        let (maker, taker) = (Uuid::new_v4(), Uuid::new_v4());
+        ledger.deposit(maker, "BTC", dec!(2)).unwrap();
+        ledger.deposit(taker, "USD", dec!(150)).unwrap();
+        let ask = limit(maker, Side::Sell, dec!(101), dec!(1));
+        let ask_id = ask.id;
+        order_book.process_order(ask).await.unwrap();
+        order_book.process_order(limit(maker, Side::Sell, dec!(102), dec!(1))).await.unwrap();
+
+        // Validation sees the ask resting, but the first operation fills it.
+        let ops = vec![
+            BatchOp::New(limit(taker, Side::Buy, dec!(101), dec!(1)).with_client_order_id("lift")),
+            BatchOp::Amend { order_id: ask_id, price: Some(dec!(103)), quantity: None },
+        ];
+        assert!(matches!(
+            order_book.process_batch(ops, true).await,
+            Err(OrderBookError::BatchRejected { index: 1, .. })
+        ));
+        assert_eq!(order_book.get_order(ask_id).unwrap().remaining_quantity(), dec!(1));
+        assert_eq!(order_book.best_ask(), Some(dec!(101)));
+        assert_eq!(order_book.best_bid(), None);
+        assert!(trade_rx.try_recv().is_err());
+        assert_eq!(ledger.position(taker, "BTC/USD"), Decimal::ZERO);
+        assert!(order_book.order_id_for_client_id(taker, "lift").is_none());
+
+        // A client order id used twice in one batch is caught up front.
+        let ops = vec![
+            BatchOp::New(limit(taker, Side::Buy, dec!(99), dec!(1)).with_client_order_id("dup")),
+            BatchOp::New(limit(taker, Side::Buy, dec!(98), dec!(1)).with_client_order_id("dup")),
+        ];
+        assert!(matches!(
+            order_book.process_batch(ops, true).await,
+            Err(OrderBookError::BatchRejected { index: 1, .. })
+        ));
+
+        let ops = vec![
+            BatchOp::New(limit(taker, Side::Buy, dec!(101), dec!(1))),
+            BatchOp::Cancel(ask_id),
+        ];
+        assert!(matches!(
+            order_book.process_batch(ops, true).await,
+            Err(OrderBookError::BatchRejected { index: 1, .. })
+        ));
+        assert_eq!(order_book.get_order(ask_id).unwrap().remaining_quantity(), dec!(1));
+
+        let results = order_book
+            .process_batch(vec![BatchOp::New(limit(taker, Side::Buy, dec!(101), dec!(1)))], true)
+            .await
+            .unwrap();
+        assert!(matches!(&results[0], Ok(BatchOutcome::Placed { trades, .. }) if trades[0].order_id == ask_id));
+        assert_eq!(trade_rx.try_recv().unwrap().order_id, ask_id);
+        assert_eq!(ledger.position(taker, "BTC/USD"), dec!(1));
+
+        // Each fill is affordable alone but not both, so neither settles.
+        ledger.deposit(maker, "BTC", dec!(1)).unwrap();
+        ledger.deposit(taker, "USD", dec!(100)).unwrap();
+        order_book.process_order(limit(maker, Side::Sell, dec!(103), dec!(1))).await.unwrap();
+        let ops = vec![
+            BatchOp::New(limit(taker, Side::Buy, dec!(102), dec!(1))),
+            BatchOp::New(limit(taker, Side::Buy, dec!(103), dec!(1))),
+        ];
+        assert!(matches!(
+            order_book.process_batch(ops, true).await,
+            Err(OrderBookError::Settlement(LedgerError::InsufficientBalance { .. }))
+        ));
+        assert_eq!(order_book.best_ask(), Some(dec!(102)));
+        assert_eq!(order_book.best_bid(), None);
+        assert_eq!(ledger.account(taker).get_balance("USD"), dec!(149));
+        ledger.check_invariants().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rollback_keeps_queue_priority() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap();
+        let first = limit(Uuid::new_v4(), Side::Sell, dec!(101), dec!(1));
+        let second = limit(Uuid::new_v4(), Side::Sell, dec!(101), dec!(1));
+        let (first_id, second_id) = (first.id, second.id);
+        order_book.process_order(first).await.unwrap();
+        order_book.process_order(second).await.unwrap();
+
+        let ops = vec![
+            BatchOp::New(limit(Uuid::new_v4(), Side::Buy, dec!(101), dec!(2))),
+            BatchOp::Amend { order_id: second_id, price: Some(dec!(102)), quantity: None },
+        ];
+        assert!(order_book.process_batch(ops, true).await.is_err());
+
+        // Both fills are undone and the first ask is still ahead of the second.
+        assert_eq!(order_book.get_order(second_id).unwrap().remaining_quantity(), dec!(1));
+        let trades = order_book.process_order(limit(Uuid::new_v4(), Side::Buy, dec!(101), dec!(1))).await.unwrap();
+        assert_eq!(trades[0].order_id, first_id);
+        assert_eq!(order_book.best_ask(), Some(dec!(101)));
+    }
+
+    #[tokio::test]
+    async fn test_batch_requote_and_partial_results() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap();
+        let maker = Uuid::new_v4();
+        let bid = limit(maker, Side::Buy, dec!(99), dec!(2));
+        let ask = limit(maker, Side::Sell, dec!(101), dec!(2));
+        let (bid_id, ask_id) = (bid.id, ask.id);
+        let results = order_book
+            .process_batch(vec![BatchOp::New(bid), BatchOp::New(ask)], true)
+            .await
+            .unwrap();
+        assert!(results.iter().all(|result| result.is_ok()));
+
+        let results = order_book
+            .process_batch(
+                vec![
+                    BatchOp::Amend { order_id: bid_id, price: Some(dec!(100)), quantity: None },
+                    BatchOp::Cancel(Uuid::new_v4()),
+                    BatchOp::Amend { order_id: ask_id, price: None, quantity: Some(dec!(1)) },
+                ],
+                false,
+            )
+            .await
+            .unwrap();
+        assert!(matches!(results[0], Ok(BatchOutcome::Amended { ref order, .. }) if order.price == dec!(100)));
+        assert!(matches!(results[1], Err(OrderBookError::OrderNotFound)));
+        assert!(results[2].is_ok());
+        assert_eq!(order_book.best_bid(), Some(dec!(100)));
+        assert_eq!(order_book.get_order(ask_id).unwrap().quantity, dec!(1));
+    }
+
+    #[tokio::test]
+    async fn test_amend_priority() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap();
+        let first = limit(Uuid::new_v4(), Side::Buy, dec!(100), dec!(2));
+        let second = limit(Uuid::new_v4(), Side::Buy, dec!(100), dec!(2));
+        let (first_id, second_id) = (first.id, second.id);
+        order_book.process_order(first).await.unwrap();
+        order_book.process_order(second).await.unwrap();
+
+        // A size cut keeps the first order at the front of the level.
+        order_book.amend_order(first_id, None, Some(dec!(1))).await.unwrap();
+        let trades = order_book.process_order(limit(Uuid::new_v4(), Side::Sell, dec!(100), dec!(1))).await.unwrap();
+        assert_eq!(trades[0].order_id, first_id);
+
+        // A size increase sends the order to the back.
+        let third = limit(Uuid::new_v4(), Side::Buy, dec!(100), dec!(1));
+        let third_id = third.id;
+        order_book.process_order(third).await.unwrap();
+        order_book.amend_order(second_id, None, Some(dec!(3))).await.unwrap();
+        let trades = order_book.process_order(limit(Uuid::new_v4(), Side::Sell, dec!(100), dec!(1))).await.unwrap();
+        assert_eq!(trades[0].order_id, third_id);
+
+        assert!(matches!(
+            order_book.amend_order(second_id, None, Some(dec!(0))).await,
+            Err(OrderBookError::InsufficientQuantity)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_client_order_id_retry_is_idempotent() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap();
+        let user_id = Uuid::new_v4();
+        order_book.process_order(limit(Uuid::new_v4(), Side::Sell, dec!(100), dec!(1))).await.unwrap();
+
+        let first = order_book
+            .process_order(limit(user_id, Side::Buy, dec!(100), dec!(2)).with_client_order_id("abc"))
+            .await
+            .unwrap();
+        // The retry is a fresh Order with a new server id but the same request.
+        let retry = order_book
+            .process_order(limit(user_id, Side::Buy, dec!(100), dec!(2)).with_client_order_id("abc"))
+            .await
+            .unwrap();
+        assert_eq!(first, retry);
+        assert_eq!(order_book.open_orders_for_user(user_id).len(), 1);
+
+        let resting = order_book.get_order_by_client_id(user_id, "abc").unwrap();
+        assert_eq!(resting.remaining_quantity(), dec!(1));
+        assert!(matches!(
+            order_book
+                .process_order(limit(user_id, Side::Buy, dec!(99), dec!(2)).with_client_order_id("abc"))
+                .await,
+            Err(OrderBookError::DuplicateClientOrderId(_))
+        ));
+
+        // Client ids are scoped to the user.
+        order_book
+            .process_order(limit(Uuid::new_v4(), Side::Buy, dec!(99), dec!(1)).with_client_order_id("abc"))
+            .await
+            .unwrap();
+
+        let cancelled = order_book.cancel_by_client_id(user_id, "abc").await.unwrap().unwrap();
+        assert_eq!(cancelled.id, resting.id);
+        assert!(matches!(
+            order_book.cancel_by_client_id(user_id, "missing").await,
+            Err(OrderBookError::OrderNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_good_till_date_expiry() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let (execution_tx, mut execution_rx) = mpsc::unbounded_channel();
+        let start = Utc::now();
+        let clock = Arc::new(ManualClock::new(start));
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap()
+            .with_clock(clock.clone())
+            .with_execution_publisher(execution_tx);
+        let user_id = Uuid::new_v4();
+        let short = limit(user_id, Side::Buy, dec!(100), dec!(1)).with_expiry(start + chrono::Duration::seconds(10));
+        let long = limit(user_id, Side::Buy, dec!(99), dec!(1)).with_expiry(start + chrono::Duration::seconds(60));
+        let short_id = short.id;
+        order_book.process_order(short).await.unwrap();
+        order_book.process_order(long).await.unwrap();
+        assert_eq!(order_book.next_expiry(), Some(start + chrono::Duration::seconds(10)));
+
+        clock.advance(chrono::Duration::seconds(5));
+        assert!(order_book.expire_orders().is_empty());
+
+        // An expired order is pulled before the next submission can trade with it.
+        clock.advance(chrono::Duration::seconds(5));
+        let trades = order_book.process_order(limit(Uuid::new_v4(), Side::Sell, dec!(99), dec!(1))).await.unwrap();
+        assert_eq!(trades[0].price, dec!(99));
+        assert!(order_book.get_order(short_id).is_none());
+
+        let report = execution_rx.try_recv().unwrap();
+        assert_eq!(report.order_id, short_id);
+        assert_eq!(report.kind, ExecutionKind::Expired);
+        assert_eq!(report.leaves_quantity, dec!(1));
+
+        // The filled order's heap entry is stale and skipped.
+        clock.advance(chrono::Duration::seconds(60));
+        assert!(order_book.expire_orders().is_empty());
+        assert!(execution_rx.try_recv().is_err());
+
+        let past = limit(user_id, Side::Buy, dec!(98), dec!(1)).with_expiry(start);
+        assert!(matches!(order_book.process_order(past).await, Err(OrderBookError::OrderExpired)));
+    }
+
+    fn gtd_book(start: DateTime<Utc>) -> (OrderBook, Arc<ManualClock>, mpsc::UnboundedReceiver<ExecutionReport>) {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let (execution_tx, execution_rx) = mpsc::unbounded_channel();
+        let clock = Arc::new(ManualClock::new(start));
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap()
+            .with_clock(clock.clone())
+            .with_execution_publisher(execution_tx);
+        (order_book, clock, execution_rx)
+    }
+
+    #[tokio::test]
+    async fn test_good_till_date_expires_at_its_deadline() {
+        let start = Utc::now();
+        let (order_book, clock, mut execution_rx) = gtd_book(start);
+        let deadline = start + chrono::Duration::seconds(10);
+        let order = limit(Uuid::new_v4(), Side::Buy, dec!(100), dec!(1)).with_expiry(deadline);
+        let order_id = order.id;
+        order_book.process_order(order).await.unwrap();
+
+        clock.set(deadline - chrono::Duration::milliseconds(1));
+        assert!(order_book.expire_orders().is_empty());
+        assert!(order_book.get_order(order_id).is_some());
+
+        // Expiry is inclusive: the order is gone at its deadline.
+        clock.set(deadline);
+        let expired = order_book.expire_orders();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].id, order_id);
+        assert_eq!(execution_rx.try_recv().unwrap().timestamp, deadline);
+        assert_eq!(order_book.next_expiry(), None);
+
+        // An order whose deadline is the current instant is already expired.
+        let due_now = limit(Uuid::new_v4(), Side::Buy, dec!(100), dec!(1)).with_expiry(deadline);
+        assert!(matches!(order_book.process_order(due_now).await, Err(OrderBookError::OrderExpired)));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_and_amended_orders_leave_stale_expiries() {
+        let start = Utc::now();
+        let (order_book, clock, mut execution_rx) = gtd_book(start);
+        let deadline = start + chrono::Duration::seconds(10);
+        let user_id = Uuid::new_v4();
+        let cancelled = limit(user_id, Side::Buy, dec!(100), dec!(1)).with_expiry(deadline);
+        let amended = limit(user_id, Side::Buy, dec!(99), dec!(1)).with_expiry(deadline);
+        let (cancelled_id, amended_id) = (cancelled.id, amended.id);
+        order_book.process_order(cancelled).await.unwrap();
+        order_book.process_order(amended).await.unwrap();
+
+        order_book.cancel_order(cancelled_id).await.unwrap().unwrap();
+        order_book.amend_order(amended_id, Some(dec!(98)), Some(dec!(2))).await.unwrap();
+        assert!(execution_rx.try_recv().is_err());
+        // The cancelled order's entry still heads the queue until it is drained.
+        assert_eq!(order_book.next_expiry(), Some(deadline));
+
+        clock.set(deadline);
+        let expired = order_book.expire_orders();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].id, amended_id);
+        assert_eq!(expired[0].price, dec!(98));
+
+        // Exactly one report, for the amended order's latest state.
+        let report = execution_rx.try_recv().unwrap();
+        assert_eq!(report.order_id, amended_id);
+        assert_eq!(report.leaves_quantity, dec!(2));
+        assert!(execution_rx.try_recv().is_err());
+        assert_eq!(order_book.next_expiry(), None);
+        assert!(order_book.expire_orders().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_partially_filled_order_expires() {
+        let start = Utc::now();
+        let (order_book, clock, mut execution_rx) = gtd_book(start);
+        let order = limit(Uuid::new_v4(), Side::Buy, dec!(100), dec!(2)).with_expiry(start + chrono::Duration::seconds(10));
+        let order_id = order.id;
+        order_book.process_order(order).await.unwrap();
+        order_book.process_order(limit(Uuid::new_v4(), Side::Sell, dec!(100), dec!(0.5))).await.unwrap();
+
+        clock.advance(chrono::Duration::seconds(10));
+        let expired = order_book.expire_orders();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(order_book.best_bid(), None);
+
+        let report = execution_rx.try_recv().unwrap();
+        assert_eq!(report.order_id, order_id);
+        assert_eq!(report.kind, ExecutionKind::Expired);
+        assert_eq!(report.filled_quantity, dec!(0.5));
+        assert_eq!(report.leaves_quantity, dec!(1.5));
+    }
+
+    fn peg(reference: PegReference, offset: Decimal, limit: Option<Decimal>) -> Peg {
+        Peg { reference, offset, limit }
+    }
+
+    #[tokio::test]
+    async fn test_primary_peg_follows_best_bid() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap();
+        let bid = limit(Uuid::new_v4(), Side::Buy, dec!(99), dec!(1));
+        let bid_id = bid.id;
+        order_book.process_order(bid).await.unwrap();
+        order_book.process_order(limit(Uuid::new_v4(), Side::Sell, dec!(101), dec!(1))).await.unwrap();
+
+        let pegged = limit(Uuid::new_v4(), Side::Buy, dec!(0), dec!(1))
+            .with_peg(peg(PegReference::BestBid, dec!(0.5), Some(dec!(100))));
+        let pegged_id = pegged.id;
+        order_book.process_order(pegged).await.unwrap();
+        assert_eq!(order_book.get_order(pegged_id).unwrap().price, dec!(99.5));
+        assert_eq!(order_book.best_bid(), Some(dec!(99.5)));
+
+        // A new unpegged best bid moves the peg, up to its cap.
+        order_book.process_order(limit(Uuid::new_v4(), Side::Buy, dec!(99.8), dec!(1))).await.unwrap();
+        assert_eq!(order_book.get_order(pegged_id).unwrap().price, dec!(100));
+
+        order_book.cancel_order(bid_id).await.unwrap();
+        order_book.mass_cancel(CancelFilter::side(Side::Buy)).await.unwrap();
+        assert!(order_book.get_order(pegged_id).is_none());
+        assert!(matches!(
+            order_book
+                .process_order(limit(Uuid::new_v4(), Side::Buy, dec!(0), dec!(1)).with_peg(peg(PegReference::BestBid, dec!(0), None)))
+                .await,
+            Err(OrderBookError::NoPegReference)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_midpoint_pegs_are_hidden_and_trade_at_mid() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap();
+        order_book.process_order(limit(Uuid::new_v4(), Side::Buy, dec!(98), dec!(5))).await.unwrap();
+        order_book.process_order(limit(Uuid::new_v4(), Side::Sell, dec!(102), dec!(5))).await.unwrap();
+
+        let mid_sell = limit(Uuid::new_v4(), Side::Sell, dec!(0), dec!(2))
+            .with_peg(peg(PegReference::Midpoint, dec!(0), Some(dec!(100.5))));
+        let mid_sell_id = mid_sell.id;
+        order_book.process_order(mid_sell).await.unwrap();
+        assert_eq!(order_book.best_ask(), Some(dec!(102)));
+
+        // Below the sell peg's floor nothing trades at mid.
+        let mid_buy = limit(Uuid::new_v4(), Side::Buy, dec!(0), dec!(1)).with_peg(peg(PegReference::Midpoint, dec!(0), None));
+        assert!(order_book.process_order(mid_buy).await.unwrap().is_empty());
+
+        // Raising the bid lifts the mid to 101 and crosses the two pegs.
+        order_book.process_order(limit(Uuid::new_v4(), Side::Buy, dec!(100), dec!(1))).await.unwrap();
+        assert_eq!(order_book.get_order(mid_sell_id).unwrap().remaining_quantity(), dec!(1));
+
+        // An incoming taker that reaches the mid fills there before the touch.
+        let trades = order_book.process_order(limit(Uuid::new_v4(), Side::Buy, dec!(102), dec!(2))).await.unwrap();
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].price, dec!(101));
+        assert_eq!(trades[0].order_id, mid_sell_id);
+        assert_eq!(trades[1].price, dec!(102));
+    }
+
+    #[tokio::test]
+    async fn test_unfunded_peg_crossing_leaves_caller_order_settled() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let ledger = Arc::new(Ledger::new());
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap().with_ledger(ledger.clone());
+        let (trader, mid_seller) = (Uuid::new_v4(), Uuid::new_v4());
+        ledger.deposit(trader, "USD", dec!(10000)).unwrap();
+        ledger.deposit(trader, "BTC", dec!(10)).unwrap();
+        ledger.deposit(mid_seller, "BTC", dec!(2)).unwrap();
+        order_book.process_order(limit(trader, Side::Buy, dec!(98), dec!(5))).await.unwrap();
+        order_book.process_order(limit(trader, Side::Sell, dec!(102), dec!(5))).await.unwrap();
+
+        let mid_sell = limit(mid_seller, Side::Sell, dec!(0), dec!(2))
+            .with_peg(peg(PegReference::Midpoint, dec!(0), Some(dec!(100.5))));
+        let mid_sell_id = mid_sell.id;
+        order_book.process_order(mid_sell).await.unwrap();
+        // The buyer has no funds, so the pegs can't settle when they cross.
+        let mid_buy = limit(Uuid::new_v4(), Side::Buy, dec!(0), dec!(1)).with_peg(peg(PegReference::Midpoint, dec!(0), None));
+        let mid_buy_id = mid_buy.id;
+        order_book.process_order(mid_buy).await.unwrap();
+
+        // The bid that moves the mid still goes in; the crossing is undone.
+        let bid = limit(trader, Side::Buy, dec!(100), dec!(1));
+        let bid_id = bid.id;
+        order_book.process_order(bid).await.unwrap();
+        assert!(order_book.get_order(bid_id).is_some());
+        assert_eq!(order_book.get_order(mid_sell_id).unwrap().remaining_quantity(), dec!(2));
+        assert_eq!(order_book.get_order(mid_buy_id).unwrap().remaining_quantity(), dec!(1));
+        assert_eq!(ledger.position(mid_seller, "BTC/USD"), Decimal::ZERO);
+        ledger.check_invariants().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reduce_only_never_flips_position() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let (execution_tx, mut execution_rx) = mpsc::unbounded_channel();
+        let ledger = Arc::new(Ledger::new());
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap()
+            .with_ledger(ledger.clone())
+            .with_execution_publisher(execution_tx);
+        let (hedger, other) = (Uuid::new_v4(), Uuid::new_v4());
+        for user_id in [hedger, other] {
+            ledger.deposit(user_id, "USD", dec!(10000)).unwrap();
+            ledger.deposit(user_id, "BTC", dec!(10)).unwrap();
+        }
+        order_book.process_order(limit(other, Side::Sell, dec!(100), dec!(3))).await.unwrap();
+        order_book.process_order(limit(hedger, Side::Buy, dec!(100), dec!(3))).await.unwrap();
+        assert_eq!(ledger.position(hedger, "BTC/USD"), dec!(3));
+
+        let reduce = limit(hedger, Side::Sell, dec!(110), dec!(5)).reduce_only();
+        let reduce_id = reduce.id;
+        order_book.process_order(reduce).await.unwrap();
+        assert_eq!(order_book.get_order(reduce_id).unwrap().quantity, dec!(3));
+        assert!(matches!(
+            order_book.process_order(limit(hedger, Side::Sell, dec!(111), dec!(1)).reduce_only()).await,
+            Err(OrderBookError::ReduceOnlyRejected)
+        ));
+
+        // Selling outside the reduce-only order shrinks it to the new position.
+        order_book.process_order(limit(other, Side::Buy, dec!(100), dec!(2))).await.unwrap();
+        order_book.process_order(limit(hedger, Side::Sell, dec!(100), dec!(2))).await.unwrap();
+        assert_eq!(order_book.get_order(reduce_id).unwrap().remaining_quantity(), dec!(1));
+        assert_eq!(execution_rx.try_recv().unwrap().kind, ExecutionKind::Restated);
+
+        // One sweep fills the plain sell first; the reduce-only one is then
+        // cancelled instead of taking the hedger short.
+        order_book.process_order(limit(hedger, Side::Sell, dec!(105), dec!(1))).await.unwrap();
+        let trades = order_book.process_order(limit(other, Side::Buy, dec!(110), dec!(2))).await.unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(ledger.position(hedger, "BTC/USD"), dec!(0));
+        assert!(order_book.get_order(reduce_id).is_none());
+        assert_eq!(execution_rx.try_recv().unwrap().kind, ExecutionKind::Cancelled);
+
+        assert!(matches!(
+            order_book.close_position(hedger, None).await,
+            Err(OrderBookError::ReduceOnlyRejected)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_reduce_only_amends_and_batches_never_flip_position() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let ledger = Arc::new(Ledger::new());
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap().with_ledger(ledger.clone());
+        let (hedger, other) = (Uuid::new_v4(), Uuid::new_v4());
+        for user_id in [hedger, other] {
+            ledger.deposit(user_id, "USD", dec!(10000)).unwrap();
+            ledger.deposit(user_id, "BTC", dec!(10)).unwrap();
+        }
+        order_book.process_order(limit(other, Side::Sell, dec!(100), dec!(3))).await.unwrap();
+        order_book.process_order(limit(hedger, Side::Buy, dec!(100), dec!(3))).await.unwrap();
+
+        // Sizing a reduce-only order up is capped at the position.
+        let reduce = limit(hedger, Side::Sell, dec!(110), dec!(2)).reduce_only();
+        let reduce_id = reduce.id;
+        order_book.process_order(reduce).await.unwrap();
+        let (amended, _) = order_book.amend_order(reduce_id, None, Some(dec!(5))).await.unwrap();
+        assert_eq!(amended.quantity, dec!(3));
+        let ops = vec![BatchOp::Amend { order_id: reduce_id, price: Some(dec!(109)), quantity: Some(dec!(6)) }];
+        let results = order_book.process_batch(ops, true).await.unwrap();
+        assert!(matches!(&results[0], Ok(BatchOutcome::Amended { order, .. }) if order.quantity == dec!(3)));
+        order_book.cancel_order(reduce_id).await.unwrap();
+
+        // The batch's own sale flattens the hedger before the reduce-only order.
+        order_book.process_order(limit(other, Side::Buy, dec!(100), dec!(3))).await.unwrap();
+        let ops = vec![
+            BatchOp::New(limit(hedger, Side::Sell, dec!(100), dec!(3))),
+            BatchOp::New(limit(hedger, Side::Sell, dec!(110), dec!(1)).reduce_only()),
+        ];
+        assert!(matches!(
+            order_book.process_batch(ops, true).await,
+            Err(OrderBookError::BatchRejected { index: 1, .. })
+        ));
+        assert_eq!(ledger.position(hedger, "BTC/USD"), dec!(3));
+        assert!(order_book.open_orders_for_user(hedger).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_close_position() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let ledger = Arc::new(Ledger::new());
+        let order_book = OrderBook::new("BTC/USD".to_string(), tx).unwrap().with_ledger(ledger.clone());
+        let (trader, other) = (Uuid::new_v4(), Uuid::new_v4());
+        for user_id in [trader, other] {
+            ledger.deposit(user_id, "USD", dec!(10000)).unwrap();
+            ledger.deposit(user_id, "BTC", dec!(10)).unwrap();
+        }
+        order_book.process_order(limit(other, Side::Buy, dec!(100), dec!(2))).await.unwrap();
+        order_book.process_order(limit(trader, Side::Sell, dec!(100), dec!(2))).await.unwrap();
+        assert_eq!(ledger.position(trader, "BTC/USD"), dec!(-2));
+
+        order_book.process_order(limit(other, Side::Sell, dec!(101), dec!(5))).await.unwrap();
+        let trades = order_book.close_position(trader, None).await.unwrap();
+        assert_eq!(trades[0].quantity, dec!(2));
+        assert_eq!(ledger.position(trader, "BTC/USD"), dec!(0));
+    }
 }
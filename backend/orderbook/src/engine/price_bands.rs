@@ -0,0 +1,111 @@
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// Dynamic price collars around a reference price.
+///
+/// Bands are fractions of the reference price, e.g. `0.05` for 5%.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PriceBandConfig {
+    /// Limit orders priced further than this from the reference are rejected.
+    pub limit_band: Decimal,
+    /// Market orders stop walking the book beyond this distance.
+    pub market_band: Decimal,
+    /// A move larger than this within `volatility_window` halts the book
+    /// into an auction.
+    pub volatility_threshold: Decimal,
+    pub volatility_window: Duration,
+}
+
+impl Default for PriceBandConfig {
+    fn default() -> Self {
+        Self {
+            limit_band: dec!(0.10),
+            market_band: dec!(0.05),
+            volatility_threshold: dec!(0.10),
+            volatility_window: Duration::minutes(5),
+        }
+    }
+}
+
+impl PriceBandConfig {
+    pub fn limit_bounds(&self, reference: Decimal) -> (Decimal, Decimal) {
+        bounds(reference, self.limit_band)
+    }
+
+    pub fn market_bounds(&self, reference: Decimal) -> (Decimal, Decimal) {
+        bounds(reference, self.market_band)
+    }
+}
+
+fn bounds(reference: Decimal, band: Decimal) -> (Decimal, Decimal) {
+    (reference * (Decimal::ONE - band), reference * (Decimal::ONE + band))
+}
+
+/// Tracks recent trade prices and trips when the market moves too far
+/// within the configured window.
+#[derive(Debug, Default)]
+pub struct VolatilityMonitor {
+    trades: VecDeque<(DateTime<Utc>, Decimal)>,
+}
+
+impl VolatilityMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a trade and returns true if its price moved more than the
+    /// threshold away from any trade still inside the window.
+    pub fn record(&mut self, config: &PriceBandConfig, at: DateTime<Utc>, price: Decimal) -> bool {
+        let cutoff = at - config.volatility_window;
+        while matches!(self.trades.front(), Some((time, _)) if *time < cutoff) {
+            self.trades.pop_front();
+        }
+
+        let tripped = self.trades.iter().any(|(_, previous)| {
+            !previous.is_zero() && ((price - *previous) / *previous).abs() > config.volatility_threshold
+        });
+
+        self.trades.push_back((at, price));
+        tripped
+    }
+
+    pub fn reset(&mut self) {
+        self.trades.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bounds() {
+        let config = PriceBandConfig::default();
+        assert_eq!(config.limit_bounds(dec!(100)), (dec!(90), dec!(110)));
+        assert_eq!(config.market_bounds(dec!(100)), (dec!(95), dec!(105)));
+    }
+
+    #[test]
+    fn test_volatility_monitor_trips_inside_window() {
+        let config = PriceBandConfig::default();
+        let mut monitor = VolatilityMonitor::new();
+        let start = Utc::now();
+
+        assert!(!monitor.record(&config, start, dec!(100)));
+        assert!(!monitor.record(&config, start + Duration::minutes(1), dec!(109)));
+        assert!(monitor.record(&config, start + Duration::minutes(2), dec!(111)));
+    }
+
+    #[test]
+    fn test_volatility_monitor_forgets_old_trades() {
+        let config = PriceBandConfig::default();
+        let mut monitor = VolatilityMonitor::new();
+        let start = Utc::now();
+
+        monitor.record(&config, start, dec!(100));
+        assert!(!monitor.record(&config, start + Duration::minutes(10), dec!(120)));
+    }
+}
@@ -0,0 +1,482 @@
+use dashmap::{mapref::entry::Entry, DashMap};
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::engine::batch::{BatchOp, BatchResult};
+use crate::engine::fees::FeeEngine;
+use crate::engine::orderbook::{CancelFilter, OrderBook};
+use crate::ledger::Ledger;
+use crate::risk::RiskEngine;
+use crate::models::{
+    execution_report::ExecutionReport,
+    instrument::{Instrument, InstrumentStatus},
+    order::Order,
+    trade::Trade,
+};
+use crate::utils::clock::Clock;
+use crate::utils::error::{OrderBookError, OrderBookResult};
+
+/// Outcome of pulling a user's orders on every book.
+#[derive(Debug, Default)]
+pub struct UserCancelResult {
+    pub cancelled: Vec<Order>,
+    /// Books that refused the cancel, with the reason.
+    pub failed: Vec<(String, OrderBookError)>,
+}
+
+/// Runtime catalogue of listed instruments and their order books.
+///
+/// Pairs are listed, reconfigured and delisted while the engine is running;
+/// every book created here shares the registry's trade and execution
+/// channels, ledger, fee engine, risk engine and clock.
+pub struct InstrumentRegistry {
+    books: DashMap<String, Arc<OrderBook>>,
+    /// Symbol each (user, client order id) is reserved on.
+    client_ids: DashMap<(Uuid, String), String>,
+    trade_tx: UnboundedSender<Trade>,
+    ledger: Option<Arc<Ledger>>,
+    fee_engine: Option<Arc<FeeEngine>>,
+    risk_engine: Option<Arc<RiskEngine>>,
+    execution_tx: Option<UnboundedSender<ExecutionReport>>,
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl InstrumentRegistry {
+    pub fn new(trade_tx: UnboundedSender<Trade>) -> Self {
+        Self {
+            books: DashMap::new(),
+            client_ids: DashMap::new(),
+            trade_tx,
+            ledger: None,
+            fee_engine: None,
+            risk_engine: None,
+            execution_tx: None,
+            clock: None,
+        }
+    }
+
+    pub fn with_ledger(mut self, ledger: Arc<Ledger>) -> Self {
+        self.ledger = Some(ledger);
+        self
+    }
+
+    pub fn with_fee_engine(mut self, fee_engine: Arc<FeeEngine>) -> Self {
+        self.fee_engine = Some(fee_engine);
+        self
+    }
+
+    pub fn with_risk_engine(mut self, risk_engine: Arc<RiskEngine>) -> Self {
+        self.risk_engine = Some(risk_engine);
+        self
+    }
+
+    pub fn with_execution_publisher(mut self, execution_tx: UnboundedSender<ExecutionReport>) -> Self {
+        self.execution_tx = Some(execution_tx);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Lists a new pair and opens an empty book for it.
+    pub fn list(&self, instrument: Instrument) -> OrderBookResult<Arc<OrderBook>> {
+        instrument.validate()?;
+
+        match self.books.entry(instrument.symbol.clone()) {
+            Entry::Occupied(_) => {
+                Err(OrderBookError::InstrumentAlreadyListed(instrument.symbol))
+            }
+            Entry::Vacant(entry) => {
+                let mut book = OrderBook::with_instrument(instrument, self.trade_tx.clone());
+                if let Some(ledger) = &self.ledger {
+                    book = book.with_ledger(ledger.clone());
+                }
+                if let Some(fee_engine) = &self.fee_engine {
+                    book = book.with_fee_engine(fee_engine.clone());
+                }
+                if let Some(risk_engine) = &self.risk_engine {
+                    book = book.with_risk_engine(risk_engine.clone());
+                }
+                if let Some(execution_tx) = &self.execution_tx {
+                    book = book.with_execution_publisher(execution_tx.clone());
+                }
+                if let Some(clock) = &self.clock {
+                    book = book.with_clock(clock.clone());
+                }
+                let book = Arc::new(book);
+                entry.insert(book.clone());
+                Ok(book)
+            }
+        }
+    }
+
+    pub fn update(&self, instrument: Instrument) -> OrderBookResult<()> {
+        self.book(&instrument.symbol)?.update_instrument(instrument)
+    }
+
+    pub fn set_status(&self, symbol: &str, status: InstrumentStatus) -> OrderBookResult<()> {
+        let book = self.book(symbol)?;
+        let mut instrument = book.instrument();
+        instrument.status = status;
+        book.update_instrument(instrument)
+    }
+
+    /// Marks the instrument delisted and removes its book from the registry.
+    pub fn delist(&self, symbol: &str) -> OrderBookResult<Arc<OrderBook>> {
+        self.set_status(symbol, InstrumentStatus::Delisted)?;
+        self.books
+            .remove(symbol)
+            .map(|(_, book)| book)
+            .ok_or_else(|| OrderBookError::UnknownInstrument(symbol.to_string()))
+    }
+
+    pub fn book(&self, symbol: &str) -> OrderBookResult<Arc<OrderBook>> {
+        self.books
+            .get(symbol)
+            .map(|book| book.clone())
+            .ok_or_else(|| OrderBookError::UnknownInstrument(symbol.to_string()))
+    }
+
+    pub fn books(&self) -> Vec<Arc<OrderBook>> {
+        self.books.iter().map(|book| book.clone()).collect()
+    }
+
+    pub fn instrument(&self, symbol: &str) -> OrderBookResult<Instrument> {
+        Ok(self.book(symbol)?.instrument())
+    }
+
+    pub fn instruments(&self) -> Vec<Instrument> {
+        self.books.iter().map(|book| book.instrument()).collect()
+    }
+
+    /// Pulls every resting order of the user across all symbols.
+    ///
+    /// Books that refuse cancels (halted) are skipped and reported in
+    /// `failed`, alongside the orders cancelled on the other books.
+    pub async fn cancel_all_for_user(&self, user_id: Uuid) -> UserCancelResult {
+        let mut result = UserCancelResult::default();
+        for book in self.books() {
+            match book.mass_cancel(CancelFilter::user(user_id)).await {
+                Ok(orders) => result.cancelled.extend(orders),
+                Err(err) => {
+                    log::warn!("mass cancel for {} skipped {}: {}", user_id, book.symbol(), err);
+                    result.failed.push((book.symbol().to_string(), err));
+                }
+            }
+        }
+        result
+    }
+
+    pub async fn cancel_for_user_symbol(&self, user_id: Uuid, symbol: &str) -> OrderBookResult<Vec<Order>> {
+        self.book(symbol)?.mass_cancel(CancelFilter::user(user_id)).await
+    }
+
+    /// Expires due good-till-date orders on every book.
+    pub fn expire_orders(&self) -> Vec<Order> {
+        self.books().iter().flat_map(|book| book.expire_orders()).collect()
+    }
+
+    /// Runs `expire_orders` on a fixed interval until the task is aborted.
+    pub fn spawn_expiry_timer(self: Arc<Self>, interval: std::time::Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                self.expire_orders();
+            }
+        })
+    }
+
+    /// Routes an order to the book for its symbol.
+    ///
+    /// Client order ids are unique per user across all symbols; a retry on
+    /// the same symbol is replayed by the book itself.
+    pub async fn submit(&self, order: Order) -> OrderBookResult<Vec<Trade>> {
+        let book = self.book(&order.symbol)?;
+        let reserved = self.reserve_client_ids(book.symbol(), order.user_id, order.client_order_id.iter())?;
+        let result = book.process_order(order).await;
+        self.release_unused(&book, reserved);
+        result
+    }
+
+    /// Claims each new client order id for `symbol` before the orders are
+    /// routed, so concurrent submits on two symbols can't both take an id.
+    /// Returns the ids reserved by this call; none are held on error.
+    fn reserve_client_ids<'a>(
+        &self,
+        symbol: &str,
+        user_id: Uuid,
+        client_order_ids: impl Iterator<Item = &'a String>,
+    ) -> OrderBookResult<Vec<(Uuid, String)>> {
+        let mut reserved = Vec::new();
+        for client_order_id in client_order_ids {
+            match self.client_ids.entry((user_id, client_order_id.clone())) {
+                Entry::Occupied(entry) if entry.get() == symbol => {}
+                Entry::Occupied(_) => {
+                    for key in reserved {
+                        self.client_ids.remove(&key);
+                    }
+                    return Err(OrderBookError::DuplicateClientOrderId(client_order_id.clone()));
+                }
+                Entry::Vacant(entry) => {
+                    reserved.push(entry.key().clone());
+                    entry.insert(symbol.to_string());
+                }
+            }
+        }
+        Ok(reserved)
+    }
+
+    /// Frees reserved ids the book did not accept an order under.
+    fn release_unused(&self, book: &OrderBook, reserved: Vec<(Uuid, String)>) {
+        for (user_id, client_order_id) in reserved {
+            if book.order_id_for_client_id(user_id, &client_order_id).is_none() {
+                self.client_ids.remove(&(user_id, client_order_id));
+            }
+        }
+    }
+
+    /// Finds the book that accepted the client order id.
+    pub fn book_for_client_id(&self, user_id: Uuid, client_order_id: &str) -> Option<Arc<OrderBook>> {
+        let symbol = self.client_ids.get(&(user_id, client_order_id.to_string()))?.clone();
+        self.book(&symbol)
+            .ok()
+            .filter(|book| book.order_id_for_client_id(user_id, client_order_id).is_some())
+    }
+
+    /// The resting order accepted under the client order id, if any.
+    pub fn order_by_client_id(&self, user_id: Uuid, client_order_id: &str) -> Option<Order> {
+        self.book_for_client_id(user_id, client_order_id)?
+            .get_order_by_client_id(user_id, client_order_id)
+    }
+
+    pub async fn cancel_by_client_id(&self, user_id: Uuid, client_order_id: &str) -> OrderBookResult<Option<Order>> {
+        let book = self
+            .book_for_client_id(user_id, client_order_id)
+            .ok_or(OrderBookError::OrderNotFound)?;
+        book.cancel_by_client_id(user_id, client_order_id).await
+    }
+
+    /// Routes a batch to the book for `symbol`; see `OrderBook::process_batch`.
+    /// Client order ids of new orders are reserved as in `submit`.
+    pub async fn submit_batch(&self, symbol: &str, ops: Vec<BatchOp>, all_or_nothing: bool) -> OrderBookResult<BatchResult> {
+        let book = self.book(symbol)?;
+        let mut reserved = Vec::new();
+        for op in &ops {
+            if let BatchOp::New(order) = op {
+                match self.reserve_client_ids(book.symbol(), order.user_id, order.client_order_id.iter()) {
+                    Ok(ids) => reserved.extend(ids),
+                    Err(err) => {
+                        self.release_unused(&book, reserved);
+                        return Err(err);
+                    }
+                }
+            }
+        }
+        let result = book.process_batch(ops, all_or_nothing).await;
+        self.release_unused(&book, reserved);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::market_state::MarketState;
+    use crate::models::order::{OrderType, Side};
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+    use tokio::sync::mpsc;
+
+    fn order(symbol: &str) -> Order {
+        Order::new(Uuid::new_v4(), symbol.to_string(), Side::Buy, OrderType::Limit, dec!(100), dec!(1))
+    }
+
+    #[tokio::test]
+    async fn test_list_at_runtime() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let registry = InstrumentRegistry::new(tx);
+
+        assert!(matches!(
+            registry.submit(order("SOL/USD")).await,
+            Err(OrderBookError::UnknownInstrument(_))
+        ));
+
+        registry.list(Instrument::new("SOL", "USD")).unwrap();
+        assert!(registry.submit(order("SOL/USD")).await.is_ok());
+        assert!(matches!(
+            registry.list(Instrument::new("SOL", "USD")),
+            Err(OrderBookError::InstrumentAlreadyListed(_))
+        ));
+        assert_eq!(registry.instruments().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_instrument_rejected() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let registry = InstrumentRegistry::new(tx);
+        let instrument = Instrument {
+            tick_size: Decimal::ZERO,
+            ..Instrument::new("SOL", "USD")
+        };
+
+        assert!(matches!(
+            registry.list(instrument),
+            Err(OrderBookError::InvalidInstrument(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_status_and_delist() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let registry = InstrumentRegistry::new(tx);
+        registry.list(Instrument::new("SOL", "USD")).unwrap();
+
+        registry.set_status("SOL/USD", InstrumentStatus::Suspended).unwrap();
+        assert!(matches!(
+            registry.submit(order("SOL/USD")).await,
+            Err(OrderBookError::InstrumentNotTrading(_))
+        ));
+
+        let book = registry.delist("SOL/USD").unwrap();
+        assert_eq!(book.instrument().status, InstrumentStatus::Delisted);
+        assert!(registry.book("SOL/USD").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_all_for_user_across_symbols() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let registry = InstrumentRegistry::new(tx);
+        registry.list(Instrument::new("SOL", "USD")).unwrap();
+        registry.list(Instrument::new("ETH", "USD")).unwrap();
+        let user_id = Uuid::new_v4();
+        for symbol in ["SOL/USD", "ETH/USD"] {
+            let order = Order::new(user_id, symbol.to_string(), Side::Buy, OrderType::Limit, dec!(100), dec!(1));
+            registry.submit(order).await.unwrap();
+        }
+        registry.submit(order("SOL/USD")).await.unwrap();
+
+        let cancelled = registry.cancel_for_user_symbol(user_id, "ETH/USD").await.unwrap();
+        assert_eq!(cancelled.len(), 1);
+
+        let result = registry.cancel_all_for_user(user_id).await;
+        assert!(result.failed.is_empty());
+        assert_eq!(result.cancelled.len(), 1);
+        assert_eq!(result.cancelled[0].symbol, "SOL/USD");
+        assert_eq!(registry.book("SOL/USD").unwrap().open_orders_for_user(user_id).len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_all_for_user_reports_halted_books() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let registry = InstrumentRegistry::new(tx);
+        registry.list(Instrument::new("SOL", "USD")).unwrap();
+        registry.list(Instrument::new("ETH", "USD")).unwrap();
+        let user_id = Uuid::new_v4();
+        for symbol in ["SOL/USD", "ETH/USD"] {
+            let order = Order::new(user_id, symbol.to_string(), Side::Buy, OrderType::Limit, dec!(100), dec!(1));
+            registry.submit(order).await.unwrap();
+        }
+        registry.book("ETH/USD").unwrap().set_market_state(MarketState::Halted);
+
+        let result = registry.cancel_all_for_user(user_id).await;
+        assert_eq!(result.cancelled.len(), 1);
+        assert_eq!(result.cancelled[0].symbol, "SOL/USD");
+        assert_eq!(result.failed.len(), 1);
+        assert_eq!(result.failed[0].0, "ETH/USD");
+        assert!(matches!(result.failed[0].1, OrderBookError::MarketStateRejected(MarketState::Halted)));
+        assert_eq!(registry.book("ETH/USD").unwrap().open_orders_for_user(user_id).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_client_order_id_unique_across_symbols() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let registry = InstrumentRegistry::new(tx);
+        registry.list(Instrument::new("SOL", "USD")).unwrap();
+        registry.list(Instrument::new("ETH", "USD")).unwrap();
+        let sol = order("SOL/USD").with_client_order_id("quote-1");
+        let user_id = sol.user_id;
+        registry.submit(sol).await.unwrap();
+
+        let eth = Order {
+            user_id,
+            ..order("ETH/USD").with_client_order_id("quote-1")
+        };
+        assert!(matches!(
+            registry.submit(eth).await,
+            Err(OrderBookError::DuplicateClientOrderId(_))
+        ));
+
+        assert_eq!(registry.order_by_client_id(user_id, "quote-1").unwrap().symbol, "SOL/USD");
+        assert!(registry.cancel_by_client_id(user_id, "quote-1").await.unwrap().is_some());
+        assert!(registry.order_by_client_id(user_id, "quote-1").is_none());
+
+        // Batches reserve their ids too.
+        let ops = vec![BatchOp::New(Order {
+            user_id,
+            ..order("ETH/USD").with_client_order_id("quote-1")
+        })];
+        assert!(matches!(
+            registry.submit_batch("ETH/USD", ops, false).await,
+            Err(OrderBookError::DuplicateClientOrderId(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rejected_order_releases_client_order_id() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let registry = InstrumentRegistry::new(tx);
+        registry.list(Instrument::new("SOL", "USD")).unwrap();
+        registry.list(Instrument::new("ETH", "USD")).unwrap();
+        registry.book("SOL/USD").unwrap().set_market_state(MarketState::Halted);
+        let sol = order("SOL/USD").with_client_order_id("quote-1");
+        let user_id = sol.user_id;
+        assert!(registry.submit(sol).await.is_err());
+
+        let eth = Order {
+            user_id,
+            ..order("ETH/USD").with_client_order_id("quote-1")
+        };
+        registry.submit(eth).await.unwrap();
+        assert_eq!(registry.book_for_client_id(user_id, "quote-1").unwrap().symbol(), "ETH/USD");
+    }
+
+    #[test]
+    fn test_concurrent_submits_reserve_client_order_id_once() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let registry = Arc::new(InstrumentRegistry::new(tx));
+        registry.list(Instrument::new("SOL", "USD")).unwrap();
+        registry.list(Instrument::new("ETH", "USD")).unwrap();
+
+        for round in 0..50 {
+            let user_id = Uuid::new_v4();
+            let client_order_id = format!("quote-{}", round);
+            let start = Arc::new(std::sync::Barrier::new(2));
+            let submits: Vec<_> = ["SOL/USD", "ETH/USD"]
+                .into_iter()
+                .map(|symbol| {
+                    let (registry, start) = (registry.clone(), start.clone());
+                    let order = Order {
+                        user_id,
+                        ..order(symbol).with_client_order_id(&client_order_id)
+                    };
+                    std::thread::spawn(move || {
+                        start.wait();
+                        futures::executor::block_on(registry.submit(order))
+                    })
+                })
+                .collect();
+
+            let accepted = submits
+                .into_iter()
+                .map(|submit| submit.join().unwrap())
+                .filter(Result::is_ok)
+                .count();
+            assert_eq!(accepted, 1);
+        }
+    }
+}
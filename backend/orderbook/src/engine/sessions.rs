@@ -0,0 +1,258 @@
+use chrono::{DateTime, Duration, Utc};
+use dashmap::DashMap;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::engine::registry::InstrumentRegistry;
+use crate::models::{order::Order, trade::Trade};
+use crate::utils::clock::{Clock, SystemClock};
+use crate::utils::error::{OrderBookError, OrderBookResult};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    /// Orders are pulled when no heartbeat arrives within this interval.
+    pub timeout: Duration,
+    pub last_heartbeat: DateTime<Utc>,
+    /// (symbol, order id) of orders submitted through the session that
+    /// were resting when last checked; `sweep` drops the ones since gone.
+    pub orders: HashSet<(String, Uuid)>,
+}
+
+impl Session {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        now - self.last_heartbeat > self.timeout
+    }
+}
+
+/// Cancel-on-disconnect for trading sessions.
+///
+/// Orders submitted through a session are tracked against it; when the
+/// session disconnects or its heartbeats stop for longer than its timeout
+/// (a dead man's switch), the orders still resting are cancelled.
+pub struct SessionManager {
+    registry: Arc<InstrumentRegistry>,
+    sessions: DashMap<Uuid, Session>,
+    clock: Arc<dyn Clock>,
+}
+
+impl SessionManager {
+    pub fn new(registry: Arc<InstrumentRegistry>) -> Self {
+        Self::with_clock(registry, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(registry: Arc<InstrumentRegistry>, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            registry,
+            sessions: DashMap::new(),
+            clock,
+        }
+    }
+
+    pub fn open(&self, user_id: Uuid, timeout: Duration) -> Uuid {
+        let session = Session {
+            id: Uuid::new_v4(),
+            user_id,
+            timeout,
+            last_heartbeat: self.clock.now(),
+            orders: HashSet::new(),
+        };
+        let session_id = session.id;
+        self.sessions.insert(session_id, session);
+        session_id
+    }
+
+    pub fn heartbeat(&self, session_id: Uuid) -> OrderBookResult<()> {
+        let mut session = self
+            .sessions
+            .get_mut(&session_id)
+            .ok_or(OrderBookError::UnknownSession(session_id))?;
+        session.last_heartbeat = self.clock.now();
+        Ok(())
+    }
+
+    pub fn session(&self, session_id: Uuid) -> Option<Session> {
+        self.sessions.get(&session_id).map(|session| session.clone())
+    }
+
+    /// Submits an order on behalf of the session and tracks it for
+    /// cancel-on-disconnect.
+    pub async fn submit(&self, session_id: Uuid, order: Order) -> OrderBookResult<Vec<Trade>> {
+        let user_id = self
+            .sessions
+            .get(&session_id)
+            .map(|session| session.user_id)
+            .ok_or(OrderBookError::UnknownSession(session_id))?;
+        if order.user_id != user_id {
+            return Err(OrderBookError::UnknownSession(session_id));
+        }
+
+        let (symbol, order_id) = (order.symbol.clone(), order.id);
+        let trades = self.registry.submit(order).await?;
+        if !self.is_resting(&symbol, order_id) {
+            return Ok(trades);
+        }
+        if let Some(mut session) = self.sessions.get_mut(&session_id) {
+            session.orders.insert((symbol, order_id));
+        }
+        Ok(trades)
+    }
+
+    /// Closes the session and cancels its resting orders.
+    pub async fn disconnect(&self, session_id: Uuid) -> OrderBookResult<Vec<Order>> {
+        let session = self
+            .session(session_id)
+            .ok_or(OrderBookError::UnknownSession(session_id))?;
+        let cancelled = self.cancel_session_orders(&session).await?;
+        self.sessions.remove(&session_id);
+        Ok(cancelled)
+    }
+
+    /// Cancels the orders of every session whose heartbeat has timed out.
+    /// Sessions whose books refused the cancel stay registered so the next
+    /// sweep retries them. Live sessions stop tracking orders that have
+    /// since filled or been cancelled.
+    pub async fn sweep(&self) -> Vec<Order> {
+        let now = self.clock.now();
+        let mut expired = Vec::new();
+        for mut session in self.sessions.iter_mut() {
+            if session.is_expired(now) {
+                expired.push(session.clone());
+            } else {
+                session.orders.retain(|(symbol, order_id)| self.is_resting(symbol, *order_id));
+            }
+        }
+
+        let mut cancelled = Vec::new();
+        for session in expired {
+            log::warn!("session {} of user {} missed heartbeats, cancelling orders", session.id, session.user_id);
+            match self.cancel_session_orders(&session).await {
+                Ok(orders) => {
+                    cancelled.extend(orders);
+                    self.sessions.remove(&session.id);
+                }
+                Err(err) => log::warn!("cancel-on-disconnect for session {} deferred: {}", session.id, err),
+            }
+        }
+        cancelled
+    }
+
+    /// Runs `sweep` on a fixed interval until the task is aborted.
+    pub fn spawn_watchdog(self: Arc<Self>, interval: std::time::Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                self.sweep().await;
+            }
+        })
+    }
+
+    fn is_resting(&self, symbol: &str, order_id: Uuid) -> bool {
+        self.registry
+            .book(symbol)
+            .is_ok_and(|book| book.get_order(order_id).is_some())
+    }
+
+    async fn cancel_session_orders(&self, session: &Session) -> OrderBookResult<Vec<Order>> {
+        let mut cancelled = Vec::new();
+        for (symbol, order_id) in &session.orders {
+            // Delisted books have nothing left to cancel.
+            let Ok(book) = self.registry.book(symbol) else {
+                continue;
+            };
+            if let Some(order) = book.cancel_order(*order_id).await? {
+                cancelled.push(order);
+            }
+        }
+        Ok(cancelled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::instrument::Instrument;
+    use crate::models::order::{OrderType, Side};
+    use crate::utils::clock::ManualClock;
+    use rust_decimal_macros::dec;
+    use tokio::sync::mpsc;
+
+    fn setup() -> (Arc<InstrumentRegistry>, Arc<ManualClock>, SessionManager) {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let registry = Arc::new(InstrumentRegistry::new(tx));
+        registry.list(Instrument::new("BTC", "USD")).unwrap();
+        let clock = Arc::new(ManualClock::new(Utc::now()));
+        let manager = SessionManager::with_clock(registry.clone(), clock.clone());
+        (registry, clock, manager)
+    }
+
+    fn quote(user_id: Uuid, price: rust_decimal::Decimal) -> Order {
+        Order::new(user_id, "BTC/USD".to_string(), Side::Buy, OrderType::Limit, price, dec!(1))
+    }
+
+    #[tokio::test]
+    async fn test_missed_heartbeats_cancel_orders() {
+        let (registry, clock, manager) = setup();
+        let user_id = Uuid::new_v4();
+        let session_id = manager.open(user_id, Duration::seconds(10));
+        manager.submit(session_id, quote(user_id, dec!(99))).await.unwrap();
+        manager.submit(session_id, quote(user_id, dec!(98))).await.unwrap();
+
+        clock.advance(Duration::seconds(8));
+        manager.heartbeat(session_id).unwrap();
+        clock.advance(Duration::seconds(8));
+        assert!(manager.sweep().await.is_empty());
+
+        clock.advance(Duration::seconds(3));
+        let cancelled = manager.sweep().await;
+        assert_eq!(cancelled.len(), 2);
+        assert!(registry.book("BTC/USD").unwrap().open_orders_for_user(user_id).is_empty());
+        assert!(manager.session(session_id).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_sweep_forgets_orders_no_longer_resting() {
+        let (registry, _clock, manager) = setup();
+        let user_id = Uuid::new_v4();
+        let session_id = manager.open(user_id, Duration::seconds(10));
+        let bid = quote(user_id, dec!(99));
+        let bid_id = bid.id;
+        manager.submit(session_id, bid).await.unwrap();
+        manager.submit(session_id, quote(user_id, dec!(98))).await.unwrap();
+        registry
+            .submit(Order::new(Uuid::new_v4(), "BTC/USD".to_string(), Side::Sell, OrderType::Limit, dec!(99), dec!(1)))
+            .await
+            .unwrap();
+
+        // Filled on arrival, so never tracked.
+        registry
+            .submit(Order::new(Uuid::new_v4(), "BTC/USD".to_string(), Side::Sell, OrderType::Limit, dec!(101), dec!(1)))
+            .await
+            .unwrap();
+        manager.submit(session_id, quote(user_id, dec!(101))).await.unwrap();
+        assert_eq!(manager.session(session_id).unwrap().orders.len(), 2);
+
+        assert!(manager.sweep().await.is_empty());
+        let orders = manager.session(session_id).unwrap().orders;
+        assert_eq!(orders.len(), 1);
+        assert!(!orders.contains(&("BTC/USD".to_string(), bid_id)));
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_only_cancels_session_orders() {
+        let (registry, _clock, manager) = setup();
+        let user_id = Uuid::new_v4();
+        let session_id = manager.open(user_id, Duration::seconds(10));
+        manager.submit(session_id, quote(user_id, dec!(99))).await.unwrap();
+        registry.submit(quote(user_id, dec!(97))).await.unwrap();
+
+        let cancelled = manager.disconnect(session_id).await.unwrap();
+        assert_eq!(cancelled.len(), 1);
+        assert_eq!(registry.book("BTC/USD").unwrap().open_orders_for_user(user_id).len(), 1);
+        assert!(matches!(manager.heartbeat(session_id), Err(OrderBookError::UnknownSession(_))));
+    }
+}
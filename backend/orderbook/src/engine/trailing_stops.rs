@@ -0,0 +1,271 @@
+use dashmap::DashMap;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::engine::registry::InstrumentRegistry;
+use crate::models::{
+    order::{Order, OrderType, Side},
+    trade::Trade,
+};
+use crate::utils::error::{OrderBookError, OrderBookResult};
+
+/// Distance the trigger keeps from the best price seen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TrailingOffset {
+    Absolute(Decimal),
+    /// Fraction of the watermark, e.g. `0.05` for 5%.
+    Percent(Decimal),
+}
+
+impl TrailingOffset {
+    fn distance(&self, watermark: Decimal) -> Decimal {
+        match self {
+            TrailingOffset::Absolute(amount) => *amount,
+            TrailingOffset::Percent(fraction) => watermark * fraction,
+        }
+    }
+}
+
+/// A stop whose trigger trails the market.
+///
+/// A sell stop tracks the highest trade price since submission (the
+/// high-water mark) and triggers when the price falls `offset` below it; a
+/// buy stop mirrors this from the lowest price. The watermark is part of
+/// the serialized state so a restored stop keeps its trigger.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrailingStop {
+    /// Sent when triggered; a limit order is priced at the trigger less
+    /// `limit_offset` in the direction of the stop.
+    pub order: Order,
+    pub offset: TrailingOffset,
+    pub limit_offset: Decimal,
+    pub watermark: Option<Decimal>,
+}
+
+impl TrailingStop {
+    pub fn trigger_price(&self) -> Option<Decimal> {
+        let watermark = self.watermark?;
+        let distance = self.offset.distance(watermark);
+        Some(match self.order.side {
+            Side::Sell => watermark - distance,
+            Side::Buy => watermark + distance,
+        })
+    }
+
+    /// Moves the watermark with a new trade price and returns whether the
+    /// stop triggered.
+    pub fn update(&mut self, price: Decimal) -> bool {
+        let triggered = match (self.trigger_price(), self.order.side) {
+            (Some(trigger), Side::Sell) => price <= trigger,
+            (Some(trigger), Side::Buy) => price >= trigger,
+            (None, _) => false,
+        };
+        if !triggered {
+            self.watermark = Some(match (self.watermark, self.order.side) {
+                (Some(watermark), Side::Sell) => watermark.max(price),
+                (Some(watermark), Side::Buy) => watermark.min(price),
+                (None, _) => price,
+            });
+        }
+        triggered
+    }
+
+    fn triggered_order(&self, tick_size: Decimal) -> Order {
+        let mut order = self.order.clone();
+        if order.order_type == OrderType::Limit {
+            let trigger = self.trigger_price().unwrap_or_default();
+            // Round away from the market so the price stays on the tick grid.
+            order.price = match order.side {
+                Side::Sell => ((trigger - self.limit_offset) / tick_size).floor() * tick_size,
+                Side::Buy => ((trigger + self.limit_offset) / tick_size).ceil() * tick_size,
+            };
+        }
+        order
+    }
+}
+
+/// Holds trailing stops off-book and submits them when the trade feed
+/// reaches their trigger.
+pub struct TrailingStopManager {
+    registry: Arc<InstrumentRegistry>,
+    stops: DashMap<Uuid, TrailingStop>,
+}
+
+impl TrailingStopManager {
+    pub fn new(registry: Arc<InstrumentRegistry>) -> Self {
+        Self {
+            registry,
+            stops: DashMap::new(),
+        }
+    }
+
+    /// Registers a stop for `order`, starting from the book's last trade.
+    pub fn submit(&self, order: Order, offset: TrailingOffset, limit_offset: Decimal) -> OrderBookResult<Uuid> {
+        let book = self.registry.book(&order.symbol)?;
+        book.instrument().validate_order(&order)?;
+        let order_id = order.id;
+        self.stops.insert(
+            order_id,
+            TrailingStop {
+                order,
+                offset,
+                limit_offset,
+                watermark: book.last_trade_price(),
+            },
+        );
+        Ok(order_id)
+    }
+
+    pub fn cancel(&self, order_id: Uuid) -> OrderBookResult<TrailingStop> {
+        self.stops
+            .remove(&order_id)
+            .map(|(_, stop)| stop)
+            .ok_or(OrderBookError::OrderNotFound)
+    }
+
+    pub fn stop(&self, order_id: Uuid) -> Option<TrailingStop> {
+        self.stops.get(&order_id).map(|stop| stop.clone())
+    }
+
+    /// Trails every stop on the trade's symbol and submits those that
+    /// triggered, returning their order ids.
+    pub async fn on_trade(&self, trade: &Trade) -> OrderBookResult<Vec<Uuid>> {
+        let mut triggered = Vec::new();
+        for mut stop in self.stops.iter_mut() {
+            if stop.order.symbol == trade.symbol && stop.update(trade.price) {
+                triggered.push(stop.order.id);
+            }
+        }
+
+        let mut first_error = None;
+        for order_id in &triggered {
+            let Some((_, stop)) = self.stops.remove(order_id) else {
+                continue;
+            };
+            log::info!("trailing stop {} triggered at {} (watermark {:?})", order_id, trade.price, stop.watermark);
+            let result = match self.registry.instrument(&stop.order.symbol) {
+                Ok(instrument) => self.registry.submit(stop.triggered_order(instrument.tick_size)).await.map(|_| ()),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result {
+                log::warn!("trailing stop {} could not be submitted: {}", order_id, err);
+                first_error.get_or_insert(err);
+            }
+        }
+        match first_error {
+            Some(err) => Err(err),
+            None => Ok(triggered),
+        }
+    }
+
+    /// Feeds every trade from `trade_rx` to `on_trade` until the channel closes.
+    pub fn spawn_listener(self: Arc<Self>, mut trade_rx: UnboundedReceiver<Trade>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            while let Some(trade) = trade_rx.recv().await {
+                if let Err(err) = self.on_trade(&trade).await {
+                    log::warn!("trailing stops failed to handle trade {}: {}", trade.id, err);
+                }
+            }
+        })
+    }
+
+    /// Working stops with their watermarks, for persistence.
+    pub fn snapshot(&self) -> Vec<TrailingStop> {
+        self.stops.iter().map(|stop| stop.clone()).collect()
+    }
+
+    /// Reloads stops from a snapshot, replacing any with the same id.
+    pub fn restore(&self, stops: Vec<TrailingStop>) {
+        for stop in stops {
+            self.stops.insert(stop.order.id, stop);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::instrument::Instrument;
+    use rust_decimal_macros::dec;
+    use tokio::sync::mpsc;
+
+    fn trailing_sell(offset: TrailingOffset) -> TrailingStop {
+        let order = Order::new(Uuid::new_v4(), "BTC/USD".to_string(), Side::Sell, OrderType::Market, Decimal::ZERO, dec!(1));
+        TrailingStop {
+            order,
+            offset,
+            limit_offset: Decimal::ZERO,
+            watermark: Some(dec!(100)),
+        }
+    }
+
+    #[test]
+    fn test_trigger_follows_high_water_mark() {
+        let mut stop = trailing_sell(TrailingOffset::Percent(dec!(0.05)));
+        assert_eq!(stop.trigger_price(), Some(dec!(95)));
+
+        assert!(!stop.update(dec!(120)));
+        assert_eq!(stop.trigger_price(), Some(dec!(114)));
+        // Falling prices never lower the trigger.
+        assert!(!stop.update(dec!(115)));
+        assert_eq!(stop.watermark, Some(dec!(120)));
+        assert!(stop.update(dec!(114)));
+    }
+
+    #[test]
+    fn test_snapshot_round_trip_keeps_watermark() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let registry = Arc::new(InstrumentRegistry::new(tx));
+        let manager = TrailingStopManager::new(registry.clone());
+        let mut stop = trailing_sell(TrailingOffset::Absolute(dec!(5)));
+        stop.update(dec!(130));
+        manager.restore(vec![stop.clone()]);
+
+        let json = serde_json::to_string(&manager.snapshot()).unwrap();
+        let restored = TrailingStopManager::new(registry);
+        restored.restore(serde_json::from_str(&json).unwrap());
+        let reloaded = restored.stop(stop.order.id).unwrap();
+        assert_eq!(reloaded.watermark, Some(dec!(130)));
+        assert_eq!(reloaded.trigger_price(), Some(dec!(125)));
+    }
+
+    #[tokio::test]
+    async fn test_triggered_stop_becomes_limit_order() {
+        let (tx, mut trade_rx) = mpsc::unbounded_channel();
+        let registry = Arc::new(InstrumentRegistry::new(tx));
+        let instrument = Instrument {
+            tick_size: dec!(0.5),
+            ..Instrument::new("BTC", "USD")
+        };
+        let book = registry.list(instrument).unwrap();
+        let manager = TrailingStopManager::new(registry.clone());
+        let trader = Uuid::new_v4();
+        let order = Order::new(trader, "BTC/USD".to_string(), Side::Sell, OrderType::Limit, dec!(1), dec!(1));
+        let stop_id = manager.submit(order, TrailingOffset::Percent(dec!(0.1)), dec!(1)).unwrap();
+        assert_eq!(manager.stop(stop_id).unwrap().watermark, None);
+
+        let (buyer, seller) = (Uuid::new_v4(), Uuid::new_v4());
+        for price in [dec!(100), dec!(110), dec!(98.5)] {
+            registry
+                .submit(Order::new(buyer, "BTC/USD".to_string(), Side::Buy, OrderType::Limit, price, dec!(1)))
+                .await
+                .unwrap();
+            registry
+                .submit(Order::new(seller, "BTC/USD".to_string(), Side::Sell, OrderType::Limit, price, dec!(1)))
+                .await
+                .unwrap();
+            let trade = trade_rx.try_recv().unwrap();
+            manager.on_trade(&trade).await.unwrap();
+        }
+
+        // Watermark 110, trigger 99, limit 98 on the 0.5 tick grid.
+        assert!(manager.stop(stop_id).is_none());
+        let resting = book.get_order(stop_id).unwrap();
+        assert_eq!(resting.price, dec!(98));
+        assert_eq!(resting.side, Side::Sell);
+    }
+}
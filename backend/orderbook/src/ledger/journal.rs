@@ -0,0 +1,562 @@
+use parking_lot::RwLock;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use super::types::{
+    EntryKind, JournalEntry, LedgerAccount, LedgerError, LedgerResult, Posting, SwapLeg,
+};
+use crate::models::{account::Account, trade::Trade};
+
+#[derive(Default)]
+struct LedgerState {
+    entries: Vec<JournalEntry>,
+    // Running totals derived from `entries`; rebuilt and compared by `check_invariants`.
+    balances: HashMap<(LedgerAccount, String), Decimal>,
+    positions: HashMap<(Uuid, String), Decimal>,
+}
+
+impl LedgerState {
+    fn apply(&mut self, entry: &JournalEntry) {
+        for posting in &entry.postings {
+            *self
+                .balances
+                .entry((posting.account.clone(), posting.asset.clone()))
+                .or_insert(Decimal::ZERO) += posting.amount;
+        }
+
+        if let Some((symbol, base_asset)) = settlement_base(entry) {
+            for posting in &entry.postings {
+                if let LedgerAccount::User(user_id) = posting.account {
+                    if posting.asset == base_asset {
+                        *self
+                            .positions
+                            .entry((user_id, symbol.to_string()))
+                            .or_insert(Decimal::ZERO) += posting.amount;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Fails if the entries would leave a user or pool account negative.
+    fn ensure_funded(&self, entries: &[JournalEntry]) -> LedgerResult<()> {
+        // Kept in posting order so the first shortfall reported is stable.
+        let mut changes: Vec<((&LedgerAccount, &str), Decimal)> = Vec::new();
+        for posting in entries.iter().flat_map(|entry| &entry.postings) {
+            if !matches!(posting.account, LedgerAccount::User(_) | LedgerAccount::Pool(_)) {
+                continue;
+            }
+            let key = (&posting.account, posting.asset.as_str());
+            match changes.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, change)) => *change += posting.amount,
+                None => changes.push((key, posting.amount)),
+            }
+        }
+        for ((account, asset), change) in changes {
+            let available = self
+                .balances
+                .get(&(account.clone(), asset.to_string()))
+                .copied()
+                .unwrap_or(Decimal::ZERO);
+            if available + change < Decimal::ZERO {
+                return Err(LedgerError::InsufficientBalance {
+                    asset: asset.to_string(),
+                    available,
+                    requested: -change,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// For a trade settlement entry, the symbol and the base asset whose
+/// movements make up the position.
+fn settlement_base(entry: &JournalEntry) -> Option<(&str, &str)> {
+    if entry.kind != EntryKind::TradeSettlement {
+        return None;
+    }
+    let symbol = entry.symbol.as_deref()?;
+    entry.postings.first().map(|posting| (symbol, posting.asset.as_str()))
+}
+
+/// Double-entry ledger for every balance movement on the exchange.
+///
+/// Balances are never written directly: each deposit, withdrawal, trade
+/// settlement, fee and swap posts a journal entry whose postings sum to zero
+/// per asset, and balances are derived from the journal. User and pool
+/// accounts hold real funds and are never overdrawn; an entry that would
+/// take one below zero is rejected whole.
+#[derive(Default)]
+pub struct Ledger {
+    state: RwLock<LedgerState>,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn deposit(&self, user_id: Uuid, asset: &str, amount: Decimal) -> LedgerResult<JournalEntry> {
+        ensure_positive(amount)?;
+        self.post(JournalEntry::new(
+            EntryKind::Deposit,
+            vec![
+                posting(LedgerAccount::External, asset, -amount),
+                posting(LedgerAccount::User(user_id), asset, amount),
+            ],
+        ))
+    }
+
+    pub fn withdraw(&self, user_id: Uuid, asset: &str, amount: Decimal) -> LedgerResult<JournalEntry> {
+        ensure_positive(amount)?;
+        self.post(JournalEntry::new(
+            EntryKind::Withdrawal,
+            vec![
+                posting(LedgerAccount::User(user_id), asset, -amount),
+                posting(LedgerAccount::External, asset, amount),
+            ],
+        ))
+    }
+
+    /// Funds a pool's initial reserves from outside the exchange.
+    pub fn seed_pool(&self, pool_id: Uuid, asset: &str, amount: Decimal) -> LedgerResult<JournalEntry> {
+        ensure_positive(amount)?;
+        self.post(
+            JournalEntry::new(
+                EntryKind::Deposit,
+                vec![
+                    posting(LedgerAccount::External, asset, -amount),
+                    posting(LedgerAccount::Pool(pool_id), asset, amount),
+                ],
+            )
+            .with_reference(pool_id),
+        )
+    }
+
+    /// Moves the base asset from seller to buyer and the quote asset from
+    /// buyer to seller at the trade price.
+    pub fn settle_trade(
+        &self,
+        trade: &Trade,
+        base_asset: &str,
+        quote_asset: &str,
+    ) -> LedgerResult<JournalEntry> {
+        self.post(trade_entry(trade, base_asset, quote_asset)?)
+    }
+
+    /// Settles the trades and charges their fees as one unit: if any
+    /// account can't cover its side, nothing is posted.
+    pub fn settle_trades(
+        &self,
+        trades: &[Trade],
+        base_asset: &str,
+        quote_asset: &str,
+    ) -> LedgerResult<Vec<JournalEntry>> {
+        let mut entries = Vec::with_capacity(trades.len());
+        for trade in trades {
+            entries.push(trade_entry(trade, base_asset, quote_asset)?);
+            if let Some(fee_asset) = &trade.fee_asset {
+                if !trade.maker_fee.is_zero() {
+                    entries.push(fee_entry(trade.maker_user_id, fee_asset, trade.maker_fee, trade.id));
+                }
+                if !trade.taker_fee.is_zero() {
+                    entries.push(fee_entry(trade.taker_user_id, fee_asset, trade.taker_fee, trade.id));
+                }
+            }
+        }
+        self.post_all(entries)
+    }
+
+    /// Charges a fee to the user; a negative amount pays a rebate out of fee revenue.
+    pub fn charge_fee(
+        &self,
+        user_id: Uuid,
+        asset: &str,
+        amount: Decimal,
+        reference: Uuid,
+    ) -> LedgerResult<JournalEntry> {
+        self.post(fee_entry(user_id, asset, amount, reference))
+    }
+
+    /// Records an AMM swap: the trader pays `amount_in` into the pool and
+    /// receives `amount_out` from it.
+    pub fn record_swap(
+        &self,
+        trader_id: Uuid,
+        pool_id: Uuid,
+        asset_in: &str,
+        amount_in: Decimal,
+        asset_out: &str,
+        amount_out: Decimal,
+    ) -> LedgerResult<JournalEntry> {
+        self.post(swap_entry(
+            trader_id,
+            &SwapLeg {
+                pool_id,
+                asset_in: asset_in.to_string(),
+                amount_in,
+                asset_out: asset_out.to_string(),
+                amount_out,
+            },
+        )?)
+    }
+
+    /// Records the legs of a routed swap as one unit: the trader must be
+    /// able to fund the first leg, and each later leg is paid for by the
+    /// one before it. If any leg can't be covered, nothing is posted.
+    pub fn record_swaps(&self, trader_id: Uuid, legs: &[SwapLeg]) -> LedgerResult<Vec<JournalEntry>> {
+        let entries = legs
+            .iter()
+            .map(|leg| swap_entry(trader_id, leg))
+            .collect::<LedgerResult<Vec<_>>>()?;
+        self.post_all(entries)
+    }
+
+    /// Records tokens a liquidity provider pays into a pool (positive
+    /// amounts) or receives from it (negative): deposits, withdrawals,
+    /// refunds and fee claims. Zero amounts are left out.
+    pub fn record_liquidity(
+        &self,
+        provider_id: Uuid,
+        pool_id: Uuid,
+        amounts: &[(&str, Decimal)],
+    ) -> LedgerResult<JournalEntry> {
+        let provider = LedgerAccount::User(provider_id);
+        let pool = LedgerAccount::Pool(pool_id);
+        let postings: Vec<Posting> = amounts
+            .iter()
+            .filter(|(_, amount)| !amount.is_zero())
+            .flat_map(|(asset, amount)| [posting(provider.clone(), asset, -*amount), posting(pool.clone(), asset, *amount)])
+            .collect();
+        if postings.is_empty() {
+            return Err(LedgerError::InvalidAmount);
+        }
+        self.post(JournalEntry::new(EntryKind::Liquidity, postings).with_reference(pool_id))
+    }
+
+    pub fn post(&self, entry: JournalEntry) -> LedgerResult<JournalEntry> {
+        let mut entries = self.post_all(vec![entry])?;
+        Ok(entries.remove(0))
+    }
+
+    /// Posts the entries together or, if any is unbalanced or would
+    /// overdraw an account, not at all.
+    pub fn post_all(&self, entries: Vec<JournalEntry>) -> LedgerResult<Vec<JournalEntry>> {
+        let mut state = self.state.write();
+        for entry in &entries {
+            if let Some((asset, _)) = entry.net_by_asset().into_iter().find(|(_, net)| !net.is_zero()) {
+                return Err(LedgerError::UnbalancedEntry(asset));
+            }
+        }
+        state.ensure_funded(&entries)?;
+
+        for entry in &entries {
+            state.apply(entry);
+            state.entries.push(entry.clone());
+        }
+        Ok(entries)
+    }
+
+    pub fn balance(&self, account: &LedgerAccount, asset: &str) -> Decimal {
+        self.state
+            .read()
+            .balances
+            .get(&(account.clone(), asset.to_string()))
+            .copied()
+            .unwrap_or(Decimal::ZERO)
+    }
+
+    pub fn position(&self, user_id: Uuid, symbol: &str) -> Decimal {
+        self.state
+            .read()
+            .positions
+            .get(&(user_id, symbol.to_string()))
+            .copied()
+            .unwrap_or(Decimal::ZERO)
+    }
+
+    pub fn account(&self, user_id: Uuid) -> Account {
+        let state = self.state.read();
+        let balances = state
+            .balances
+            .iter()
+            .filter(|((account, _), _)| *account == LedgerAccount::User(user_id))
+            .map(|((_, asset), amount)| (asset.clone(), *amount))
+            .collect();
+        let positions = state
+            .positions
+            .iter()
+            .filter(|((owner, _), _)| *owner == user_id)
+            .map(|((_, symbol), quantity)| (symbol.clone(), *quantity))
+            .collect();
+
+        Account {
+            user_id,
+            balances,
+            positions,
+        }
+    }
+
+    pub fn entries(&self) -> Vec<JournalEntry> {
+        self.state.read().entries.clone()
+    }
+
+    /// Replays the journal and verifies that every entry balances, that the
+    /// cached balances match the replay, and that for every asset the funds
+    /// held inside the exchange equal total deposits minus withdrawals.
+    pub fn check_invariants(&self) -> LedgerResult<()> {
+        let state = self.state.read();
+        let mut replay = LedgerState::default();
+        let mut net_deposits: HashMap<String, Decimal> = HashMap::new();
+
+        for entry in &state.entries {
+            if let Some((asset, _)) = entry.net_by_asset().into_iter().find(|(_, net)| !net.is_zero()) {
+                return Err(LedgerError::UnbalancedEntry(asset));
+            }
+            if matches!(entry.kind, EntryKind::Deposit | EntryKind::Withdrawal) {
+                for posting in &entry.postings {
+                    if posting.account == LedgerAccount::External {
+                        *net_deposits.entry(posting.asset.clone()).or_insert(Decimal::ZERO) -= posting.amount;
+                    }
+                }
+            }
+            replay.apply(entry);
+        }
+
+        for (key, expected) in &replay.balances {
+            let actual = state.balances.get(key).copied().unwrap_or(Decimal::ZERO);
+            if actual != *expected {
+                return Err(LedgerError::InvariantViolation {
+                    asset: key.1.clone(),
+                    expected: *expected,
+                    actual,
+                });
+            }
+        }
+
+        let mut held: HashMap<String, Decimal> = HashMap::new();
+        for ((account, asset), amount) in &replay.balances {
+            if *account != LedgerAccount::External {
+                *held.entry(asset.clone()).or_insert(Decimal::ZERO) += *amount;
+            }
+        }
+        for (asset, actual) in held {
+            let expected = net_deposits.get(&asset).copied().unwrap_or(Decimal::ZERO);
+            if actual != expected {
+                return Err(LedgerError::InvariantViolation {
+                    asset,
+                    expected,
+                    actual,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn trade_entry(trade: &Trade, base_asset: &str, quote_asset: &str) -> LedgerResult<JournalEntry> {
+    ensure_positive(trade.quantity)?;
+    let buyer = LedgerAccount::User(trade.buyer_id());
+    let seller = LedgerAccount::User(trade.seller_id());
+    let notional = trade.notional();
+
+    Ok(JournalEntry::new(
+        EntryKind::TradeSettlement,
+        vec![
+            posting(buyer.clone(), base_asset, trade.quantity),
+            posting(seller.clone(), base_asset, -trade.quantity),
+            posting(buyer, quote_asset, -notional),
+            posting(seller, quote_asset, notional),
+        ],
+    )
+    .with_symbol(&trade.symbol)
+    .with_reference(trade.id))
+}
+
+fn swap_entry(trader_id: Uuid, leg: &SwapLeg) -> LedgerResult<JournalEntry> {
+    ensure_positive(leg.amount_in)?;
+    ensure_positive(leg.amount_out)?;
+    let trader = LedgerAccount::User(trader_id);
+    let pool = LedgerAccount::Pool(leg.pool_id);
+
+    Ok(JournalEntry::new(
+        EntryKind::Swap,
+        vec![
+            posting(trader.clone(), &leg.asset_in, -leg.amount_in),
+            posting(pool.clone(), &leg.asset_in, leg.amount_in),
+            posting(pool, &leg.asset_out, -leg.amount_out),
+            posting(trader, &leg.asset_out, leg.amount_out),
+        ],
+    )
+    .with_reference(leg.pool_id))
+}
+
+fn fee_entry(user_id: Uuid, asset: &str, amount: Decimal, reference: Uuid) -> JournalEntry {
+    JournalEntry::new(
+        EntryKind::Fee,
+        vec![
+            posting(LedgerAccount::User(user_id), asset, -amount),
+            posting(LedgerAccount::FeeRevenue, asset, amount),
+        ],
+    )
+    .with_reference(reference)
+}
+
+fn posting(account: LedgerAccount, asset: &str, amount: Decimal) -> Posting {
+    Posting {
+        account,
+        asset: asset.to_string(),
+        amount,
+    }
+}
+
+fn ensure_positive(amount: Decimal) -> LedgerResult<()> {
+    if amount <= Decimal::ZERO {
+        Err(LedgerError::InvalidAmount)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::order::{Order, OrderType, Side};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_deposit_and_withdraw() {
+        let ledger = Ledger::new();
+        let user_id = Uuid::new_v4();
+
+        ledger.deposit(user_id, "USD", dec!(1000)).unwrap();
+        ledger.withdraw(user_id, "USD", dec!(400)).unwrap();
+
+        assert_eq!(ledger.account(user_id).get_balance("USD"), dec!(600));
+        assert_eq!(ledger.balance(&LedgerAccount::External, "USD"), dec!(-600));
+        ledger.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_withdraw_more_than_balance() {
+        let ledger = Ledger::new();
+        let user_id = Uuid::new_v4();
+        ledger.deposit(user_id, "USD", dec!(100)).unwrap();
+
+        let result = ledger.withdraw(user_id, "USD", dec!(101));
+        assert!(matches!(result, Err(LedgerError::InsufficientBalance { .. })));
+        assert_eq!(ledger.entries().len(), 1);
+    }
+
+    #[test]
+    fn test_trade_settlement_adds_to_positions() {
+        let ledger = Ledger::new();
+        let buyer = Uuid::new_v4();
+        let seller = Uuid::new_v4();
+        ledger.deposit(buyer, "USD", dec!(100000)).unwrap();
+        ledger.deposit(seller, "BTC", dec!(2)).unwrap();
+
+        let maker = Order::new(seller, "BTC/USD".to_string(), Side::Sell, OrderType::Limit, dec!(50000), dec!(2));
+        let taker = Order::new(buyer, "BTC/USD".to_string(), Side::Buy, OrderType::Limit, dec!(50000), dec!(2));
+        ledger.settle_trade(&Trade::new(&maker, &taker, dec!(1)), "BTC", "USD").unwrap();
+        ledger.settle_trade(&Trade::new(&maker, &taker, dec!(0.5)), "BTC", "USD").unwrap();
+
+        let buyer_account = ledger.account(buyer);
+        assert_eq!(buyer_account.get_balance("BTC"), dec!(1.5));
+        assert_eq!(buyer_account.get_balance("USD"), dec!(25000));
+        assert_eq!(buyer_account.get_position("BTC/USD"), dec!(1.5));
+        assert_eq!(ledger.position(seller, "BTC/USD"), dec!(-1.5));
+        assert_eq!(ledger.account(seller).get_balance("USD"), dec!(75000));
+        ledger.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_fees_and_swaps_keep_invariants() {
+        let ledger = Ledger::new();
+        let user_id = Uuid::new_v4();
+        let pool_id = Uuid::new_v4();
+        ledger.deposit(user_id, "USDC", dec!(1000)).unwrap();
+        ledger.seed_pool(pool_id, "ETH", dec!(1)).unwrap();
+
+        ledger.charge_fee(user_id, "USDC", dec!(3), Uuid::new_v4()).unwrap();
+        ledger.record_swap(user_id, pool_id, "USDC", dec!(500), "ETH", dec!(0.25)).unwrap();
+
+        assert_eq!(ledger.balance(&LedgerAccount::FeeRevenue, "USDC"), dec!(3));
+        assert_eq!(ledger.balance(&LedgerAccount::Pool(pool_id), "ETH"), dec!(0.75));
+        assert_eq!(ledger.account(user_id).get_balance("USDC"), dec!(497));
+        assert_eq!(ledger.account(user_id).get_balance("ETH"), dec!(0.25));
+        ledger.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_unfunded_settlement_rejected() {
+        let ledger = Ledger::new();
+        let (buyer, seller) = (Uuid::new_v4(), Uuid::new_v4());
+        ledger.deposit(buyer, "USD", dec!(100000)).unwrap();
+        ledger.deposit(seller, "BTC", dec!(1)).unwrap();
+        let maker = Order::new(seller, "BTC/USD".to_string(), Side::Sell, OrderType::Limit, dec!(50000), dec!(2));
+        let taker = Order::new(buyer, "BTC/USD".to_string(), Side::Buy, OrderType::Limit, dec!(50000), dec!(2));
+
+        // The seller holds 1 BTC of the 2 sold.
+        let result = ledger.settle_trade(&Trade::new(&maker, &taker, dec!(2)), "BTC", "USD");
+        assert!(matches!(
+            result,
+            Err(LedgerError::InsufficientBalance { ref asset, available, requested })
+                if asset == "BTC" && available == dec!(1) && requested == dec!(2)
+        ));
+
+        // Two fills the seller can deliver one at a time post together or not at all.
+        let fills = [Trade::new(&maker, &taker, dec!(0.5)), Trade::new(&maker, &taker, dec!(0.75))];
+        assert!(matches!(
+            ledger.settle_trades(&fills, "BTC", "USD"),
+            Err(LedgerError::InsufficientBalance { .. })
+        ));
+        assert_eq!(ledger.entries().len(), 2);
+        assert_eq!(ledger.account(buyer).get_balance("USD"), dec!(100000));
+        ledger.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_liquidity_moves_between_provider_and_pool() {
+        let ledger = Ledger::new();
+        let (provider_id, pool_id) = (Uuid::new_v4(), Uuid::new_v4());
+        ledger.deposit(provider_id, "USDC", dec!(2000)).unwrap();
+        ledger.deposit(provider_id, "ETH", dec!(1)).unwrap();
+
+        ledger.record_liquidity(provider_id, pool_id, &[("USDC", dec!(2000)), ("ETH", dec!(1))]).unwrap();
+        assert!(matches!(
+            ledger.record_liquidity(provider_id, pool_id, &[("USDC", dec!(1))]),
+            Err(LedgerError::InsufficientBalance { .. })
+        ));
+        // Withdrawals and refunds are negative; zero amounts are dropped.
+        let entry = ledger.record_liquidity(provider_id, pool_id, &[("USDC", dec!(-500)), ("ETH", Decimal::ZERO)]).unwrap();
+        assert_eq!(entry.postings.len(), 2);
+        assert!(matches!(
+            ledger.record_liquidity(provider_id, pool_id, &[("ETH", dec!(-2))]),
+            Err(LedgerError::InsufficientBalance { .. })
+        ));
+        assert!(matches!(
+            ledger.record_liquidity(provider_id, pool_id, &[("ETH", Decimal::ZERO)]),
+            Err(LedgerError::InvalidAmount)
+        ));
+
+        assert_eq!(ledger.balance(&LedgerAccount::Pool(pool_id), "USDC"), dec!(1500));
+        assert_eq!(ledger.account(provider_id).get_balance("USDC"), dec!(500));
+        ledger.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_unbalanced_entry_rejected() {
+        let ledger = Ledger::new();
+        let entry = JournalEntry::new(
+            EntryKind::Deposit,
+            vec![posting(LedgerAccount::User(Uuid::new_v4()), "USD", dec!(10))],
+        );
+
+        assert!(matches!(ledger.post(entry), Err(LedgerError::UnbalancedEntry(_))));
+        assert!(ledger.entries().is_empty());
+    }
+}
@@ -0,0 +1,5 @@
+pub mod journal;
+pub mod types;
+
+pub use journal::Ledger;
+pub use types::{EntryKind, JournalEntry, LedgerAccount, LedgerError, Posting, SwapLeg};
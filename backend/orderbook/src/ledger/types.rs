@@ -0,0 +1,118 @@
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// A book of account that postings are made against.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum LedgerAccount {
+    /// Funds held by a trading user.
+    User(Uuid),
+    /// Reserves held by an AMM pool.
+    Pool(Uuid),
+    /// Fees collected by the exchange.
+    FeeRevenue,
+    /// Counterparty for deposits and withdrawals (the outside world).
+    External,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EntryKind {
+    Deposit,
+    Withdrawal,
+    TradeSettlement,
+    Fee,
+    Swap,
+    /// Tokens moved between a liquidity provider and a pool.
+    Liquidity,
+}
+
+/// A single signed movement of one asset on one account.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Posting {
+    pub account: LedgerAccount,
+    pub asset: String,
+    pub amount: Decimal,
+}
+
+/// One pool swap of a routed trade: the trader pays `amount_in` into the
+/// pool and receives `amount_out` from it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SwapLeg {
+    pub pool_id: Uuid,
+    pub asset_in: String,
+    pub amount_in: Decimal,
+    pub asset_out: String,
+    pub amount_out: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub id: Uuid,
+    pub kind: EntryKind,
+    /// Order-book symbol for trade settlements, used to derive positions.
+    pub symbol: Option<String>,
+    /// Id of the trade, swap or request that caused the entry.
+    pub reference: Option<Uuid>,
+    pub postings: Vec<Posting>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl JournalEntry {
+    pub fn new(kind: EntryKind, postings: Vec<Posting>) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            kind,
+            symbol: None,
+            reference: None,
+            postings,
+            created_at: Utc::now(),
+        }
+    }
+
+    pub fn with_symbol(mut self, symbol: &str) -> Self {
+        self.symbol = Some(symbol.to_string());
+        self
+    }
+
+    pub fn with_reference(mut self, reference: Uuid) -> Self {
+        self.reference = Some(reference);
+        self
+    }
+
+    /// Net amount per asset; every value is zero for a balanced entry.
+    pub fn net_by_asset(&self) -> HashMap<String, Decimal> {
+        let mut net: HashMap<String, Decimal> = HashMap::new();
+        for posting in &self.postings {
+            *net.entry(posting.asset.clone()).or_insert(Decimal::ZERO) += posting.amount;
+        }
+        net
+    }
+
+    pub fn is_balanced(&self) -> bool {
+        self.net_by_asset().values().all(|net| net.is_zero())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum LedgerError {
+    #[error("Amount must be positive")]
+    InvalidAmount,
+    #[error("Insufficient balance of {asset}: available {available}, requested {requested}")]
+    InsufficientBalance {
+        asset: String,
+        available: Decimal,
+        requested: Decimal,
+    },
+    #[error("Journal entry does not balance for {0}")]
+    UnbalancedEntry(String),
+    #[error("Ledger invariant violated for {asset}: expected {expected}, found {actual}")]
+    InvariantViolation {
+        asset: String,
+        expected: Decimal,
+        actual: Decimal,
+    },
+}
+
+pub type LedgerResult<T> = Result<T, LedgerError>;
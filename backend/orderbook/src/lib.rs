@@ -1,7 +1,10 @@
+pub mod api;
 pub mod engine;  // This exposes the engine module
 pub mod models;  // Add this line to expose the models module
 pub mod utils;
 pub mod market_maker;
+pub mod ledger;
+pub mod risk;
 
 pub use engine::orderbook::OrderBook;
 pub use market_maker::AutomatedMarketMaker;
@@ -1,28 +1,77 @@
+use chrono::Duration;
 use rust_decimal::Decimal;
 use tokio::sync::RwLock;
 use std::sync::Arc;
 use std::collections::HashMap;
 
 use super::{
-    types::{MarketMakerError, Pool, SwapResult, PoolPosition},
-    LiquidityPool, PriceImpactCalculator, SlippageProtection,
+    types::{
+        ConcentratedPoolInfo, EarnedFees, FeeClaim, LiquidityChange, MarketMakerError, Pool, PoolCurve, PoolKey,
+        PricingMode, SwapResult, PoolPosition, TwapPrice, RangeLiquidityChange, WeightedLiquidityChange, WeightedPoolInfo, WeightedToken, ZapResult,
+    },
+    AmmPool, ConcentratedPool, LiquidityPool, PriceImpactCalculator, SlippageProtection, StableSwapPool, WeightedPool,
 };
+use super::pool::round_down;
+use crate::ledger::Ledger;
+use crate::utils::clock::{Clock, SystemClock};
+
+type SharedPool = Arc<RwLock<dyn AmmPool>>;
 
 pub struct AutomatedMarketMaker {
-    pools: Arc<RwLock<HashMap<String, Arc<RwLock<LiquidityPool>>>>>,
+    pools: Arc<RwLock<HashMap<PoolKey, SharedPool>>>,
+    /// Pools of more than two tokens, which a token pair cannot key.
+    weighted_pools: Arc<RwLock<HashMap<uuid::Uuid, Arc<RwLock<WeightedPool>>>>>,
+    /// Pools whose liquidity is provided over price ranges.
+    concentrated_pools: Arc<RwLock<HashMap<uuid::Uuid, Arc<RwLock<ConcentratedPool>>>>>,
     price_calculator: PriceImpactCalculator,
     slippage_protection: SlippageProtection,
+    pricing_mode: PricingMode,
+    ledger: Option<Arc<Ledger>>,
+    /// Times the price observations of pools created from now on.
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for AutomatedMarketMaker {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl AutomatedMarketMaker {
     pub fn new() -> Self {
         Self {
             pools: Arc::new(RwLock::new(HashMap::new())),
+            weighted_pools: Arc::new(RwLock::new(HashMap::new())),
+            concentrated_pools: Arc::new(RwLock::new(HashMap::new())),
             price_calculator: PriceImpactCalculator::new(),
             slippage_protection: SlippageProtection::new(Decimal::new(2, 2)), // 2% default
+            pricing_mode: PricingMode::default(),
+            ledger: None,
+            clock: Arc::new(SystemClock),
         }
     }
 
+    pub fn with_pricing_mode(mut self, pricing_mode: PricingMode) -> Self {
+        self.pricing_mode = pricing_mode;
+        self
+    }
+
+    /// Journals pool reserves, liquidity changes, fee claims and swaps made
+    /// through `swap_for_account` on the given ledger, which rejects any
+    /// the provider or trader can't fund. Plain `swap` is refused from then
+    /// on, since it has no account to post to.
+    pub fn with_ledger(mut self, ledger: Arc<Ledger>) -> Self {
+        self.ledger = Some(ledger);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Registers a constant product pool under the canonical key of its
+    /// pair and fee tier.
     pub async fn create_pool(
         &self,
         token_a: String,
@@ -31,8 +80,213 @@ impl AutomatedMarketMaker {
         initial_b: Decimal,
         fee_percentage: Decimal,
     ) -> Result<Pool, MarketMakerError> {
-        let pool = LiquidityPool::new(token_a, token_b, initial_a, initial_b, fee_percentage);
-        let pool_key = format!("{}-{}", pool.token_a(), pool.token_b());
+        validate_pool(&token_a, &token_b, fee_percentage)?;
+        let pool = LiquidityPool::new(token_a, token_b, initial_a, initial_b, fee_percentage).with_clock(self.clock.clone());
+        self.register(pool).await
+    }
+
+    /// Registers a StableSwap pool for a pegged pair. A pair and fee tier
+    /// holds one pool whatever its curve.
+    pub async fn create_stable_pool(
+        &self,
+        token_a: String,
+        token_b: String,
+        initial_a: Decimal,
+        initial_b: Decimal,
+        fee_percentage: Decimal,
+        amplification: Decimal,
+    ) -> Result<Pool, MarketMakerError> {
+        validate_pool(&token_a, &token_b, fee_percentage)?;
+        if amplification < Decimal::ONE {
+            return Err(MarketMakerError::InvalidPoolParameters);
+        }
+        let pool = StableSwapPool::new(token_a, token_b, initial_a, initial_b, fee_percentage, amplification)
+            .with_clock(self.clock.clone());
+        self.register(pool).await
+    }
+
+    pub async fn create_weighted_pool(
+        &self,
+        tokens: Vec<WeightedToken>,
+        fee_percentage: Decimal,
+    ) -> Result<WeightedPoolInfo, MarketMakerError> {
+        let pool = WeightedPool::new(tokens, fee_percentage)?;
+        let pool_info = pool.pool_info().clone();
+        self.weighted_pools
+            .write()
+            .await
+            .insert(pool_info.id, Arc::new(RwLock::new(pool)));
+        Ok(pool_info)
+    }
+
+    pub async fn get_weighted_pool_info(&self, pool_id: uuid::Uuid) -> Result<WeightedPoolInfo, MarketMakerError> {
+        let pool = self.weighted_pool(pool_id).await?;
+        let pool = pool.read().await;
+        Ok(pool.pool_info().clone())
+    }
+
+    /// Swaps between any two members of a weighted pool.
+    pub async fn swap_weighted(
+        &self,
+        pool_id: uuid::Uuid,
+        token_in: &str,
+        token_out: &str,
+        input_amount: Decimal,
+        min_output: Decimal,
+    ) -> Result<SwapResult, MarketMakerError> {
+        let pool = self.weighted_pool(pool_id).await?;
+        let mut pool = pool.write().await;
+        pool.swap_exact_input(token_in, token_out, input_amount, min_output)
+    }
+
+    /// Joins a weighted pool with a single token.
+    pub async fn join_weighted(
+        &self,
+        pool_id: uuid::Uuid,
+        provider_id: uuid::Uuid,
+        token: &str,
+        amount: Decimal,
+    ) -> Result<WeightedLiquidityChange, MarketMakerError> {
+        let pool = self.weighted_pool(pool_id).await?;
+        let mut pool = pool.write().await;
+        pool.join_single(provider_id, token, amount)
+    }
+
+    /// Joins a weighted pool with every token, `amounts` in the pool's
+    /// token order. This is how an unseeded pool gets its first liquidity.
+    pub async fn join_weighted_all(
+        &self,
+        pool_id: uuid::Uuid,
+        provider_id: uuid::Uuid,
+        amounts: &[Decimal],
+    ) -> Result<WeightedLiquidityChange, MarketMakerError> {
+        let pool = self.weighted_pool(pool_id).await?;
+        let mut pool = pool.write().await;
+        pool.join(provider_id, amounts)
+    }
+
+    /// Exits a weighted pool into every token in proportion to the balances.
+    pub async fn exit_weighted_all(
+        &self,
+        pool_id: uuid::Uuid,
+        provider_id: uuid::Uuid,
+        shares: Decimal,
+    ) -> Result<WeightedLiquidityChange, MarketMakerError> {
+        let pool = self.weighted_pool(pool_id).await?;
+        let mut pool = pool.write().await;
+        pool.exit(provider_id, shares)
+    }
+
+    /// Exits a weighted pool into a single token.
+    pub async fn exit_weighted(
+        &self,
+        pool_id: uuid::Uuid,
+        provider_id: uuid::Uuid,
+        token: &str,
+        shares: Decimal,
+    ) -> Result<WeightedLiquidityChange, MarketMakerError> {
+        let pool = self.weighted_pool(pool_id).await?;
+        let mut pool = pool.write().await;
+        pool.exit_single(provider_id, token, shares)
+    }
+
+    async fn weighted_pool(&self, pool_id: uuid::Uuid) -> Result<Arc<RwLock<WeightedPool>>, MarketMakerError> {
+        self.weighted_pools
+            .read()
+            .await
+            .get(&pool_id)
+            .cloned()
+            .ok_or(MarketMakerError::WeightedPoolNotFound(pool_id))
+    }
+
+    /// Opens a concentrated liquidity pool at `price`, in `token_1` per
+    /// `token_0`.
+    pub async fn create_concentrated_pool(
+        &self,
+        token_0: String,
+        token_1: String,
+        fee_percentage: Decimal,
+        tick_spacing: i32,
+        price: Decimal,
+    ) -> Result<ConcentratedPoolInfo, MarketMakerError> {
+        let pool = ConcentratedPool::new(token_0, token_1, fee_percentage, tick_spacing, price)?;
+        let pool_info = pool.pool_info().clone();
+        self.concentrated_pools
+            .write()
+            .await
+            .insert(pool_info.id, Arc::new(RwLock::new(pool)));
+        Ok(pool_info)
+    }
+
+    pub async fn get_concentrated_pool_info(&self, pool_id: uuid::Uuid) -> Result<ConcentratedPoolInfo, MarketMakerError> {
+        let pool = self.concentrated_pool(pool_id).await?;
+        let pool = pool.read().await;
+        Ok(pool.pool_info().clone())
+    }
+
+    pub async fn swap_concentrated(
+        &self,
+        pool_id: uuid::Uuid,
+        input_token: &str,
+        input_amount: Decimal,
+        min_output: Decimal,
+    ) -> Result<SwapResult, MarketMakerError> {
+        let pool = self.concentrated_pool(pool_id).await?;
+        let mut pool = pool.write().await;
+        pool.swap_exact_input(input_token, input_amount, min_output)
+    }
+
+    /// Provides `liquidity` over `[tick_lower, tick_upper)`.
+    pub async fn mint_range(
+        &self,
+        pool_id: uuid::Uuid,
+        provider_id: uuid::Uuid,
+        tick_lower: i32,
+        tick_upper: i32,
+        liquidity: Decimal,
+    ) -> Result<RangeLiquidityChange, MarketMakerError> {
+        let pool = self.concentrated_pool(pool_id).await?;
+        let mut pool = pool.write().await;
+        pool.mint(provider_id, tick_lower, tick_upper, liquidity)
+    }
+
+    pub async fn burn_range(
+        &self,
+        pool_id: uuid::Uuid,
+        provider_id: uuid::Uuid,
+        tick_lower: i32,
+        tick_upper: i32,
+        liquidity: Decimal,
+    ) -> Result<RangeLiquidityChange, MarketMakerError> {
+        let pool = self.concentrated_pool(pool_id).await?;
+        let mut pool = pool.write().await;
+        pool.burn(provider_id, tick_lower, tick_upper, liquidity)
+    }
+
+    /// Pays out the fees a range position has earned.
+    pub async fn collect_range_fees(
+        &self,
+        pool_id: uuid::Uuid,
+        provider_id: uuid::Uuid,
+        tick_lower: i32,
+        tick_upper: i32,
+    ) -> Result<(Decimal, Decimal), MarketMakerError> {
+        let pool = self.concentrated_pool(pool_id).await?;
+        let mut pool = pool.write().await;
+        pool.collect(provider_id, tick_lower, tick_upper)
+    }
+
+    async fn concentrated_pool(&self, pool_id: uuid::Uuid) -> Result<Arc<RwLock<ConcentratedPool>>, MarketMakerError> {
+        self.concentrated_pools
+            .read()
+            .await
+            .get(&pool_id)
+            .cloned()
+            .ok_or(MarketMakerError::ConcentratedPoolNotFound(pool_id))
+    }
+
+    async fn register(&self, pool: impl AmmPool + 'static) -> Result<Pool, MarketMakerError> {
+        let pool_key = pool.pool_info().key();
         
         let mut pools = self.pools.write().await;
         if pools.contains_key(&pool_key) {
@@ -40,42 +294,175 @@ impl AutomatedMarketMaker {
         }
         
         let pool_info = pool.pool_info().clone();
+        if let Some(ledger) = &self.ledger {
+            for (token, reserve) in [(&pool_info.token_a, pool_info.reserve_a), (&pool_info.token_b, pool_info.reserve_b)] {
+                if !reserve.is_zero() {
+                    ledger.seed_pool(pool_info.id, token, reserve)?;
+                }
+            }
+        }
         pools.insert(pool_key, Arc::new(RwLock::new(pool)));
         
         Ok(pool_info)
     }
 
+    /// Keys of every pool trading the pair, in either order, across fee tiers.
+    pub async fn pools_for_pair(&self, token_a: &str, token_b: &str) -> Vec<PoolKey> {
+        let wanted = PoolKey::new(token_a, token_b, Decimal::ZERO);
+        let pools = self.pools.read().await;
+        let mut keys: Vec<PoolKey> = pools
+            .keys()
+            .filter(|key| key.token_0 == wanted.token_0 && key.token_1 == wanted.token_1)
+            .cloned()
+            .collect();
+        keys.sort();
+        keys
+    }
+
     pub async fn add_liquidity(
         &self,
-        token_pair: &str,
+        pool_key: &PoolKey,
         provider_id: uuid::Uuid,
         amount_a: Decimal,
         amount_b: Decimal,
-    ) -> Result<PoolPosition, MarketMakerError> {
-        let pools = self.pools.read().await;
-        let pool = pools.get(token_pair)
-            .ok_or(MarketMakerError::InvalidPoolParameters)?;
-            
+    ) -> Result<LiquidityChange, MarketMakerError> {
+        if amount_a < Decimal::ZERO || amount_b < Decimal::ZERO {
+            return Err(MarketMakerError::InvalidAmount);
+        }
+        let pool = self.pool(pool_key).await?;
         let mut pool = pool.write().await;
-        pool.add_liquidity(provider_id, amount_a, amount_b)
+        let pool_info = pool.pool_info().clone();
+        // Taking the full amounts up front checks the provider can fund them.
+        self.journal_liquidity(provider_id, &pool_info, amount_a, amount_b)?;
+        let change = match pool.add_liquidity(provider_id, amount_a, amount_b) {
+            Ok(change) => change,
+            Err(err) => {
+                self.journal_liquidity(provider_id, &pool_info, -amount_a, -amount_b)?;
+                return Err(err);
+            }
+        };
+        self.journal_liquidity(provider_id, &pool_info, -change.refund_a, -change.refund_b)?;
+        Ok(change)
+    }
+
+    /// Deposits a single token, swapping part of it into the other side.
+    pub async fn zap_in(
+        &self,
+        pool_key: &PoolKey,
+        provider_id: uuid::Uuid,
+        input_token: &str,
+        amount: Decimal,
+    ) -> Result<ZapResult, MarketMakerError> {
+        if amount <= Decimal::ZERO {
+            return Err(MarketMakerError::InvalidAmount);
+        }
+        let pool = self.pool(pool_key).await?;
+        let mut pool = pool.write().await;
+        let pool_info = pool.pool_info().clone();
+        let (amount_a, amount_b) = if input_token == pool_info.token_a {
+            (amount, Decimal::ZERO)
+        } else if input_token == pool_info.token_b {
+            (Decimal::ZERO, amount)
+        } else {
+            return Err(MarketMakerError::TokenNotInPool(input_token.to_string()));
+        };
+        self.journal_liquidity(provider_id, &pool_info, amount_a, amount_b)?;
+        let zap = match pool.zap_in(provider_id, input_token, amount) {
+            Ok(zap) => zap,
+            Err(err) => {
+                self.journal_liquidity(provider_id, &pool_info, -amount_a, -amount_b)?;
+                return Err(err);
+            }
+        };
+        self.journal_liquidity(provider_id, &pool_info, -zap.deposit.refund_a, -zap.deposit.refund_b)?;
+        Ok(zap)
+    }
+
+    /// Burns `shares` of the provider's LP position.
+    pub async fn remove_liquidity(
+        &self,
+        pool_key: &PoolKey,
+        provider_id: uuid::Uuid,
+        shares: Decimal,
+    ) -> Result<LiquidityChange, MarketMakerError> {
+        let pool = self.pool(pool_key).await?;
+        let mut pool = pool.write().await;
+        let pool_info = pool.pool_info().clone();
+        // Pay out first, so a posting the ledger rejects leaves the shares.
+        let payout = pool.quote_remove_liquidity(provider_id, shares)?;
+        self.journal_liquidity(provider_id, &pool_info, -payout.token_a_amount, -payout.token_b_amount)?;
+        pool.remove_liquidity(provider_id, shares).or_else(|err| {
+            self.journal_liquidity(provider_id, &pool_info, payout.token_a_amount, payout.token_b_amount)?;
+            Err(err)
+        })
+    }
+
+    /// Posts what the provider pays into the pool (positive) or receives
+    /// from it (negative), if a ledger is attached.
+    fn journal_liquidity(
+        &self,
+        provider_id: uuid::Uuid,
+        pool: &Pool,
+        amount_a: Decimal,
+        amount_b: Decimal,
+    ) -> Result<(), MarketMakerError> {
+        if let (Some(ledger), false) = (&self.ledger, amount_a.is_zero() && amount_b.is_zero()) {
+            ledger.record_liquidity(provider_id, pool.id, &[(&pool.token_a, amount_a), (&pool.token_b, amount_b)])?;
+        }
+        Ok(())
+    }
+
+    pub async fn position(&self, pool_key: &PoolKey, provider_id: uuid::Uuid) -> Result<PoolPosition, MarketMakerError> {
+        let pool = self.pool(pool_key).await?;
+        let pool = pool.read().await;
+        pool.position(provider_id).ok_or(MarketMakerError::InsufficientLiquidity)
+    }
+
+    /// Swap fees the provider has earned in the pool and not yet claimed.
+    pub async fn earned_fees(&self, pool_key: &PoolKey, provider_id: uuid::Uuid) -> Result<EarnedFees, MarketMakerError> {
+        let pool = self.pool(pool_key).await?;
+        let pool = pool.read().await;
+        Ok(pool.earned_fees(provider_id))
+    }
+
+    /// Pays out the provider's earned fees, leaving their LP shares in place.
+    pub async fn claim_fees(&self, pool_key: &PoolKey, provider_id: uuid::Uuid) -> Result<FeeClaim, MarketMakerError> {
+        let pool = self.pool(pool_key).await?;
+        let mut pool = pool.write().await;
+        let pool_info = pool.pool_info().clone();
+        // A claim pays exactly what has been earned; post it before the
+        // pool marks the fees as claimed.
+        let earned = pool.earned_fees(provider_id);
+        self.journal_liquidity(provider_id, &pool_info, -earned.token_a_amount, -earned.token_b_amount)?;
+        pool.claim_fees(provider_id).or_else(|err| {
+            self.journal_liquidity(provider_id, &pool_info, earned.token_a_amount, earned.token_b_amount)?;
+            Err(err)
+        })
+    }
+
+    pub async fn fee_claims(&self, pool_key: &PoolKey, provider_id: uuid::Uuid) -> Result<Vec<FeeClaim>, MarketMakerError> {
+        let pool = self.pool(pool_key).await?;
+        let pool = pool.read().await;
+        Ok(pool.fee_claims(provider_id))
     }
 
     pub async fn quote(
         &self,
-        token_pair: &str,
+        pool_key: &PoolKey,
         input_token: &str,
         input_amount: Decimal,
     ) -> Result<SwapResult, MarketMakerError> {
-        let pools = self.pools.read().await;
-        let pool = pools.get(token_pair)
-            .ok_or(MarketMakerError::InvalidPoolParameters)?;
+        let pool = self.pool(pool_key).await?;
         let pool = pool.read().await;
+        self.quote_pool(&*pool, input_token, input_amount)
+    }
+
+    fn quote_pool(&self, pool: &dyn AmmPool, input_token: &str, input_amount: Decimal) -> Result<SwapResult, MarketMakerError> {
+        if !self.impact_adjusted(pool) {
+            return pool.quote_exact_input(input_token, input_amount);
+        }
         
-        let (input_reserve, output_reserve) = if input_token == pool.token_a() {
-            (pool.reserve_a(), pool.reserve_b())
-        } else {
-            (pool.reserve_b(), pool.reserve_a())
-        };
+        let (input_reserve, output_reserve) = pool.reserves_for(input_token)?;
 
         let (output_amount, price_impact) = self.price_calculator
             .estimate_output_with_impact(input_amount, input_reserve, output_reserve)?;
@@ -90,12 +477,70 @@ impl AutomatedMarketMaker {
 
     pub async fn swap(
         &self,
-        token_pair: &str,
+        pool_key: &PoolKey,
+        input_token: &str,
+        input_amount: Decimal,
+        min_output: Decimal,
+    ) -> Result<SwapResult, MarketMakerError> {
+        // Reserves moved here would drift from the pool's ledger balance.
+        if self.ledger.is_some() {
+            return Err(MarketMakerError::AccountRequired);
+        }
+        // Price and execute under one write lock so the reserves cannot
+        // move in between.
+        let pool = self.pool(pool_key).await?;
+        let mut pool = pool.write().await;
+        let result = self.price_swap(&*pool, input_token, input_amount, min_output)?;
+        self.execute_priced(&mut *pool, input_token, &result)?;
+        Ok(result)
+    }
+
+    /// Swaps on behalf of a trader and posts the swap to the ledger.
+    ///
+    /// The swap is posted before the pool moves, under the pool's lock, so
+    /// a trader who can't fund the input is rejected and the reserves stay
+    /// in step with the journal.
+    pub async fn swap_for_account(
+        &self,
+        trader_id: uuid::Uuid,
+        pool_key: &PoolKey,
+        input_token: &str,
+        input_amount: Decimal,
+        min_output: Decimal,
+    ) -> Result<SwapResult, MarketMakerError> {
+        let pool = self.pool(pool_key).await?;
+        let mut pool = pool.write().await;
+        let result = self.price_swap(&*pool, input_token, input_amount, min_output)?;
+        if let Some(ledger) = &self.ledger {
+            ledger.record_swap(
+                trader_id,
+                pool.pool_info().id,
+                input_token,
+                result.input_amount,
+                pool.other_token(input_token)?,
+                result.output_amount,
+            )?;
+        }
+        self.execute_priced(&mut *pool, input_token, &result)?;
+        Ok(result)
+    }
+
+    /// What a swap on the locked pool pays, failing if it pays less than
+    /// `min_output`.
+    fn price_swap(
+        &self,
+        pool: &dyn AmmPool,
         input_token: &str,
         input_amount: Decimal,
         min_output: Decimal,
     ) -> Result<SwapResult, MarketMakerError> {
-        let quote = self.quote(token_pair, input_token, input_amount).await?;
+        let quote = self.quote_pool(pool, input_token, input_amount)?;
+        if !self.impact_adjusted(pool) {
+            if quote.output_amount < min_output {
+                return Err(MarketMakerError::SlippageExceeded);
+            }
+            return Ok(quote);
+        }
         
         println!(
             "Swap - input_amount: {}, output_amount: {}, min_output: {}, price_impact: {}",
@@ -104,38 +549,102 @@ impl AutomatedMarketMaker {
         
         self.slippage_protection.check_slippage(quote.output_amount, min_output)?;
         
-        let pools = self.pools.read().await;
-        let pool = pools.get(token_pair)
-            .ok_or(MarketMakerError::InvalidPoolParameters)?;
-        let mut pool = pool.write().await;
-        
-        let fee_amount = pool.calculate_fee(input_amount);
-        let net_output = quote.output_amount - fee_amount;
-        
-        pool.execute_swap(input_token, input_amount, net_output)?;
-        
+        // The quote is in output units, so the fee comes off it at the fee
+        // rate; `fee_amount` is reported in input units like every quote's.
+        let output_amount = round_down(quote.output_amount * (Decimal::ONE - pool.pool_info().fee_percentage));
+        if output_amount <= Decimal::ZERO {
+            return Err(MarketMakerError::InsufficientLiquidity);
+        }
         Ok(SwapResult {
             input_amount,
-            output_amount: net_output,
+            output_amount,
             price_impact: quote.price_impact,
-            fee_amount,
+            fee_amount: quote.fee_amount,
         })
     }
 
-    pub async fn get_pool_info(&self, token_pair: &str) -> Result<Pool, MarketMakerError> {
-        let pools = self.pools.read().await;
-        let pool = pools.get(token_pair)
-            .ok_or(MarketMakerError::InvalidPoolParameters)?;
+    /// Moves the locked pool's reserves by a swap `price_swap` priced.
+    fn execute_priced(&self, pool: &mut dyn AmmPool, input_token: &str, result: &SwapResult) -> Result<(), MarketMakerError> {
+        if self.impact_adjusted(pool) {
+            pool.execute_swap(input_token, result.input_amount, result.output_amount)
+        } else {
+            pool.swap_exact_input(input_token, result.input_amount, result.output_amount).map(|_| ())
+        }
+    }
+
+    pub async fn get_pool_info(&self, pool_key: &PoolKey) -> Result<Pool, MarketMakerError> {
+        let pool = self.pool(pool_key).await?;
         let pool = pool.read().await;
         Ok(pool.pool_info().clone())
     }
+
+    /// Time-weighted average price over the last `window`, a reference
+    /// price that a single swap cannot move far.
+    pub async fn twap(&self, pool_key: &PoolKey, window: Duration) -> Result<TwapPrice, MarketMakerError> {
+        let pool = self.pool(pool_key).await?;
+        let pool = pool.read().await;
+        pool.twap(window)
+    }
+
+    pub async fn geometric_twap(&self, pool_key: &PoolKey, window: Duration) -> Result<TwapPrice, MarketMakerError> {
+        let pool = self.pool(pool_key).await?;
+        let pool = pool.read().await;
+        pool.geometric_twap(window)
+    }
+
+    /// The legacy impact penalty only applies to constant product pools;
+    /// other curves always price with their own invariant.
+    fn impact_adjusted(&self, pool: &dyn AmmPool) -> bool {
+        self.pricing_mode == PricingMode::ImpactAdjusted && pool.pool_info().curve == PoolCurve::ConstantProduct
+    }
+
+    /// Every pool in key order, the order multi-pool operations lock them in.
+    pub(crate) fn ledger(&self) -> Option<&Arc<Ledger>> {
+        self.ledger.as_ref()
+    }
+
+    pub(crate) async fn pool_handles(&self) -> Vec<(PoolKey, SharedPool)> {
+        let mut handles: Vec<_> = self.pools
+            .read()
+            .await
+            .iter()
+            .map(|(key, pool)| (key.clone(), pool.clone()))
+            .collect();
+        handles.sort_by(|(a, _), (b, _)| a.cmp(b));
+        handles
+    }
+
+    async fn pool(&self, pool_key: &PoolKey) -> Result<SharedPool, MarketMakerError> {
+        // Re-canonicalize so keys built by hand in either order still match.
+        let pool_key = PoolKey::new(&pool_key.token_0, &pool_key.token_1, pool_key.fee_tier);
+        self.pools
+            .read()
+            .await
+            .get(&pool_key)
+            .cloned()
+            .ok_or(MarketMakerError::PoolNotFound(pool_key))
+    }
+}
+
+fn validate_pool(token_a: &str, token_b: &str, fee_percentage: Decimal) -> Result<(), MarketMakerError> {
+    if token_a == token_b || fee_percentage < Decimal::ZERO || fee_percentage >= Decimal::ONE {
+        return Err(MarketMakerError::InvalidPoolParameters);
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ledger::{LedgerAccount, LedgerError};
+    use crate::utils::clock::ManualClock;
+    use chrono::Utc;
     use rust_decimal_macros::dec;
 
+    fn usdc_eth() -> PoolKey {
+        PoolKey::new("USDC", "ETH", dec!(0.003))
+    }
+
     #[tokio::test]
     async fn test_pool_creation() {
         let amm = AutomatedMarketMaker::new();
@@ -154,6 +663,183 @@ mod tests {
         assert_eq!(pool.reserve_b, dec!(500));
     }
 
+    #[tokio::test]
+    async fn test_pool_lookup_is_order_independent() {
+        let amm = AutomatedMarketMaker::new();
+        amm.create_pool("USDC".to_string(), "ETH".to_string(), dec!(1000000), dec!(500), dec!(0.003)).await.unwrap();
+        amm.create_pool("ETH".to_string(), "USDC".to_string(), dec!(2000000), dec!(1000), dec!(0.0005)).await.unwrap();
+
+        // Same pair and fee tier in the other order is a duplicate.
+        assert!(matches!(
+            amm.create_pool("ETH".to_string(), "USDC".to_string(), dec!(1), dec!(1), dec!(0.0030)).await,
+            Err(MarketMakerError::InvalidPoolParameters)
+        ));
+        assert_eq!(amm.pools_for_pair("USDC", "ETH").await, amm.pools_for_pair("ETH", "USDC").await);
+        assert_eq!(amm.pools_for_pair("ETH", "USDC").await.len(), 2);
+
+        let reversed = PoolKey { token_0: "USDC".to_string(), token_1: "ETH".to_string(), fee_tier: dec!(0.003) };
+        let pool = amm.get_pool_info(&reversed).await.unwrap();
+        assert_eq!(pool.reserve_a, dec!(1000000));
+        assert!(matches!(
+            amm.get_pool_info(&PoolKey::new("USDC", "ETH", dec!(0.01))).await,
+            Err(MarketMakerError::PoolNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_token_outside_pool_is_rejected() {
+        for mode in [PricingMode::ConstantProduct, PricingMode::ImpactAdjusted] {
+            let amm = AutomatedMarketMaker::new().with_pricing_mode(mode);
+            amm.create_pool("USDC".to_string(), "ETH".to_string(), dec!(1000000), dec!(500), dec!(0.003)).await.unwrap();
+
+            assert!(matches!(
+                amm.quote(&usdc_eth(), "BTC", dec!(100)).await,
+                Err(MarketMakerError::TokenNotInPool(token)) if token == "BTC"
+            ));
+            assert!(matches!(
+                amm.swap(&usdc_eth(), "BTC", dec!(100), Decimal::ZERO).await,
+                Err(MarketMakerError::TokenNotInPool(_))
+            ));
+            assert_eq!(amm.get_pool_info(&usdc_eth()).await.unwrap().reserve_b, dec!(500));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_impact_adjusted_fee_comes_off_the_output() {
+        let amm = AutomatedMarketMaker::new().with_pricing_mode(PricingMode::ImpactAdjusted);
+        amm.create_pool("USDC".to_string(), "ETH".to_string(), dec!(1000000), dec!(500), dec!(0.003)).await.unwrap();
+
+        // The 0.03 USDC fee would exceed the ~0.005 ETH paid out if it were
+        // subtracted from the output as is.
+        let quote = amm.quote(&usdc_eth(), "USDC", dec!(10)).await.unwrap();
+        let swap = amm.swap(&usdc_eth(), "USDC", dec!(10), quote.output_amount).await.unwrap();
+        assert_eq!(swap.fee_amount, dec!(0.03));
+        assert!(swap.output_amount > Decimal::ZERO && swap.output_amount < quote.output_amount);
+        assert_eq!(swap.output_amount, round_down(quote.output_amount * dec!(0.997)));
+        assert_eq!(amm.get_pool_info(&usdc_eth()).await.unwrap().reserve_b, dec!(500) - swap.output_amount);
+
+        let dust = amm.quote(&usdc_eth(), "USDC", dec!(0.000001)).await.unwrap();
+        assert!(matches!(
+            amm.swap(&usdc_eth(), "USDC", dec!(0.000001), dust.output_amount).await,
+            Err(MarketMakerError::InsufficientLiquidity)
+        ));
+        assert_eq!(amm.get_pool_info(&usdc_eth()).await.unwrap().reserve_b, dec!(500) - swap.output_amount);
+    }
+
+    #[tokio::test]
+    async fn test_stable_pool_shares_the_pool_api() {
+        let amm = AutomatedMarketMaker::new().with_pricing_mode(PricingMode::ImpactAdjusted);
+        let pool = amm.create_stable_pool(
+            "USDC".to_string(),
+            "USDT".to_string(),
+            dec!(1000000),
+            dec!(1000000),
+            dec!(0.0004),
+            dec!(200),
+        ).await.unwrap();
+        assert_eq!(pool.curve, PoolCurve::StableSwap { amplification: dec!(200) });
+        assert!(matches!(
+            amm.create_pool("USDT".to_string(), "USDC".to_string(), dec!(1), dec!(1), dec!(0.0004)).await,
+            Err(MarketMakerError::InvalidPoolParameters)
+        ));
+
+        let key = pool.key();
+        let provider_id = uuid::Uuid::new_v4();
+        let deposit = amm.add_liquidity(&key, provider_id, dec!(1000), dec!(1000)).await.unwrap();
+        let swap = amm.swap(&key, "USDT", dec!(10000), dec!(9990)).await.unwrap();
+        assert!(swap.output_amount < dec!(10000));
+        let removed = amm.remove_liquidity(&key, provider_id, deposit.shares).await.unwrap();
+        assert!(removed.token_a_amount + removed.token_b_amount >= dec!(2000));
+
+        // About 1/1001 of the 4 USDT fee, claimable after withdrawing.
+        let earned = amm.earned_fees(&key, provider_id).await.unwrap();
+        assert!(earned.token_b_amount > dec!(0.0039) && earned.token_b_amount < dec!(0.004));
+        let claim = amm.claim_fees(&key, provider_id).await.unwrap();
+        assert_eq!(claim.token_b_amount, earned.token_b_amount);
+        assert_eq!(amm.fee_claims(&key, provider_id).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_weighted_pool_through_amm() {
+        let amm = AutomatedMarketMaker::new();
+        let tokens = ["BTC", "ETH", "SOL"]
+            .iter()
+            .zip([dec!(34), dec!(660), dec!(16500)])
+            .map(|(token, balance)| WeightedToken {
+                token: token.to_string(),
+                weight: if *token == "BTC" { dec!(0.34) } else { dec!(0.33) },
+                balance,
+            })
+            .collect();
+        let pool = amm.create_weighted_pool(tokens, dec!(0.003)).await.unwrap();
+
+        let provider_id = uuid::Uuid::new_v4();
+        let joined = amm.join_weighted(pool.id, provider_id, "SOL", dec!(1000)).await.unwrap();
+        amm.swap_weighted(pool.id, "BTC", "SOL", dec!(1), dec!(400)).await.unwrap();
+        let exited = amm.exit_weighted(pool.id, provider_id, "BTC", joined.shares).await.unwrap();
+        assert!(exited.amounts[0].1 > Decimal::ZERO);
+
+        let info = amm.get_weighted_pool_info(pool.id).await.unwrap();
+        assert_eq!(info.total_shares, pool.total_shares);
+        assert!(matches!(
+            amm.get_weighted_pool_info(uuid::Uuid::new_v4()).await,
+            Err(MarketMakerError::WeightedPoolNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unseeded_weighted_pool_funded_through_amm() {
+        let amm = AutomatedMarketMaker::new();
+        let tokens = ["BTC", "ETH"]
+            .into_iter()
+            .map(|token| WeightedToken {
+                token: token.to_string(),
+                weight: dec!(0.5),
+                balance: Decimal::ZERO,
+            })
+            .collect();
+        let pool = amm.create_weighted_pool(tokens, dec!(0.003)).await.unwrap();
+        let provider_id = uuid::Uuid::new_v4();
+        assert!(matches!(
+            amm.join_weighted(pool.id, provider_id, "BTC", dec!(1)).await,
+            Err(MarketMakerError::InsufficientLiquidity)
+        ));
+
+        let joined = amm.join_weighted_all(pool.id, provider_id, &[dec!(1), dec!(20)]).await.unwrap();
+        assert!(joined.shares > Decimal::ZERO);
+        assert!(matches!(
+            amm.join_weighted_all(pool.id, provider_id, &[dec!(1)]).await,
+            Err(MarketMakerError::InvalidAmount)
+        ));
+
+        let exited = amm.exit_weighted_all(pool.id, provider_id, joined.shares).await.unwrap();
+        assert_eq!(exited.amounts.len(), 2);
+        assert!(exited.amounts.iter().all(|(_, amount)| *amount > Decimal::ZERO));
+        assert!(matches!(
+            amm.exit_weighted_all(pool.id, provider_id, dec!(1)).await,
+            Err(MarketMakerError::InsufficientLiquidity)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_concentrated_pool_through_amm() {
+        let amm = AutomatedMarketMaker::new();
+        let pool = amm.create_concentrated_pool("ETH".to_string(), "USDC".to_string(), dec!(0.003), 60, dec!(2000)).await.unwrap();
+
+        let provider_id = uuid::Uuid::new_v4();
+        let (lower, upper) = (pool.tick - pool.tick % 60 - 600, pool.tick - pool.tick % 60 + 600);
+        let minted = amm.mint_range(pool.id, provider_id, lower, upper, dec!(1000)).await.unwrap();
+        assert!(minted.amount_0 > Decimal::ZERO && minted.amount_1 > Decimal::ZERO);
+
+        let swap = amm.swap_concentrated(pool.id, "USDC", dec!(100), dec!(0.04)).await.unwrap();
+        assert!(swap.output_amount < dec!(0.05));
+        let (fees_0, fees_1) = amm.collect_range_fees(pool.id, provider_id, lower, upper).await.unwrap();
+        assert_eq!((fees_0, fees_1), (Decimal::ZERO, dec!(0.3)));
+
+        amm.burn_range(pool.id, provider_id, lower, upper, dec!(1000)).await.unwrap();
+        assert_eq!(amm.get_concentrated_pool_info(pool.id).await.unwrap().liquidity, Decimal::ZERO);
+    }
+
     #[tokio::test]
     async fn test_liquidity_provision() {
         let amm = AutomatedMarketMaker::new();
@@ -168,7 +854,7 @@ mod tests {
         
         let provider_id = uuid::Uuid::new_v4();
         let position = amm.add_liquidity(
-            "USDC-ETH",
+            &usdc_eth(),
             provider_id,
             dec!(10000),
             dec!(5),
@@ -176,6 +862,197 @@ mod tests {
         
         assert_eq!(position.token_a_amount, dec!(10000));
         assert_eq!(position.token_b_amount, dec!(5));
+
+        let half = position.shares / dec!(2);
+        let removed = amm.remove_liquidity(&usdc_eth(), provider_id, half).await.unwrap();
+        assert_eq!(removed.token_b_amount.round_dp(6), dec!(2.5));
+        let remaining = amm.position(&usdc_eth(), provider_id).await.unwrap();
+        assert_eq!(remaining.shares, position.shares - half);
+    }
+
+    #[tokio::test]
+    async fn test_swap_for_account_posts_to_ledger() {
+        let ledger = Arc::new(Ledger::new());
+        let amm = AutomatedMarketMaker::new().with_ledger(ledger.clone());
+
+        amm.create_pool(
+            "USDC".to_string(),
+            "USDT".to_string(),
+            dec!(1000000),
+            dec!(1000000),
+            dec!(0.003),
+        ).await.unwrap();
+
+        let trader = uuid::Uuid::new_v4();
+        ledger.deposit(trader, "USDC", dec!(1000)).unwrap();
+        let result = amm.swap_for_account(trader, &PoolKey::new("USDC", "USDT", dec!(0.003)), "USDC", dec!(1000), dec!(980)).await.unwrap();
+
+        let account = ledger.account(trader);
+        assert_eq!(account.get_balance("USDC"), Decimal::ZERO);
+        assert_eq!(account.get_balance("USDT"), result.output_amount);
+        ledger.check_invariants().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unfunded_swap_leaves_pool_untouched() {
+        let ledger = Arc::new(Ledger::new());
+        let amm = AutomatedMarketMaker::new().with_ledger(ledger.clone());
+        let key = PoolKey::new("USDC", "USDT", dec!(0.003));
+        amm.create_pool("USDC".to_string(), "USDT".to_string(), dec!(1000000), dec!(1000000), dec!(0.003)).await.unwrap();
+
+        let trader = uuid::Uuid::new_v4();
+        ledger.deposit(trader, "USDC", dec!(999)).unwrap();
+        let before = amm.get_pool_info(&key).await.unwrap();
+        assert!(matches!(
+            amm.swap_for_account(trader, &key, "USDC", dec!(1000), Decimal::ZERO).await,
+            Err(MarketMakerError::Ledger(LedgerError::InsufficientBalance { .. }))
+        ));
+
+        let after = amm.get_pool_info(&key).await.unwrap();
+        assert_eq!((after.reserve_a, after.reserve_b), (before.reserve_a, before.reserve_b));
+        assert_eq!(ledger.account(trader).get_balance("USDC"), dec!(999));
+        ledger.check_invariants().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_liquidity_is_journaled() {
+        let ledger = Arc::new(Ledger::new());
+        let amm = AutomatedMarketMaker::new().with_ledger(ledger.clone());
+        let pool = amm.create_pool("USDC".to_string(), "ETH".to_string(), dec!(0), dec!(0), dec!(0.003)).await.unwrap();
+        let pool_account = LedgerAccount::Pool(pool.id);
+
+        let provider = uuid::Uuid::new_v4();
+        assert!(matches!(
+            amm.add_liquidity(&usdc_eth(), provider, dec!(20000), dec!(10)).await,
+            Err(MarketMakerError::Ledger(LedgerError::InsufficientBalance { .. }))
+        ));
+
+        ledger.deposit(provider, "USDC", dec!(25000)).unwrap();
+        ledger.deposit(provider, "ETH", dec!(15)).unwrap();
+        let added = amm.add_liquidity(&usdc_eth(), provider, dec!(20000), dec!(10)).await.unwrap();
+        assert_eq!(ledger.balance(&pool_account, "USDC"), added.token_a_amount);
+        assert_eq!(ledger.balance(&pool_account, "ETH"), added.token_b_amount);
+
+        // The excess over the reserve ratio is handed back to the provider.
+        let topped_up = amm.add_liquidity(&usdc_eth(), provider, dec!(5000), dec!(5)).await.unwrap();
+        assert_eq!(topped_up.refund_b, dec!(2.5));
+        assert_eq!(ledger.account(provider).get_balance("ETH"), dec!(2.5));
+        assert_eq!(ledger.balance(&pool_account, "ETH"), dec!(12.5));
+
+        let trader = uuid::Uuid::new_v4();
+        ledger.deposit(trader, "USDC", dec!(1000)).unwrap();
+        amm.swap_for_account(trader, &usdc_eth(), "USDC", dec!(1000), Decimal::ZERO).await.unwrap();
+
+        let claim = amm.claim_fees(&usdc_eth(), provider).await.unwrap();
+        assert!(claim.token_a_amount > Decimal::ZERO);
+        amm.remove_liquidity(&usdc_eth(), provider, added.shares + topped_up.shares).await.unwrap();
+
+        // The pool's account holds the locked minimum's reserves, plus the
+        // protocol's cut of the fee on the USDC side.
+        let info = amm.get_pool_info(&usdc_eth()).await.unwrap();
+        assert!(ledger.balance(&pool_account, "USDC") > info.reserve_a);
+        assert_eq!(ledger.balance(&pool_account, "ETH"), info.reserve_b);
+        ledger.check_invariants().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rejected_payout_keeps_shares_and_fees() {
+        let ledger = Arc::new(Ledger::new());
+        let amm = AutomatedMarketMaker::new().with_ledger(ledger.clone());
+        let pool = amm.create_pool("USDC".to_string(), "ETH".to_string(), dec!(0), dec!(0), dec!(0.003)).await.unwrap();
+        let provider = uuid::Uuid::new_v4();
+        ledger.deposit(provider, "USDC", dec!(20000)).unwrap();
+        ledger.deposit(provider, "ETH", dec!(10)).unwrap();
+        let added = amm.add_liquidity(&usdc_eth(), provider, dec!(20000), dec!(10)).await.unwrap();
+        let trader = uuid::Uuid::new_v4();
+        ledger.deposit(trader, "USDC", dec!(1000)).unwrap();
+        amm.swap_for_account(trader, &usdc_eth(), "USDC", dec!(1000), Decimal::ZERO).await.unwrap();
+
+        // Drain the pool's ledger balance so every payout is refused.
+        let drained = ledger.balance(&LedgerAccount::Pool(pool.id), "USDC");
+        ledger.record_liquidity(uuid::Uuid::new_v4(), pool.id, &[("USDC", -drained)]).unwrap();
+
+        let earned = amm.earned_fees(&usdc_eth(), provider).await.unwrap();
+        assert!(matches!(
+            amm.claim_fees(&usdc_eth(), provider).await,
+            Err(MarketMakerError::Ledger(LedgerError::InsufficientBalance { .. }))
+        ));
+        assert_eq!(amm.earned_fees(&usdc_eth(), provider).await.unwrap().token_a_amount, earned.token_a_amount);
+        assert!(matches!(
+            amm.remove_liquidity(&usdc_eth(), provider, added.shares).await,
+            Err(MarketMakerError::Ledger(LedgerError::InsufficientBalance { .. }))
+        ));
+        assert_eq!(amm.position(&usdc_eth(), provider).await.unwrap().shares, added.shares);
+        ledger.check_invariants().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failed_zap_keeps_reserves_in_step_with_ledger() {
+        let ledger = Arc::new(Ledger::new());
+        let amm = AutomatedMarketMaker::new().with_ledger(ledger.clone());
+        let pool = amm.create_pool("USDC".to_string(), "ETH".to_string(), dec!(1000000000), dec!(1), dec!(0.003)).await.unwrap();
+        let provider = uuid::Uuid::new_v4();
+        ledger.deposit(provider, "USDC", dec!(0.0001)).unwrap();
+
+        assert!(amm.zap_in(&usdc_eth(), provider, "USDC", dec!(0.0001)).await.is_err());
+        assert_eq!(ledger.account(provider).get_balance("USDC"), dec!(0.0001));
+        let info = amm.get_pool_info(&usdc_eth()).await.unwrap();
+        assert_eq!(ledger.balance(&LedgerAccount::Pool(pool.id), "USDC"), info.reserve_a);
+        ledger.check_invariants().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_swap_without_account_refused_on_ledger() {
+        let ledger = Arc::new(Ledger::new());
+        let amm = AutomatedMarketMaker::new().with_ledger(ledger);
+        amm.create_pool("USDC".to_string(), "ETH".to_string(), dec!(1000000), dec!(500), dec!(0.003)).await.unwrap();
+
+        assert!(matches!(
+            amm.swap(&usdc_eth(), "USDC", dec!(1000), Decimal::ZERO).await,
+            Err(MarketMakerError::AccountRequired)
+        ));
+        assert_eq!(amm.get_pool_info(&usdc_eth()).await.unwrap().reserve_a, dec!(1000000));
+    }
+
+    #[tokio::test]
+    async fn test_constant_product_swap_takes_fee_from_input() {
+        let amm = AutomatedMarketMaker::new();
+        amm.create_pool(
+            "USDC".to_string(),
+            "ETH".to_string(),
+            dec!(1000000),
+            dec!(500),
+            dec!(0.003),
+        ).await.unwrap();
+
+        let quote = amm.quote(&usdc_eth(), "USDC", dec!(50000)).await.unwrap();
+        let swap = amm.swap(&usdc_eth(), "USDC", dec!(50000), quote.output_amount).await.unwrap();
+        assert_eq!(swap.output_amount, quote.output_amount);
+        assert_eq!(swap.fee_amount, dec!(150));
+
+        let pool = amm.get_pool_info(&usdc_eth()).await.unwrap();
+        // The fee is set aside for the LPs rather than added to the reserves.
+        assert_eq!(pool.reserve_a, dec!(1049850));
+        assert!(pool.reserve_a * pool.reserve_b >= dec!(1000000) * dec!(500));
+    }
+
+    #[tokio::test]
+    async fn test_twap_through_amm() {
+        let clock = Arc::new(ManualClock::new(Utc::now()));
+        let amm = AutomatedMarketMaker::new().with_clock(clock.clone());
+        amm.create_pool("USDC".to_string(), "ETH".to_string(), dec!(0), dec!(0), dec!(0.003)).await.unwrap();
+        assert!(matches!(
+            amm.twap(&usdc_eth(), Duration::seconds(1)).await,
+            Err(MarketMakerError::InsufficientPriceHistory)
+        ));
+
+        // The first deposit sets the price.
+        amm.add_liquidity(&usdc_eth(), uuid::Uuid::new_v4(), dec!(1000000), dec!(500)).await.unwrap();
+        clock.advance(Duration::minutes(10));
+        let twap = amm.twap(&usdc_eth(), Duration::minutes(10)).await.unwrap();
+        assert_eq!((twap.price_a, twap.price_b), (dec!(0.0005), dec!(2000)));
+        let geometric = amm.geometric_twap(&usdc_eth(), Duration::minutes(5)).await.unwrap();
+        assert_eq!(geometric.price_b.round_dp(8), dec!(2000));
     }
 
     #[tokio::test]
@@ -192,7 +1069,7 @@ mod tests {
         
         // Perform a swap and get a quote
         let result = amm.swap(
-            "USDC/ETH",
+            &PoolKey::new("ETH", "USDC", dec!(0.003)),
             "USDC",
             dec!(50000),
             dec!(23), // Set min_output slightly below expected
@@ -235,7 +1112,7 @@ mod tests {
         
         // Perform a swap with min_output set significantly higher to trigger slippage protection
         let result = amm.swap(
-            "USDC/ETH",
+            &PoolKey::new("ETH", "USDC", dec!(0.003)),
             "USDC",
             dec!(1000),
             dec!(1), // Set min_output higher than possible
@@ -0,0 +1,593 @@
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::{Decimal, MathematicalOps};
+use rust_decimal_macros::dec;
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+
+use super::pool::{round_down, round_up};
+use super::types::{ConcentratedPoolInfo, ConcentratedPosition, MarketMakerError, RangeLiquidityChange, SwapResult};
+
+/// Each tick moves the price by one basis point.
+const TICK_BASE: Decimal = dec!(1.0001);
+/// Half of Uniswap v3's range, which keeps square root prices and token
+/// amounts well inside what `Decimal` represents.
+pub const MIN_TICK: i32 = -443636;
+pub const MAX_TICK: i32 = 443636;
+
+pub fn sqrt_price_at_tick(tick: i32) -> Decimal {
+    TICK_BASE.powd(Decimal::from(tick) / Decimal::TWO)
+}
+
+/// Greatest tick whose price is at or below `sqrt_price`.
+pub fn tick_at_sqrt_price(sqrt_price: Decimal) -> i32 {
+    let estimate = (sqrt_price.ln() * Decimal::TWO / TICK_BASE.ln())
+        .floor()
+        .to_i32()
+        .unwrap_or_default()
+        .clamp(MIN_TICK, MAX_TICK);
+    // The logarithm can land one tick off right at a boundary.
+    if estimate < MAX_TICK && sqrt_price_at_tick(estimate + 1) <= sqrt_price {
+        estimate + 1
+    } else if sqrt_price_at_tick(estimate) > sqrt_price {
+        estimate - 1
+    } else {
+        estimate
+    }
+}
+
+/// `token_0` held by `liquidity` between two square root prices.
+fn amount_0_delta(sqrt_lower: Decimal, sqrt_upper: Decimal, liquidity: Decimal) -> Decimal {
+    liquidity * (sqrt_upper - sqrt_lower) / (sqrt_lower * sqrt_upper)
+}
+
+/// `token_1` held by `liquidity` between two square root prices.
+fn amount_1_delta(sqrt_lower: Decimal, sqrt_upper: Decimal, liquidity: Decimal) -> Decimal {
+    liquidity * (sqrt_upper - sqrt_lower)
+}
+
+#[derive(Debug, Clone, Default)]
+struct TickInfo {
+    liquidity_gross: Decimal,
+    /// Liquidity added when the price crosses the tick upwards.
+    liquidity_net: Decimal,
+    /// Fee growth on the side of the tick away from the current price.
+    fee_growth_outside_0: Decimal,
+    fee_growth_outside_1: Decimal,
+}
+
+/// Outcome of walking the curve for a swap, applied only if it completes.
+struct SwapPlan {
+    result: SwapResult,
+    sqrt_price: Decimal,
+    tick: i32,
+    liquidity: Decimal,
+    fee_growth_global_in: Decimal,
+    /// Ticks crossed, with the input token's global fee growth at the time.
+    crossed: Vec<(i32, Decimal)>,
+}
+
+/// Uniswap v3-style pool where liquidity is provided over price ranges.
+///
+/// Prices are quantized to ticks, `price = 1.0001 ^ tick`. A position's
+/// liquidity is only active while the price is inside its range, so
+/// swaps move through ranges of constant liquidity and cross ticks where
+/// positions start or end. The fee is taken from the input and credited to
+/// in-range liquidity through per-token fee growth accumulators; each
+/// position tracks the growth inside its range to know what it has earned.
+pub struct ConcentratedPool {
+    info: ConcentratedPoolInfo,
+    ticks: BTreeMap<i32, TickInfo>,
+    positions: HashMap<(Uuid, i32, i32), ConcentratedPosition>,
+}
+
+impl ConcentratedPool {
+    /// Opens an empty pool at `price`, in `token_1` per `token_0`.
+    pub fn new(
+        token_0: String,
+        token_1: String,
+        fee_percentage: Decimal,
+        tick_spacing: i32,
+        price: Decimal,
+    ) -> Result<Self, MarketMakerError> {
+        if token_0 == token_1
+            || tick_spacing <= 0
+            || fee_percentage < Decimal::ZERO
+            || fee_percentage >= Decimal::ONE
+            || price <= Decimal::ZERO
+        {
+            return Err(MarketMakerError::InvalidPoolParameters);
+        }
+        let sqrt_price = price.sqrt().ok_or(MarketMakerError::InvalidPoolParameters)?;
+        if sqrt_price < sqrt_price_at_tick(MIN_TICK) || sqrt_price >= sqrt_price_at_tick(MAX_TICK) {
+            return Err(MarketMakerError::InvalidPoolParameters);
+        }
+
+        Ok(Self {
+            info: ConcentratedPoolInfo {
+                id: Uuid::new_v4(),
+                token_0,
+                token_1,
+                fee_percentage,
+                tick_spacing,
+                sqrt_price,
+                tick: tick_at_sqrt_price(sqrt_price),
+                liquidity: Decimal::ZERO,
+                fee_growth_global_0: Decimal::ZERO,
+                fee_growth_global_1: Decimal::ZERO,
+            },
+            ticks: BTreeMap::new(),
+            positions: HashMap::new(),
+        })
+    }
+
+    pub fn pool_info(&self) -> &ConcentratedPoolInfo {
+        &self.info
+    }
+
+    pub fn price(&self) -> Decimal {
+        self.info.sqrt_price * self.info.sqrt_price
+    }
+
+    /// The position with its fees accrued up to now.
+    pub fn position(&self, provider_id: Uuid, tick_lower: i32, tick_upper: i32) -> Option<ConcentratedPosition> {
+        let mut position = self.positions.get(&(provider_id, tick_lower, tick_upper))?.clone();
+        let (inside_0, inside_1) = self.fee_growth_inside(tick_lower, tick_upper);
+        accrue(&mut position, inside_0, inside_1);
+        Some(position)
+    }
+
+    /// Most liquidity the given amounts can provide over a range at the
+    /// current price.
+    pub fn liquidity_for_amounts(
+        &self,
+        tick_lower: i32,
+        tick_upper: i32,
+        amount_0: Decimal,
+        amount_1: Decimal,
+    ) -> Result<Decimal, MarketMakerError> {
+        self.check_range(tick_lower, tick_upper)?;
+        let (sqrt_lower, sqrt_upper) = (sqrt_price_at_tick(tick_lower), sqrt_price_at_tick(tick_upper));
+        let sqrt_price = self.info.sqrt_price;
+        let from_0 = |lower: Decimal| amount_0 * lower * sqrt_upper / (sqrt_upper - lower);
+        let from_1 = |upper: Decimal| amount_1 / (upper - sqrt_lower);
+        let liquidity = if sqrt_price <= sqrt_lower {
+            from_0(sqrt_lower)
+        } else if sqrt_price < sqrt_upper {
+            from_0(sqrt_price).min(from_1(sqrt_price))
+        } else {
+            from_1(sqrt_upper)
+        };
+        Ok(round_down(liquidity))
+    }
+
+    /// Adds `liquidity` over `[tick_lower, tick_upper)`, returning the token
+    /// amounts it costs, rounded up.
+    pub fn mint(
+        &mut self,
+        provider_id: Uuid,
+        tick_lower: i32,
+        tick_upper: i32,
+        liquidity: Decimal,
+    ) -> Result<RangeLiquidityChange, MarketMakerError> {
+        self.check_range(tick_lower, tick_upper)?;
+        if liquidity <= Decimal::ZERO {
+            return Err(MarketMakerError::InvalidAmount);
+        }
+
+        self.update_tick(tick_lower, liquidity, false);
+        self.update_tick(tick_upper, liquidity, true);
+        let (amount_0, amount_1) = self.amounts_for(tick_lower, tick_upper, liquidity);
+        self.update_position(provider_id, tick_lower, tick_upper, liquidity);
+
+        Ok(RangeLiquidityChange {
+            pool_id: self.info.id,
+            provider_id,
+            tick_lower,
+            tick_upper,
+            liquidity,
+            amount_0: round_up(amount_0),
+            amount_1: round_up(amount_1),
+        })
+    }
+
+    /// Removes `liquidity` from a position and returns its principal,
+    /// rounded down. Earned fees stay owed to the position until collected.
+    pub fn burn(
+        &mut self,
+        provider_id: Uuid,
+        tick_lower: i32,
+        tick_upper: i32,
+        liquidity: Decimal,
+    ) -> Result<RangeLiquidityChange, MarketMakerError> {
+        let held = self
+            .positions
+            .get(&(provider_id, tick_lower, tick_upper))
+            .map(|position| position.liquidity)
+            .unwrap_or_default();
+        if liquidity <= Decimal::ZERO || liquidity > held {
+            return Err(MarketMakerError::InsufficientLiquidity);
+        }
+
+        // Settle fees before the ticks holding the range's history can be
+        // cleared.
+        self.update_position(provider_id, tick_lower, tick_upper, -liquidity);
+        self.update_tick(tick_lower, -liquidity, false);
+        self.update_tick(tick_upper, -liquidity, true);
+        let (amount_0, amount_1) = self.amounts_for(tick_lower, tick_upper, liquidity);
+
+        Ok(RangeLiquidityChange {
+            pool_id: self.info.id,
+            provider_id,
+            tick_lower,
+            tick_upper,
+            liquidity,
+            amount_0: round_down(amount_0),
+            amount_1: round_down(amount_1),
+        })
+    }
+
+    /// Pays out the fees a position has earned, rounded down, as
+    /// `(token_0, token_1)`.
+    pub fn collect(&mut self, provider_id: Uuid, tick_lower: i32, tick_upper: i32) -> Result<(Decimal, Decimal), MarketMakerError> {
+        let key = (provider_id, tick_lower, tick_upper);
+        if !self.positions.contains_key(&key) {
+            return Err(MarketMakerError::InsufficientLiquidity);
+        }
+        self.update_position(provider_id, tick_lower, tick_upper, Decimal::ZERO);
+
+        let position = self.positions.get_mut(&key).ok_or(MarketMakerError::InsufficientLiquidity)?;
+        let collected = (round_down(position.tokens_owed_0), round_down(position.tokens_owed_1));
+        position.tokens_owed_0 -= collected.0;
+        position.tokens_owed_1 -= collected.1;
+        if position.liquidity.is_zero() {
+            self.positions.remove(&key);
+        }
+        Ok(collected)
+    }
+
+    pub fn quote_exact_input(&self, input_token: &str, input_amount: Decimal) -> Result<SwapResult, MarketMakerError> {
+        Ok(self.plan_swap(input_token, input_amount)?.result)
+    }
+
+    /// Swaps through as many ranges as needed. Fails without changing the
+    /// pool if liquidity runs out or the output is below `min_output`.
+    pub fn swap_exact_input(
+        &mut self,
+        input_token: &str,
+        input_amount: Decimal,
+        min_output: Decimal,
+    ) -> Result<SwapResult, MarketMakerError> {
+        let plan = self.plan_swap(input_token, input_amount)?;
+        if plan.result.output_amount < min_output {
+            return Err(MarketMakerError::SlippageExceeded);
+        }
+
+        let zero_for_one = input_token == self.info.token_0;
+        for (tick, growth_in) in plan.crossed {
+            let (growth_0, growth_1) = if zero_for_one {
+                (growth_in, self.info.fee_growth_global_1)
+            } else {
+                (self.info.fee_growth_global_0, growth_in)
+            };
+            if let Some(info) = self.ticks.get_mut(&tick) {
+                info.fee_growth_outside_0 = growth_0 - info.fee_growth_outside_0;
+                info.fee_growth_outside_1 = growth_1 - info.fee_growth_outside_1;
+            }
+        }
+        if zero_for_one {
+            self.info.fee_growth_global_0 = plan.fee_growth_global_in;
+        } else {
+            self.info.fee_growth_global_1 = plan.fee_growth_global_in;
+        }
+        self.info.sqrt_price = plan.sqrt_price;
+        self.info.tick = plan.tick;
+        self.info.liquidity = plan.liquidity;
+        Ok(plan.result)
+    }
+
+    fn plan_swap(&self, input_token: &str, input_amount: Decimal) -> Result<SwapPlan, MarketMakerError> {
+        if input_amount <= Decimal::ZERO {
+            return Err(MarketMakerError::InvalidAmount);
+        }
+        let zero_for_one = if input_token == self.info.token_0 {
+            true
+        } else if input_token == self.info.token_1 {
+            false
+        } else {
+            return Err(MarketMakerError::TokenNotInPool(input_token.to_string()));
+        };
+
+        let fee = self.info.fee_percentage;
+        let mut sqrt_price = self.info.sqrt_price;
+        let mut tick = self.info.tick;
+        let mut liquidity = self.info.liquidity;
+        let mut fee_growth = if zero_for_one { self.info.fee_growth_global_0 } else { self.info.fee_growth_global_1 };
+        let mut remaining = input_amount;
+        let mut output = Decimal::ZERO;
+        let mut crossed = Vec::new();
+
+        while remaining > Decimal::ZERO {
+            let next_tick = if zero_for_one {
+                self.ticks.range(..=tick).next_back().map(|(tick, _)| *tick)
+            } else {
+                self.ticks.range(tick + 1..).next().map(|(tick, _)| *tick)
+            };
+            let Some(next_tick) = next_tick else {
+                return Err(MarketMakerError::InsufficientLiquidity);
+            };
+            let target = sqrt_price_at_tick(next_tick);
+
+            let less_fee = remaining * (Decimal::ONE - fee);
+            let to_target = if zero_for_one {
+                amount_0_delta(target, sqrt_price, liquidity)
+            } else {
+                amount_1_delta(sqrt_price, target, liquidity)
+            };
+            let (next_price, amount_in, fee_amount) = if less_fee >= to_target {
+                (target, to_target, to_target * fee / (Decimal::ONE - fee))
+            } else if zero_for_one {
+                (liquidity * sqrt_price / (liquidity + less_fee * sqrt_price), less_fee, remaining - less_fee)
+            } else {
+                (sqrt_price + less_fee / liquidity, less_fee, remaining - less_fee)
+            };
+
+            output += if zero_for_one {
+                amount_1_delta(next_price, sqrt_price, liquidity)
+            } else {
+                amount_0_delta(sqrt_price, next_price, liquidity)
+            };
+            remaining -= amount_in + fee_amount;
+            if liquidity > Decimal::ZERO {
+                fee_growth += fee_amount / liquidity;
+            }
+            sqrt_price = next_price;
+
+            if next_price == target {
+                crossed.push((next_tick, fee_growth));
+                let net = self.ticks.get(&next_tick).map(|info| info.liquidity_net).unwrap_or_default();
+                if zero_for_one {
+                    liquidity -= net;
+                    tick = next_tick - 1;
+                } else {
+                    liquidity += net;
+                    tick = next_tick;
+                }
+            } else {
+                tick = tick_at_sqrt_price(sqrt_price);
+            }
+        }
+
+        let output_amount = round_down(output);
+        let fee_amount = input_amount * fee;
+        let spot = if zero_for_one {
+            self.price()
+        } else {
+            Decimal::ONE / self.price()
+        };
+        let price_impact = (Decimal::ONE - output_amount / ((input_amount - fee_amount) * spot)).max(Decimal::ZERO);
+
+        Ok(SwapPlan {
+            result: SwapResult {
+                input_amount,
+                output_amount,
+                price_impact,
+                fee_amount,
+            },
+            sqrt_price,
+            tick,
+            liquidity,
+            fee_growth_global_in: fee_growth,
+            crossed,
+        })
+    }
+
+    fn check_range(&self, tick_lower: i32, tick_upper: i32) -> Result<(), MarketMakerError> {
+        let spacing = self.info.tick_spacing;
+        if tick_lower >= tick_upper
+            || tick_lower < MIN_TICK
+            || tick_upper > MAX_TICK
+            || tick_lower % spacing != 0
+            || tick_upper % spacing != 0
+        {
+            return Err(MarketMakerError::InvalidTickRange(tick_lower, tick_upper));
+        }
+        Ok(())
+    }
+
+    /// Token amounts backing `liquidity` over a range at the current price.
+    fn amounts_for(&self, tick_lower: i32, tick_upper: i32, liquidity: Decimal) -> (Decimal, Decimal) {
+        let (sqrt_lower, sqrt_upper) = (sqrt_price_at_tick(tick_lower), sqrt_price_at_tick(tick_upper));
+        let sqrt_price = self.info.sqrt_price;
+        if self.info.tick < tick_lower {
+            (amount_0_delta(sqrt_lower, sqrt_upper, liquidity), Decimal::ZERO)
+        } else if self.info.tick < tick_upper {
+            (
+                amount_0_delta(sqrt_price, sqrt_upper, liquidity),
+                amount_1_delta(sqrt_lower, sqrt_price, liquidity),
+            )
+        } else {
+            (Decimal::ZERO, amount_1_delta(sqrt_lower, sqrt_upper, liquidity))
+        }
+    }
+
+    /// Adds `delta` liquidity at a range boundary, initializing or clearing
+    /// the tick as needed.
+    fn update_tick(&mut self, tick: i32, delta: Decimal, upper: bool) {
+        let current = self.info.tick;
+        let (global_0, global_1) = (self.info.fee_growth_global_0, self.info.fee_growth_global_1);
+        let info = self.ticks.entry(tick).or_insert_with(|| {
+            // By convention all growth so far happened below the tick if the
+            // price is at or above it.
+            if tick <= current {
+                TickInfo {
+                    fee_growth_outside_0: global_0,
+                    fee_growth_outside_1: global_1,
+                    ..TickInfo::default()
+                }
+            } else {
+                TickInfo::default()
+            }
+        });
+        info.liquidity_gross += delta;
+        info.liquidity_net += if upper { -delta } else { delta };
+        if info.liquidity_gross.is_zero() {
+            self.ticks.remove(&tick);
+        }
+    }
+
+    fn update_position(&mut self, provider_id: Uuid, tick_lower: i32, tick_upper: i32, delta: Decimal) {
+        let (inside_0, inside_1) = self.fee_growth_inside(tick_lower, tick_upper);
+        let pool_id = self.info.id;
+        let position = self
+            .positions
+            .entry((provider_id, tick_lower, tick_upper))
+            .or_insert_with(|| ConcentratedPosition {
+                pool_id,
+                provider_id,
+                tick_lower,
+                tick_upper,
+                liquidity: Decimal::ZERO,
+                fee_growth_inside_0_last: inside_0,
+                fee_growth_inside_1_last: inside_1,
+                tokens_owed_0: Decimal::ZERO,
+                tokens_owed_1: Decimal::ZERO,
+            });
+        accrue(position, inside_0, inside_1);
+        position.liquidity += delta;
+
+        if self.info.tick >= tick_lower && self.info.tick < tick_upper {
+            self.info.liquidity += delta;
+        }
+    }
+
+    /// Fee growth per unit of liquidity earned inside a range.
+    fn fee_growth_inside(&self, tick_lower: i32, tick_upper: i32) -> (Decimal, Decimal) {
+        let (global_0, global_1) = (self.info.fee_growth_global_0, self.info.fee_growth_global_1);
+        let outside = |tick: i32| {
+            self.ticks
+                .get(&tick)
+                .map(|info| (info.fee_growth_outside_0, info.fee_growth_outside_1))
+                .unwrap_or_default()
+        };
+        let (lower_0, lower_1) = outside(tick_lower);
+        let (upper_0, upper_1) = outside(tick_upper);
+        let (below_0, below_1) = if self.info.tick >= tick_lower {
+            (lower_0, lower_1)
+        } else {
+            (global_0 - lower_0, global_1 - lower_1)
+        };
+        let (above_0, above_1) = if self.info.tick < tick_upper {
+            (upper_0, upper_1)
+        } else {
+            (global_0 - upper_0, global_1 - upper_1)
+        };
+        (global_0 - below_0 - above_0, global_1 - below_1 - above_1)
+    }
+}
+
+/// Credits a position with the fee growth inside its range since it was
+/// last updated.
+fn accrue(position: &mut ConcentratedPosition, inside_0: Decimal, inside_1: Decimal) {
+    position.tokens_owed_0 += position.liquidity * (inside_0 - position.fee_growth_inside_0_last);
+    position.tokens_owed_1 += position.liquidity * (inside_1 - position.fee_growth_inside_1_last);
+    position.fee_growth_inside_0_last = inside_0;
+    position.fee_growth_inside_1_last = inside_1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reference values below were computed independently from the v3
+    // whitepaper formulas at 60 significant digits.
+
+    fn assert_close(actual: Decimal, expected: Decimal) {
+        assert!((actual - expected).abs() <= dec!(0.00000002), "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn test_tick_math_reference_vectors() {
+        assert_eq!(sqrt_price_at_tick(0), Decimal::ONE);
+        assert_close(sqrt_price_at_tick(1), dec!(1.000049998750062496));
+        assert_close(sqrt_price_at_tick(-1), dec!(0.999950003749687527));
+        assert_close(sqrt_price_at_tick(100), dec!(1.005012269623051203));
+        assert_close(sqrt_price_at_tick(-100000), dec!(0.006739631584094860));
+
+        for tick in [-100000, -1, 0, 1, 85176, 200000] {
+            assert_eq!(tick_at_sqrt_price(sqrt_price_at_tick(tick)), tick);
+        }
+        assert_eq!(tick_at_sqrt_price(dec!(5000).sqrt().unwrap()), 85176);
+    }
+
+    #[test]
+    fn test_mint_and_swap_within_range() {
+        // 5000 USDC/ETH with liquidity over 4545..5500.
+        let mut pool = ConcentratedPool::new("ETH".to_string(), "USDC".to_string(), Decimal::ZERO, 1, dec!(5000)).unwrap();
+        let liquidity = dec!(1517.882343751509868544);
+        let minted = pool.mint(Uuid::new_v4(), 84222, 86129, liquidity).unwrap();
+        assert_close(minted.amount_0, dec!(0.998628802115142752));
+        assert_close(minted.amount_1, dec!(5000.209190920485557568));
+        assert_eq!(pool.pool_info().liquidity, liquidity);
+
+        let swap = pool.swap_exact_input("USDC", dec!(42), Decimal::ZERO).unwrap();
+        assert_close(swap.output_amount, dec!(0.008396714242162445));
+        assert_close(pool.price(), dec!(5003.913912782393110970));
+        assert_eq!(pool.pool_info().tick, 85184);
+    }
+
+    #[test]
+    fn test_swap_crosses_ticks_and_splits_fees() {
+        let mut pool = ConcentratedPool::new("A".to_string(), "B".to_string(), dec!(0.003), 60, Decimal::ONE).unwrap();
+        let (wide, upper) = (Uuid::new_v4(), Uuid::new_v4());
+        pool.mint(wide, -600, 600, dec!(1000)).unwrap();
+        // Out of range: all token_0 and no active liquidity yet.
+        let minted = pool.mint(upper, 600, 1200, dec!(2000)).unwrap();
+        assert_eq!(minted.amount_1, Decimal::ZERO);
+        assert_eq!(pool.pool_info().liquidity, dec!(1000));
+
+        let swap = pool.swap_exact_input("B", dec!(50), Decimal::ZERO).unwrap();
+        assert_close(swap.output_amount, dec!(47.650155029502990079));
+        assert_close(pool.price(), dec!(1.081915130861438252));
+        assert_eq!(pool.pool_info().liquidity, dec!(2000));
+
+        // Each position earned the fees paid while it was in range.
+        assert_close(pool.position(wide, -600, 600).unwrap().tokens_owed_1, dec!(0.091633866727922038));
+        assert_close(pool.position(upper, 600, 1200).unwrap().tokens_owed_1, dec!(0.058366133272077962));
+        assert_eq!(pool.position(upper, 600, 1200).unwrap().tokens_owed_0, Decimal::ZERO);
+
+        let burned = pool.burn(wide, -600, 600, dec!(1000)).unwrap();
+        assert_eq!(burned.amount_0, Decimal::ZERO);
+        let (fees_0, fees_1) = pool.collect(wide, -600, 600).unwrap();
+        assert_eq!(fees_0, Decimal::ZERO);
+        assert_close(fees_1, dec!(0.091633866727922038));
+        assert!(pool.position(wide, -600, 600).is_none());
+
+        // Swapping back down crosses 600 again and leaves no liquidity.
+        assert!(matches!(
+            pool.swap_exact_input("A", dec!(1000), Decimal::ZERO),
+            Err(MarketMakerError::InsufficientLiquidity)
+        ));
+        assert_close(pool.price(), dec!(1.081915130861438252));
+    }
+
+    #[test]
+    fn test_invalid_ranges_rejected() {
+        let mut pool = ConcentratedPool::new("A".to_string(), "B".to_string(), dec!(0.003), 60, Decimal::ONE).unwrap();
+        assert!(matches!(
+            pool.mint(Uuid::new_v4(), 600, -600, dec!(1)),
+            Err(MarketMakerError::InvalidTickRange(600, -600))
+        ));
+        assert!(pool.mint(Uuid::new_v4(), -50, 600, dec!(1)).is_err());
+        assert!(pool.mint(Uuid::new_v4(), MIN_TICK - 60, 0, dec!(1)).is_err());
+    }
+
+    #[test]
+    fn test_liquidity_for_amounts_round_trip() {
+        let mut pool = ConcentratedPool::new("ETH".to_string(), "USDC".to_string(), dec!(0.003), 1, dec!(5000)).unwrap();
+        let liquidity = pool.liquidity_for_amounts(84222, 86129, dec!(1), dec!(5000)).unwrap();
+        let minted = pool.mint(Uuid::new_v4(), 84222, 86129, liquidity).unwrap();
+        assert!(minted.amount_0 <= dec!(1) && minted.amount_1 <= dec!(5000));
+        // The binding side is used in full.
+        assert!(minted.amount_1 > dec!(4999.9999));
+    }
+}
@@ -1,12 +1,11 @@
-use rust_decimal::Decimal;
-use std::collections::HashMap;
+use rust_decimal::{Decimal, MathematicalOps};
 use uuid::Uuid;
 
-use super::types::{MarketMakerError, Pool, PoolPosition};
+use super::pool::{round_down, round_up, AmmPool, PoolState, MINIMUM_LIQUIDITY};
+use super::types::{LiquidityChange, MarketMakerError, Pool, PoolCurve, SwapResult, ZapResult};
 
 pub struct LiquidityPool {
-    pool: Pool,
-    positions: HashMap<Uuid, PoolPosition>,
+    state: PoolState,
 }
 
 impl LiquidityPool {
@@ -17,107 +16,233 @@ impl LiquidityPool {
         reserve_b: Decimal,
         fee_percentage: Decimal,
     ) -> Self {
-        Self {
-            pool: Pool {
+        let mut pool = Self {
+            state: PoolState::new(Pool {
                 id: Uuid::new_v4(),
                 token_a,
                 token_b,
                 reserve_a,
                 reserve_b,
                 fee_percentage,
-            },
-            positions: HashMap::new(),
-        }
-    }
-
-    pub fn pool_info(&self) -> &Pool {
-        &self.pool
+                total_shares: initial_shares(reserve_a, reserve_b),
+                curve: PoolCurve::ConstantProduct,
+            }),
+        };
+        pool.record_price();
+        pool
     }
 
     pub fn token_a(&self) -> &str {
-        &self.pool.token_a
+        &self.state.pool.token_a
     }
 
     pub fn token_b(&self) -> &str {
-        &self.pool.token_b
+        &self.state.pool.token_b
     }
 
     pub fn reserve_a(&self) -> Decimal {
-        self.pool.reserve_a
+        self.state.pool.reserve_a
     }
 
     pub fn reserve_b(&self) -> Decimal {
-        self.pool.reserve_b
+        self.state.pool.reserve_b
     }
 
     pub fn fee_percentage(&self) -> Decimal {
-        self.pool.fee_percentage
+        self.state.pool.fee_percentage
     }
 
-    pub fn add_liquidity(
-        &mut self,
-        provider_id: Uuid,
-        token_a_amount: Decimal,
-        token_b_amount: Decimal,
-    ) -> Result<PoolPosition, MarketMakerError> {
-        let total_liquidity = self.pool.reserve_a + self.pool.reserve_b;
-        let share_percentage = if total_liquidity == Decimal::ZERO {
-            Decimal::ONE
+    pub fn total_shares(&self) -> Decimal {
+        self.state.pool.total_shares
+    }
+
+    /// The largest amounts at the reserve ratio within the desired ones,
+    /// rounded in the pool's favour.
+    fn balanced_amounts(reserve_a: Decimal, reserve_b: Decimal, amount_a: Decimal, amount_b: Decimal) -> (Decimal, Decimal) {
+        let optimal_b = round_up(amount_a * reserve_b / reserve_a);
+        if optimal_b <= amount_b {
+            (amount_a, optimal_b)
         } else {
-            (token_a_amount + token_b_amount) / total_liquidity
-        };
+            (round_up(amount_b * reserve_a / reserve_b).min(amount_a), amount_b)
+        }
+    }
 
-        let position = PoolPosition {
-            pool_id: self.pool.id,
-            provider_id,
-            token_a_amount,
-            token_b_amount,
-            share_percentage,
-        };
+    /// The amounts a deposit against `reserve_a` and `reserve_b` would take
+    /// and the shares it would mint, without changing the pool.
+    fn price_deposit(
+        &self,
+        reserve_a: Decimal,
+        reserve_b: Decimal,
+        amount_a: Decimal,
+        amount_b: Decimal,
+    ) -> Result<(Decimal, Decimal, Decimal), MarketMakerError> {
+        if amount_a <= Decimal::ZERO || amount_b <= Decimal::ZERO {
+            return Err(MarketMakerError::InvalidAmount);
+        }
+        if self.state.pool.total_shares.is_zero() {
+            let liquidity = initial_shares(amount_a, amount_b);
+            if liquidity <= MINIMUM_LIQUIDITY {
+                return Err(MarketMakerError::InsufficientLiquidity);
+            }
+            return Ok((amount_a, amount_b, liquidity - MINIMUM_LIQUIDITY));
+        }
 
-        self.pool.reserve_a += token_a_amount;
-        self.pool.reserve_b += token_b_amount;
-        self.positions.insert(provider_id, position.clone());
+        let (token_a_amount, token_b_amount) = Self::balanced_amounts(reserve_a, reserve_b, amount_a, amount_b);
+        let ratio = (token_a_amount / reserve_a).min(token_b_amount / reserve_b);
+        let shares = round_down(ratio * self.state.pool.total_shares);
+        if shares.is_zero() {
+            return Err(MarketMakerError::InvalidAmount);
+        }
+        Ok((token_a_amount, token_b_amount, shares))
+    }
+}
+
+impl AmmPool for LiquidityPool {
+    fn state(&self) -> &PoolState {
+        &self.state
+    }
 
-        Ok(position)
+    fn state_mut(&mut self) -> &mut PoolState {
+        &mut self.state
     }
 
-    pub fn remove_liquidity(&mut self, provider_id: Uuid) -> Result<PoolPosition, MarketMakerError> {
-        let position = self.positions
-            .remove(&provider_id)
-            .ok_or(MarketMakerError::InsufficientLiquidity)?;
+    fn record_price(&mut self) {
+        if !self.state.pool.reserve_a.is_zero() {
+            self.state.oracle.record(self.state.pool.reserve_b / self.state.pool.reserve_a);
+        }
+    }
 
-        self.pool.reserve_a -= position.token_a_amount;
-        self.pool.reserve_b -= position.token_b_amount;
+    /// Constant product quote for selling `input_amount` of `input_token`.
+    ///
+    /// The fee is taken from the input and set aside for the LPs:
+    /// `output = reserve_out * in_after_fee / (reserve_in + in_after_fee)`.
+    /// Price impact is the move of the execution price against the spot
+    /// price, excluding the fee.
+    fn quote_exact_input(&self, input_token: &str, input_amount: Decimal) -> Result<SwapResult, MarketMakerError> {
+        if input_amount <= Decimal::ZERO {
+            return Err(MarketMakerError::InvalidAmount);
+        }
+        let (reserve_in, reserve_out) = self.reserves_for(input_token)?;
+        if reserve_in.is_zero() || reserve_out.is_zero() {
+            return Err(MarketMakerError::InsufficientLiquidity);
+        }
 
-        Ok(position)
+        let fee_amount = self.calculate_fee(input_amount);
+        let in_after_fee = input_amount - fee_amount;
+        let output_amount = round_down(reserve_out * in_after_fee / (reserve_in + in_after_fee));
+        // Too small to buy anything: the pool would keep the input for nothing.
+        if output_amount.is_zero() {
+            return Err(MarketMakerError::InvalidAmount);
+        }
+
+        Ok(SwapResult {
+            input_amount,
+            output_amount,
+            price_impact: in_after_fee / (reserve_in + in_after_fee),
+            fee_amount,
+        })
     }
 
-    pub fn execute_swap(
+    /// Deposits both tokens and mints LP shares.
+    ///
+    /// The first deposit sets the price and mints `sqrt(a * b)` shares, of
+    /// which `MINIMUM_LIQUIDITY` stay locked in the pool. Later deposits are
+    /// taken at the current reserve ratio, the excess of either token is
+    /// refunded, and shares are minted in proportion to the smaller of the
+    /// two deposit ratios.
+    fn add_liquidity(
         &mut self,
-        input_token: &str,
-        input_amount: Decimal,
-        output_amount: Decimal,
-    ) -> Result<(), MarketMakerError> {
-        if input_token == self.pool.token_a {
-            self.pool.reserve_a += input_amount;
-            self.pool.reserve_b -= output_amount;
-        } else {
-            self.pool.reserve_b += input_amount;
-            self.pool.reserve_a -= output_amount;
+        provider_id: Uuid,
+        amount_a: Decimal,
+        amount_b: Decimal,
+    ) -> Result<LiquidityChange, MarketMakerError> {
+        let (token_a_amount, token_b_amount, shares) =
+            self.price_deposit(self.state.pool.reserve_a, self.state.pool.reserve_b, amount_a, amount_b)?;
+        if self.state.pool.total_shares.is_zero() {
+            self.state.pool.total_shares = MINIMUM_LIQUIDITY;
         }
 
-        Ok(())
+        self.state.pool.reserve_a += token_a_amount;
+        self.state.pool.reserve_b += token_b_amount;
+        self.state.pool.total_shares += shares;
+        let held = self.state.positions.entry(provider_id).or_default();
+        self.state.fees.settle(provider_id, *held);
+        *held += shares;
+
+        self.record_price();
+
+        Ok(LiquidityChange {
+            pool_id: self.state.pool.id,
+            provider_id,
+            shares,
+            token_a_amount,
+            token_b_amount,
+            refund_a: amount_a - token_a_amount,
+            refund_b: amount_b - token_b_amount,
+        })
     }
 
-    pub fn calculate_fee(&self, amount: Decimal) -> Decimal {
-        amount * self.pool.fee_percentage
+    /// Swaps the part of the deposit that leaves the remainder at the
+    /// post-swap reserve ratio, then deposits both sides.
+    ///
+    /// Both legs are priced before either runs, so a zap whose swap pays
+    /// nothing or whose deposit would mint no shares leaves the pool as it
+    /// was.
+    fn zap_in(&mut self, provider_id: Uuid, input_token: &str, amount: Decimal) -> Result<ZapResult, MarketMakerError> {
+        if amount <= Decimal::ZERO {
+            return Err(MarketMakerError::InvalidAmount);
+        }
+        let (reserve_in, reserve_out) = self.reserves_for(input_token)?;
+        if self.state.pool.total_shares.is_zero() {
+            return Err(MarketMakerError::InsufficientLiquidity);
+        }
+
+        let quote = self.quote_exact_input(input_token, zap_swap_amount(reserve_in, amount, self.state.pool.fee_percentage))?;
+        let remaining = amount - quote.input_amount;
+        // The swap's fee is kept out of the reserves.
+        let reserve_in = reserve_in + quote.input_amount - quote.fee_amount;
+        let reserve_out = reserve_out - quote.output_amount;
+        let input_is_a = input_token == self.state.pool.token_a;
+        if input_is_a {
+            self.price_deposit(reserve_in, reserve_out, remaining, quote.output_amount)?;
+        } else {
+            self.price_deposit(reserve_out, reserve_in, quote.output_amount, remaining)?;
+        }
+
+        let swap = self.swap_exact_input(input_token, quote.input_amount, quote.output_amount)?;
+        let deposit = if input_is_a {
+            self.add_liquidity(provider_id, remaining, swap.output_amount)?
+        } else {
+            self.add_liquidity(provider_id, swap.output_amount, remaining)?
+        };
+        Ok(ZapResult { swap: Some(swap), deposit })
     }
+
+}
+
+fn initial_shares(reserve_a: Decimal, reserve_b: Decimal) -> Decimal {
+    round_down((reserve_a * reserve_b).sqrt().unwrap_or_default())
+}
+
+/// Portion of a single-token deposit to swap so that the rest matches the
+/// reserve ratio after the swap, given the fee leaves the reserves:
+/// `(sqrt(r * (r * (2 - f)^2 + 4 * (1 - f)^2 * amount)) - r * (2 - f)) / (2 * (1 - f)^2)`.
+fn zap_swap_amount(reserve_in: Decimal, amount: Decimal, fee: Decimal) -> Decimal {
+    let two = Decimal::TWO;
+    let keep = Decimal::ONE - fee;
+    let root = (reserve_in * (reserve_in * (two - fee) * (two - fee) + Decimal::from(4) * keep * keep * amount))
+        .sqrt()
+        .unwrap_or_default();
+    round_down((root - reserve_in * (two - fee)) / (two * keep * keep))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::clock::{Clock, ManualClock};
+    use std::sync::Arc;
+    use chrono::{Duration, Utc};
+    use proptest::prelude::*;
     use rust_decimal_macros::dec;
 
     #[test]
@@ -149,11 +274,18 @@ mod tests {
         );
 
         let provider_id = Uuid::new_v4();
-        let result = pool.add_liquidity(provider_id, dec!(1000), dec!(1)).unwrap();
+        let minted = pool.add_liquidity(provider_id, dec!(1600), dec!(1)).unwrap();
+
+        assert_eq!(minted.token_a_amount, dec!(1600));
+        assert_eq!(minted.token_b_amount, dec!(1));
+        assert_eq!(minted.shares, dec!(40) - MINIMUM_LIQUIDITY);
+        assert_eq!(pool.total_shares(), dec!(40));
 
-        assert_eq!(result.token_a_amount, dec!(1000));
-        assert_eq!(result.token_b_amount, dec!(1));
-        assert_eq!(result.share_percentage, Decimal::ONE);
+        // Minted against the smaller ratio: 10% of the ETH reserve.
+        let second = Uuid::new_v4();
+        let minted = pool.add_liquidity(second, dec!(320), dec!(0.1)).unwrap();
+        assert_eq!(minted.shares, dec!(4));
+        assert_eq!(pool.position(second).unwrap().share_percentage, dec!(4) / dec!(44));
     }
 
     #[test]
@@ -167,13 +299,386 @@ mod tests {
         );
 
         let provider_id = Uuid::new_v4();
-        pool.add_liquidity(provider_id, dec!(1000), dec!(1)).unwrap();
-        
-        let removed = pool.remove_liquidity(provider_id).unwrap();
-        
-        assert_eq!(removed.token_a_amount, dec!(1000));
-        assert_eq!(removed.token_b_amount, dec!(1));
-        assert_eq!(pool.reserve_a(), dec!(0));
-        assert_eq!(pool.reserve_b(), dec!(0));
-    }
-} 
\ No newline at end of file
+        let minted = pool.add_liquidity(provider_id, dec!(1600), dec!(1)).unwrap();
+
+        let half = pool.remove_liquidity(provider_id, minted.shares / dec!(2)).unwrap();
+        assert_eq!(half.token_a_amount, dec!(799.9998));
+        assert_eq!(pool.position(provider_id).unwrap().shares, minted.shares / dec!(2));
+
+        let rest = pool.remove_liquidity(provider_id, minted.shares / dec!(2)).unwrap();
+        assert!(pool.position(provider_id).is_none());
+        assert_eq!(half.token_a_amount + rest.token_a_amount, dec!(1599.9996));
+        // The locked minimum keeps the pool from being drained.
+        assert_eq!(pool.total_shares(), MINIMUM_LIQUIDITY);
+        assert_eq!(pool.reserve_a(), dec!(0.0004));
+        assert!(matches!(
+            pool.remove_liquidity(provider_id, dec!(1)),
+            Err(MarketMakerError::InsufficientLiquidity)
+        ));
+    }
+
+    #[test]
+    fn test_add_liquidity_rejects_unusable_deposits() {
+        let mut pool = LiquidityPool::new("USDC".to_string(), "ETH".to_string(), dec!(0), dec!(0), dec!(0.003));
+        let provider_id = Uuid::new_v4();
+        for (amount_a, amount_b) in [(dec!(0), dec!(1)), (dec!(1600), dec!(0)), (dec!(-1), dec!(1))] {
+            assert!(matches!(
+                pool.add_liquidity(provider_id, amount_a, amount_b),
+                Err(MarketMakerError::InvalidAmount)
+            ));
+        }
+
+        // A first deposit must mint more than the locked minimum.
+        assert!(matches!(
+            pool.add_liquidity(provider_id, MINIMUM_LIQUIDITY, MINIMUM_LIQUIDITY),
+            Err(MarketMakerError::InsufficientLiquidity)
+        ));
+        assert!(pool.total_shares().is_zero());
+
+        // A deposit too small to mint a whole share unit leaves the pool as it was.
+        pool.add_liquidity(provider_id, dec!(1600), dec!(1)).unwrap();
+        assert!(matches!(
+            pool.add_liquidity(Uuid::new_v4(), dec!(0.00000016), dec!(0.0000000001)),
+            Err(MarketMakerError::InvalidAmount)
+        ));
+        assert_eq!((pool.reserve_a(), pool.reserve_b(), pool.total_shares()), (dec!(1600), dec!(1), dec!(40)));
+    }
+
+    #[test]
+    fn test_remove_liquidity_rejects_invalid_burns() {
+        let mut pool = LiquidityPool::new("USDC".to_string(), "ETH".to_string(), dec!(0), dec!(0), dec!(0.003));
+        let provider_id = Uuid::new_v4();
+        let minted = pool.add_liquidity(provider_id, dec!(1600), dec!(1)).unwrap();
+
+        for shares in [Decimal::ZERO, dec!(-1)] {
+            assert!(matches!(
+                pool.remove_liquidity(provider_id, shares),
+                Err(MarketMakerError::InvalidAmount)
+            ));
+        }
+        assert!(matches!(
+            pool.remove_liquidity(provider_id, minted.shares + dec!(0.00000001)),
+            Err(MarketMakerError::InsufficientLiquidity)
+        ));
+        // Shares can't be burned on behalf of someone who holds none.
+        assert!(matches!(
+            pool.remove_liquidity(Uuid::new_v4(), dec!(1)),
+            Err(MarketMakerError::InsufficientLiquidity)
+        ));
+        assert_eq!((pool.reserve_a(), pool.reserve_b()), (dec!(1600), dec!(1)));
+        assert_eq!(pool.position(provider_id).unwrap().shares, minted.shares);
+    }
+
+    #[test]
+    fn test_burn_pays_share_of_current_reserves() {
+        let mut pool = LiquidityPool::new("USDC".to_string(), "ETH".to_string(), dec!(0), dec!(0), dec!(0.003));
+        let provider_id = Uuid::new_v4();
+        let minted = pool.add_liquidity(provider_id, dec!(1000000), dec!(500)).unwrap();
+
+        // Buying ETH moves the reserves; a burn pays out at the new mix.
+        pool.swap_exact_input("USDC", dec!(50000), Decimal::ZERO).unwrap();
+        let (reserve_a, reserve_b, total) = (pool.reserve_a(), pool.reserve_b(), pool.total_shares());
+        let half = minted.shares / dec!(2);
+        let burned = pool.remove_liquidity(provider_id, half).unwrap();
+        assert_eq!(burned.token_a_amount, round_down(reserve_a * half / total));
+        assert_eq!(burned.token_b_amount, round_down(reserve_b * half / total));
+        assert!(burned.token_a_amount > dec!(500000));
+        assert!(burned.token_b_amount < dec!(250));
+    }
+
+    #[test]
+    fn test_seeded_pool_mints_against_unowned_shares() {
+        let mut pool = LiquidityPool::new("USDC".to_string(), "ETH".to_string(), dec!(1600), dec!(1), dec!(0.003));
+        assert_eq!(pool.total_shares(), dec!(40));
+        assert!(pool.provider_shares().is_zero());
+
+        let provider_id = Uuid::new_v4();
+        let minted = pool.add_liquidity(provider_id, dec!(160), dec!(0.1)).unwrap();
+        assert_eq!(minted.shares, dec!(4));
+        assert_eq!(pool.position(provider_id).unwrap().share_percentage, dec!(4) / dec!(44));
+    }
+
+    #[test]
+    fn test_deposit_refunds_excess_over_ratio() {
+        let mut pool = LiquidityPool::new("USDC".to_string(), "ETH".to_string(), dec!(0), dec!(0), dec!(0.003));
+        pool.add_liquidity(Uuid::new_v4(), dec!(1600), dec!(1)).unwrap();
+
+        let deposit = pool.add_liquidity(Uuid::new_v4(), dec!(4000), dec!(2)).unwrap();
+        assert_eq!((deposit.token_a_amount, deposit.token_b_amount), (dec!(3200), dec!(2)));
+        assert_eq!((deposit.refund_a, deposit.refund_b), (dec!(800), dec!(0)));
+        assert_eq!(pool.reserve_a() / pool.reserve_b(), dec!(1600));
+
+        let deposit = pool.add_liquidity(Uuid::new_v4(), dec!(160), dec!(5)).unwrap();
+        assert_eq!((deposit.token_a_amount, deposit.token_b_amount), (dec!(160), dec!(0.1)));
+        assert_eq!(deposit.refund_b, dec!(4.9));
+        assert_eq!(pool.reserve_a() / pool.reserve_b(), dec!(1600));
+    }
+
+    #[test]
+    fn test_zap_in_deposits_single_token() {
+        let mut pool = LiquidityPool::new("USDC".to_string(), "ETH".to_string(), dec!(0), dec!(0), dec!(0.003));
+        pool.add_liquidity(Uuid::new_v4(), dec!(1000000), dec!(500)).unwrap();
+
+        let provider_id = Uuid::new_v4();
+        let zap = pool.zap_in(provider_id, "ETH", dec!(10)).unwrap();
+        let swap = zap.swap.unwrap();
+        // Roughly half is swapped, and almost nothing is left over.
+        assert!(swap.input_amount > dec!(4.9) && swap.input_amount < dec!(5.1));
+        assert_eq!(zap.deposit.token_a_amount, swap.output_amount - zap.deposit.refund_a);
+        assert!(zap.deposit.refund_a < dec!(0.0001) && zap.deposit.refund_b < dec!(0.0001));
+        assert_eq!(
+            swap.input_amount + zap.deposit.token_b_amount + zap.deposit.refund_b,
+            dec!(10)
+        );
+        assert_eq!(pool.position(provider_id).unwrap().shares, zap.deposit.shares);
+
+        assert!(matches!(
+            pool.zap_in(provider_id, "BTC", dec!(1)),
+            Err(MarketMakerError::TokenNotInPool(_))
+        ));
+    }
+
+    #[test]
+    fn test_failed_zap_leaves_pool_untouched() {
+        let mut pool = LiquidityPool::new("USDC".to_string(), "ETH".to_string(), dec!(0), dec!(0), dec!(0.003));
+        let founder = Uuid::new_v4();
+        pool.add_liquidity(founder, dec!(1000000000), dec!(1)).unwrap();
+        let total_shares = pool.total_shares();
+
+        // The swap leg would pay out nothing, so nothing is swapped either.
+        let provider_id = Uuid::new_v4();
+        assert!(matches!(
+            pool.zap_in(provider_id, "USDC", dec!(0.0001)),
+            Err(MarketMakerError::InvalidAmount)
+        ));
+        assert_eq!((pool.reserve_a(), pool.reserve_b()), (dec!(1000000000), dec!(1)));
+        assert_eq!(pool.total_shares(), total_shares);
+        assert!(pool.earned_fees(founder).token_a_amount.is_zero());
+        assert!(pool.position(provider_id).is_none());
+    }
+
+    #[test]
+    fn test_fees_accrue_apart_from_principal() {
+        let mut pool = LiquidityPool::new("USDC".to_string(), "ETH".to_string(), dec!(0), dec!(0), dec!(0.003));
+        let provider_id = Uuid::new_v4();
+        let minted = pool.add_liquidity(provider_id, dec!(1000000), dec!(500)).unwrap();
+
+        let out = pool.swap_exact_input("USDC", dec!(50000), Decimal::ZERO).unwrap().output_amount;
+        let eth_fee = pool.swap_exact_input("ETH", out, Decimal::ZERO).unwrap().fee_amount;
+        let earned = pool.earned_fees(provider_id);
+        // All the fees bar the locked minimum's cut, which the protocol gets.
+        assert!(earned.token_a_amount < dec!(150) && earned.token_a_amount > dec!(149.99));
+        assert!(earned.token_b_amount < eth_fee && earned.token_b_amount > eth_fee * dec!(0.9999));
+        let (protocol_a, protocol_b) = pool.protocol_fees();
+        assert!(dec!(150) - earned.token_a_amount - protocol_a <= dec!(0.00000001));
+        assert!(eth_fee - earned.token_b_amount - protocol_b <= dec!(0.00000001));
+
+        let burned = pool.remove_liquidity(provider_id, minted.shares).unwrap();
+        assert!(burned.token_a_amount * burned.token_b_amount > dec!(1000000) * dec!(500) - dec!(1));
+        assert!(burned.token_a_amount < dec!(1000000) + dec!(150));
+
+        // Withdrawing the principal leaves the fees claimable, once.
+        let claim = pool.claim_fees(provider_id).unwrap();
+        assert_eq!((claim.token_a_amount, claim.token_b_amount), (earned.token_a_amount, earned.token_b_amount));
+        assert!(matches!(pool.claim_fees(provider_id), Err(MarketMakerError::NoFeesToClaim)));
+        assert_eq!(pool.fee_claims(provider_id).len(), 1);
+    }
+
+    #[test]
+    fn test_fees_go_to_shares_outstanding_when_paid() {
+        let mut pool = LiquidityPool::new("USDC".to_string(), "ETH".to_string(), dec!(0), dec!(0), dec!(0.003));
+        let (early, late) = (Uuid::new_v4(), Uuid::new_v4());
+        pool.add_liquidity(early, dec!(1000000), dec!(500)).unwrap();
+        pool.swap_exact_input("USDC", dec!(10000), Decimal::ZERO).unwrap();
+
+        pool.add_liquidity(late, dec!(1000000), dec!(1000)).unwrap();
+        assert!(pool.earned_fees(late).token_a_amount.is_zero());
+        assert!(matches!(pool.claim_fees(late), Err(MarketMakerError::NoFeesToClaim)));
+
+        // Later fees split by share count.
+        let before = pool.earned_fees(early).token_a_amount;
+        pool.swap_exact_input("USDC", dec!(10000), Decimal::ZERO).unwrap();
+        let early_cut = pool.earned_fees(early).token_a_amount - before;
+        let late_cut = pool.earned_fees(late).token_a_amount;
+        let late_share = pool.position(late).unwrap().share_percentage;
+        assert!((late_cut - dec!(30) * late_share).abs() <= dec!(0.00000001));
+        assert!(early_cut + late_cut <= dec!(30) && early_cut + late_cut > dec!(29.9999));
+
+        let claim = pool.claim_fees(late).unwrap();
+        assert_eq!(claim.token_a_amount, late_cut);
+        assert_eq!(claim.pool_id, pool.pool_info().id);
+        assert!(pool.earned_fees(late).token_a_amount.is_zero());
+        assert!(pool.fee_claims(early).is_empty());
+    }
+
+    #[test]
+    fn test_fees_accrue_across_add_and_remove() {
+        let mut pool = LiquidityPool::new("USDC".to_string(), "ETH".to_string(), dec!(0), dec!(0), dec!(0.003));
+        let (provider_id, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let minted = pool.add_liquidity(provider_id, dec!(1000000), dec!(500)).unwrap();
+        pool.add_liquidity(other, dec!(1000000), dec!(500)).unwrap();
+
+        let mut expected = Decimal::ZERO;
+        let mut swap = |pool: &mut LiquidityPool| {
+            let share = pool.position(provider_id).map_or(Decimal::ZERO, |position| position.shares) / pool.total_shares();
+            pool.swap_exact_input("USDC", dec!(10000), Decimal::ZERO).unwrap();
+            expected += dec!(30) * share;
+        };
+        swap(&mut pool);
+        pool.remove_liquidity(provider_id, minted.shares / dec!(2)).unwrap();
+        swap(&mut pool);
+        pool.remove_liquidity(provider_id, pool.position(provider_id).unwrap().shares).unwrap();
+        swap(&mut pool);
+        pool.add_liquidity(provider_id, dec!(100000), dec!(100)).unwrap();
+        swap(&mut pool);
+
+        // Each swap paid the provider for the shares held at the time.
+        let earned = pool.earned_fees(provider_id).token_a_amount;
+        assert!((earned - expected).abs() <= dec!(0.00000004));
+        assert_eq!(pool.claim_fees(provider_id).unwrap().token_a_amount, earned);
+    }
+
+    #[test]
+    fn test_fees_cannot_be_claimed_twice() {
+        let clock = Arc::new(ManualClock::new(Utc::now()));
+        let mut pool = LiquidityPool::new("USDC".to_string(), "ETH".to_string(), dec!(0), dec!(0), dec!(0.003))
+            .with_clock(clock.clone());
+        let provider_id = Uuid::new_v4();
+        pool.add_liquidity(provider_id, dec!(1000000), dec!(500)).unwrap();
+        assert!(matches!(pool.claim_fees(provider_id), Err(MarketMakerError::NoFeesToClaim)));
+        assert!(matches!(pool.claim_fees(Uuid::new_v4()), Err(MarketMakerError::NoFeesToClaim)));
+
+        pool.swap_exact_input("USDC", dec!(10000), Decimal::ZERO).unwrap();
+        let first = pool.claim_fees(provider_id).unwrap();
+        assert_eq!(first.claimed_at, clock.now());
+        assert!(matches!(pool.claim_fees(provider_id), Err(MarketMakerError::NoFeesToClaim)));
+
+        clock.advance(Duration::minutes(5));
+        pool.swap_exact_input("USDC", dec!(10000), Decimal::ZERO).unwrap();
+        let second = pool.claim_fees(provider_id).unwrap();
+        assert_eq!(second.claimed_at, first.claimed_at + Duration::minutes(5));
+        assert!(pool.earned_fees(provider_id).token_a_amount.is_zero());
+        let claims = pool.fee_claims(provider_id);
+        assert_eq!(claims.iter().map(|claim| claim.id).collect::<Vec<_>>(), vec![first.id, second.id]);
+    }
+
+    #[test]
+    fn test_fees_on_seeded_reserves_go_to_protocol() {
+        let mut pool = LiquidityPool::new("USDC".to_string(), "ETH".to_string(), dec!(1000000), dec!(500), dec!(0.003));
+        pool.swap_exact_input("USDC", dec!(10000), Decimal::ZERO).unwrap();
+        assert_eq!(pool.protocol_fees(), (dec!(30), Decimal::ZERO));
+
+        // A provider joining now splits later fees with the seeded shares.
+        let provider_id = Uuid::new_v4();
+        pool.add_liquidity(provider_id, dec!(1010000), dec!(1000)).unwrap();
+        let share = pool.position(provider_id).unwrap().share_percentage;
+        pool.swap_exact_input("USDC", dec!(10000), Decimal::ZERO).unwrap();
+        let earned = pool.earned_fees(provider_id).token_a_amount;
+        assert!((earned - dec!(30) * share).abs() <= dec!(0.00000001));
+        assert!((pool.protocol_fees().0 + earned - dec!(60)).abs() <= dec!(0.00000001));
+
+        let collected = pool.collect_protocol_fees();
+        assert!(dec!(60) - collected.0 - earned <= dec!(0.00000002));
+        assert_eq!(pool.protocol_fees(), (Decimal::ZERO, Decimal::ZERO));
+    }
+
+    #[test]
+    fn test_constant_product_quote() {
+        let pool = LiquidityPool::new("USDC".to_string(), "ETH".to_string(), dec!(1000000), dec!(500), dec!(0.003));
+
+        let quote = pool.quote_exact_input("USDC", dec!(50000)).unwrap();
+        assert_eq!(quote.fee_amount, dec!(150));
+        // 500 * 49850 / 1049850, rounded down
+        assert_eq!(quote.output_amount, dec!(23.74148687));
+        assert!(quote.price_impact > dec!(0.04) && quote.price_impact < dec!(0.05));
+        assert!(matches!(
+            pool.quote_exact_input("BTC", dec!(1)),
+            Err(MarketMakerError::TokenNotInPool(_))
+        ));
+    }
+
+    #[test]
+    fn test_swap_too_small_to_pay_out_is_rejected() {
+        let mut pool = LiquidityPool::new("USDC".to_string(), "ETH".to_string(), dec!(1000000000), dec!(1), dec!(0.003));
+
+        assert!(matches!(
+            pool.swap_exact_input("USDC", dec!(0.0001), Decimal::ZERO),
+            Err(MarketMakerError::InvalidAmount)
+        ));
+        assert_eq!((pool.reserve_a(), pool.reserve_b()), (dec!(1000000000), dec!(1)));
+        assert_eq!(pool.protocol_fees(), (Decimal::ZERO, Decimal::ZERO));
+    }
+
+    #[test]
+    fn test_swap_respects_min_output() {
+        let mut pool = LiquidityPool::new("USDC".to_string(), "ETH".to_string(), dec!(1000000), dec!(500), dec!(0.003));
+
+        assert!(matches!(
+            pool.swap_exact_input("USDC", dec!(1000), dec!(1)),
+            Err(MarketMakerError::SlippageExceeded)
+        ));
+        assert_eq!(pool.reserve_a(), dec!(1000000));
+
+        let result = pool.swap_exact_input("ETH", dec!(1), dec!(1900)).unwrap();
+        assert_eq!(pool.reserve_b(), dec!(500.997));
+        assert_eq!(pool.reserve_a(), dec!(1000000) - result.output_amount);
+    }
+
+    #[test]
+    fn test_twap_resists_short_lived_swap() {
+        let clock = Arc::new(ManualClock::new(Utc::now()));
+        let mut pool = LiquidityPool::new("ETH".to_string(), "USDC".to_string(), dec!(500), dec!(1000000), dec!(0.003))
+            .with_clock(clock.clone());
+        clock.advance(Duration::minutes(59));
+
+        // Dumping as much ETH again quarters the spot price for a minute.
+        pool.swap_exact_input("ETH", dec!(500), Decimal::ZERO).unwrap();
+        let spot = pool.reserve_b() / pool.reserve_a();
+        assert!(spot < dec!(510));
+        clock.advance(Duration::minutes(1));
+
+        let twap = pool.twap(Duration::hours(1)).unwrap();
+        assert_eq!(twap.price_a.round_dp(12), ((dec!(2000) * dec!(59) + spot) / dec!(60)).round_dp(12));
+        assert!(twap.price_a > dec!(1970));
+        let geometric = pool.geometric_twap(Duration::hours(1)).unwrap();
+        assert!(geometric.price_a < twap.price_a && geometric.price_a > dec!(1950));
+        assert!(matches!(
+            pool.twap(Duration::hours(2)),
+            Err(MarketMakerError::InsufficientPriceHistory)
+        ));
+    }
+
+    fn amount(units: u64, cents: u64) -> Decimal {
+        Decimal::from(units) + Decimal::new(cents as i64, 2)
+    }
+
+    proptest! {
+        #[test]
+        fn prop_k_never_decreases(
+            reserve_a in 1u64..1_000_000_000,
+            reserve_b in 1u64..1_000_000_000,
+            fee_bps in 0u64..100,
+            swaps in prop::collection::vec((any::<bool>(), 0u64..10_000_000, 1u64..100), 1..20),
+        ) {
+            let fee = Decimal::new(fee_bps as i64, 4);
+            let mut pool = LiquidityPool::new("A".to_string(), "B".to_string(), reserve_a.into(), reserve_b.into(), fee);
+
+            for (a_to_b, units, cents) in swaps {
+                let input_token = if a_to_b { "A" } else { "B" };
+                let k_before = pool.reserve_a() * pool.reserve_b();
+                let reserve_out = if a_to_b { pool.reserve_b() } else { pool.reserve_a() };
+
+                let result = match pool.swap_exact_input(input_token, amount(units, cents), Decimal::ZERO) {
+                    // Too small to pay anything out; the pool is left alone.
+                    Err(MarketMakerError::InvalidAmount) => {
+                        prop_assert_eq!(pool.reserve_a() * pool.reserve_b(), k_before);
+                        continue;
+                    }
+                    result => result.unwrap(),
+                };
+                prop_assert!(result.output_amount < reserve_out);
+                prop_assert!(pool.reserve_a() * pool.reserve_b() >= k_before);
+            }
+        }
+    }
+}
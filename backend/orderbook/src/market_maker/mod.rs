@@ -1,10 +1,22 @@
 pub mod amm;
+pub mod concentrated;
 pub mod price_impact;
 pub mod slippage;
 pub mod types;
 pub mod liquidity_pool;
+pub mod oracle;
+pub mod pool;
+pub mod router;
+pub mod stable_swap;
+pub mod weighted_pool;
 
 pub use amm::AutomatedMarketMaker;
+pub use concentrated::ConcentratedPool;
 pub use price_impact::PriceImpactCalculator;
 pub use slippage::SlippageProtection;
-pub use liquidity_pool::LiquidityPool; 
\ No newline at end of file
+pub use liquidity_pool::LiquidityPool;
+pub use oracle::PriceOracle;
+pub use pool::AmmPool;
+pub use router::Router;
+pub use stable_swap::StableSwapPool;
+pub use weighted_pool::WeightedPool;
@@ -0,0 +1,198 @@
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::{Decimal, MathematicalOps};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use super::types::{MarketMakerError, PriceObservation, TwapPrice};
+use crate::utils::clock::{Clock, SystemClock};
+
+/// Observations kept per pool, as in Uniswap v3's largest oracle. How far
+/// back a TWAP can reach depends on how often the pool's price changes.
+pub const OBSERVATION_CAPACITY: usize = 65_535;
+
+/// Cumulative price accumulators of a two-token pool.
+///
+/// Pools record their spot price after every change to the reserves; each
+/// record first adds the previous price times the seconds it was in effect
+/// to the accumulators. The mean price over a window is the change in an
+/// accumulator divided by the window's length, so moving it means holding
+/// a manipulated price for a large part of the window. Prices are of
+/// `token_a` in units of `token_b`.
+pub struct PriceOracle {
+    clock: Arc<dyn Clock>,
+    observations: VecDeque<PriceObservation>,
+}
+
+impl Default for PriceOracle {
+    fn default() -> Self {
+        Self::new(Arc::new(SystemClock))
+    }
+}
+
+impl PriceOracle {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            clock,
+            observations: VecDeque::new(),
+        }
+    }
+
+    /// Accumulates the price in effect so far and switches to `price`.
+    /// Several records at one instant keep only the last price.
+    pub(crate) fn record(&mut self, price: Decimal) {
+        if price <= Decimal::ZERO {
+            return;
+        }
+        let now = self.clock.now();
+        let mut observation = match self.observations.back() {
+            Some(last) => advance(last, now.max(last.timestamp)),
+            None => PriceObservation {
+                timestamp: now,
+                price,
+                price_a_cumulative: Decimal::ZERO,
+                price_b_cumulative: Decimal::ZERO,
+                log_price_cumulative: Decimal::ZERO,
+            },
+        };
+        observation.price = price;
+
+        if self.observations.back().is_some_and(|last| last.timestamp == observation.timestamp) {
+            self.observations.pop_back();
+        }
+        self.observations.push_back(observation);
+        if self.observations.len() > OBSERVATION_CAPACITY {
+            self.observations.pop_front();
+        }
+    }
+
+    /// The time on the pool's clock.
+    pub(crate) fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    /// The accumulators as of now, or `None` before the pool had a price.
+    pub fn observe(&self) -> Option<PriceObservation> {
+        let last = self.observations.back()?;
+        Some(advance(last, self.clock.now().max(last.timestamp)))
+    }
+
+    /// Arithmetic mean of each token's price over the last `window`.
+    pub fn twap(&self, window: Duration) -> Result<TwapPrice, MarketMakerError> {
+        let (start, end) = self.window(window)?;
+        let seconds = seconds_between(start.timestamp, end.timestamp);
+        Ok(TwapPrice {
+            start: start.timestamp,
+            end: end.timestamp,
+            price_a: (end.price_a_cumulative - start.price_a_cumulative) / seconds,
+            price_b: (end.price_b_cumulative - start.price_b_cumulative) / seconds,
+        })
+    }
+
+    /// Geometric mean price over the last `window`. Unlike the arithmetic
+    /// mean, `price_b` is exactly the reciprocal of `price_a`, and a spike
+    /// moves it far less.
+    pub fn geometric_twap(&self, window: Duration) -> Result<TwapPrice, MarketMakerError> {
+        let (start, end) = self.window(window)?;
+        let seconds = seconds_between(start.timestamp, end.timestamp);
+        let price_a = ((end.log_price_cumulative - start.log_price_cumulative) / seconds).exp();
+        Ok(TwapPrice {
+            start: start.timestamp,
+            end: end.timestamp,
+            price_a,
+            price_b: Decimal::ONE / price_a,
+        })
+    }
+
+    /// The accumulators at the start and end of a window ending now.
+    fn window(&self, window: Duration) -> Result<(PriceObservation, PriceObservation), MarketMakerError> {
+        if window <= Duration::zero() {
+            return Err(MarketMakerError::InvalidAmount);
+        }
+        let end = self.observe().ok_or(MarketMakerError::InsufficientPriceHistory)?;
+        let start_time = end.timestamp - window;
+        let before = self.observations.partition_point(|observation| observation.timestamp <= start_time);
+        if before == 0 {
+            return Err(MarketMakerError::InsufficientPriceHistory);
+        }
+        Ok((advance(&self.observations[before - 1], start_time), end))
+    }
+}
+
+/// Extends an observation to `timestamp` at its price.
+fn advance(observation: &PriceObservation, timestamp: DateTime<Utc>) -> PriceObservation {
+    let seconds = seconds_between(observation.timestamp, timestamp);
+    PriceObservation {
+        timestamp,
+        price: observation.price,
+        price_a_cumulative: observation.price_a_cumulative + observation.price * seconds,
+        price_b_cumulative: observation.price_b_cumulative + seconds / observation.price,
+        log_price_cumulative: observation.log_price_cumulative + observation.price.ln() * seconds,
+    }
+}
+
+fn seconds_between(from: DateTime<Utc>, to: DateTime<Utc>) -> Decimal {
+    Decimal::new((to - from).num_milliseconds(), 3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::clock::ManualClock;
+    use rust_decimal_macros::dec;
+
+    fn oracle() -> (Arc<ManualClock>, PriceOracle) {
+        let clock = Arc::new(ManualClock::new(Utc::now()));
+        (clock.clone(), PriceOracle::new(clock))
+    }
+
+    #[test]
+    fn test_arithmetic_and_geometric_means() {
+        let (clock, mut oracle) = oracle();
+        oracle.record(dec!(1));
+        clock.advance(Duration::seconds(10));
+        oracle.record(dec!(4));
+        clock.advance(Duration::seconds(10));
+
+        let twap = oracle.twap(Duration::seconds(20)).unwrap();
+        assert_eq!(twap.price_a, dec!(2.5));
+        assert_eq!(twap.price_b, dec!(0.625));
+        let geometric = oracle.geometric_twap(Duration::seconds(20)).unwrap();
+        assert_eq!(geometric.price_a.round_dp(8), dec!(2));
+        assert_eq!(geometric.price_b.round_dp(8), dec!(0.5));
+    }
+
+    #[test]
+    fn test_window_starts_between_observations() {
+        let (clock, mut oracle) = oracle();
+        oracle.record(dec!(100));
+        clock.advance(Duration::seconds(30));
+        oracle.record(dec!(200));
+        // Replaced by the later record at the same instant.
+        oracle.record(dec!(110));
+        clock.advance(Duration::seconds(10));
+
+        // 10s at 100 and 10s at 110.
+        let twap = oracle.twap(Duration::seconds(20)).unwrap();
+        assert_eq!(twap.price_a, dec!(105));
+        assert_eq!(twap.end - twap.start, Duration::seconds(20));
+        assert_eq!(oracle.twap(Duration::seconds(5)).unwrap().price_a, dec!(110));
+    }
+
+    #[test]
+    fn test_window_needs_history() {
+        let (clock, mut oracle) = oracle();
+        assert!(matches!(
+            oracle.twap(Duration::seconds(1)),
+            Err(MarketMakerError::InsufficientPriceHistory)
+        ));
+
+        oracle.record(dec!(1));
+        clock.advance(Duration::seconds(60));
+        assert!(oracle.twap(Duration::seconds(60)).is_ok());
+        assert!(matches!(
+            oracle.geometric_twap(Duration::seconds(61)),
+            Err(MarketMakerError::InsufficientPriceHistory)
+        ));
+        assert!(matches!(oracle.twap(Duration::zero()), Err(MarketMakerError::InvalidAmount)));
+    }
+}
@@ -0,0 +1,440 @@
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::collections::HashMap;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use super::oracle::PriceOracle;
+use super::types::{
+    EarnedFees, FeeClaim, LiquidityChange, MarketMakerError, Pool, PoolPosition, SwapResult, TwapPrice, ZapResult,
+};
+use crate::utils::clock::Clock;
+
+/// Decimal places swap outputs are rounded down to, so rounding never takes
+/// value out of the pool.
+pub const OUTPUT_DP: u32 = 8;
+
+/// Shares burned from the first deposit so the pool can never be fully
+/// drained and the share price cannot be inflated from zero.
+pub const MINIMUM_LIQUIDITY: Decimal = Decimal::from_parts(1000, 0, 0, false, OUTPUT_DP);
+
+/// Operations shared by every two-token pool curve, so
+/// `AutomatedMarketMaker` and the router can hold any kind of pool.
+///
+/// A curve supplies its pricing, deposits and spot price; everything that
+/// only touches the shared `PoolState` is implemented here once.
+pub trait AmmPool: Send + Sync {
+    fn state(&self) -> &PoolState;
+
+    fn state_mut(&mut self) -> &mut PoolState;
+
+    /// Records the spot price with the oracle after the reserves change.
+    fn record_price(&mut self);
+
+    /// Times price observations with `clock`, dropping any taken so far.
+    fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self
+    where
+        Self: Sized,
+    {
+        self.state_mut().oracle = PriceOracle::new(clock);
+        self.record_price();
+        self
+    }
+
+    fn pool_info(&self) -> &Pool {
+        &self.state().pool
+    }
+
+    /// Prices selling `input_amount` of `input_token` on the pool's curve.
+    fn quote_exact_input(&self, input_token: &str, input_amount: Decimal) -> Result<SwapResult, MarketMakerError>;
+
+    fn add_liquidity(
+        &mut self,
+        provider_id: Uuid,
+        amount_a: Decimal,
+        amount_b: Decimal,
+    ) -> Result<LiquidityChange, MarketMakerError>;
+
+    /// Deposits a single token.
+    fn zap_in(&mut self, provider_id: Uuid, input_token: &str, amount: Decimal) -> Result<ZapResult, MarketMakerError>;
+
+    /// Burns `shares` of the provider's position for the same fraction of
+    /// the current reserves.
+    fn remove_liquidity(&mut self, provider_id: Uuid, shares: Decimal) -> Result<LiquidityChange, MarketMakerError> {
+        let change = self.state_mut().burn(provider_id, shares)?;
+        self.record_price();
+        Ok(change)
+    }
+
+    /// The provider's shares priced at the current reserves.
+    fn position(&self, provider_id: Uuid) -> Option<PoolPosition> {
+        let state = self.state();
+        let shares = *state.positions.get(&provider_id)?;
+        let (token_a_amount, token_b_amount) = share_of_reserves(&state.pool, shares);
+        Some(PoolPosition {
+            pool_id: state.pool.id,
+            provider_id,
+            shares,
+            token_a_amount,
+            token_b_amount,
+            share_percentage: shares / state.pool.total_shares,
+        })
+    }
+
+    /// What `remove_liquidity` would pay for `shares`, without burning them.
+    fn quote_remove_liquidity(&self, provider_id: Uuid, shares: Decimal) -> Result<LiquidityChange, MarketMakerError> {
+        if shares <= Decimal::ZERO {
+            return Err(MarketMakerError::InvalidAmount);
+        }
+        let held = self.position(provider_id).map(|position| position.shares).unwrap_or_default();
+        if shares > held {
+            return Err(MarketMakerError::InsufficientLiquidity);
+        }
+        let pool = self.pool_info();
+        let (token_a_amount, token_b_amount) = share_of_reserves(pool, shares);
+        Ok(LiquidityChange {
+            pool_id: pool.id,
+            provider_id,
+            shares,
+            token_a_amount,
+            token_b_amount,
+            refund_a: Decimal::ZERO,
+            refund_b: Decimal::ZERO,
+        })
+    }
+
+    /// Shares held by providers. The locked minimum and the shares backing
+    /// seeded reserves are not among them; their cut of the fees goes to
+    /// the protocol.
+    fn provider_shares(&self) -> Decimal {
+        self.state().positions.values().sum()
+    }
+
+    /// Swap fees set aside for the pool's LPs.
+    fn fees(&self) -> &FeeAccumulator {
+        &self.state().fees
+    }
+
+    fn fees_mut(&mut self) -> &mut FeeAccumulator {
+        &mut self.state_mut().fees
+    }
+
+    /// Price accumulators, updated on every swap and liquidity change.
+    fn oracle(&self) -> &PriceOracle {
+        &self.state().oracle
+    }
+
+    /// Moves the reserves by a swap that has already been priced.
+    fn execute_swap(
+        &mut self,
+        input_token: &str,
+        input_amount: Decimal,
+        output_amount: Decimal,
+    ) -> Result<(), MarketMakerError> {
+        let pool = &mut self.state_mut().pool;
+        if input_token == pool.token_a {
+            pool.reserve_a += input_amount;
+            pool.reserve_b -= output_amount;
+        } else if input_token == pool.token_b {
+            pool.reserve_b += input_amount;
+            pool.reserve_a -= output_amount;
+        } else {
+            return Err(MarketMakerError::TokenNotInPool(input_token.to_string()));
+        }
+        self.record_price();
+        Ok(())
+    }
+
+    /// Executes a swap at the quoted price, failing if it pays less than
+    /// `min_output`.
+    ///
+    /// The fee is kept out of the reserves and shared between the providers
+    /// and the protocol, unless there are no shares to credit it to.
+    fn swap_exact_input(
+        &mut self,
+        input_token: &str,
+        input_amount: Decimal,
+        min_output: Decimal,
+    ) -> Result<SwapResult, MarketMakerError> {
+        let quote = self.quote_exact_input(input_token, input_amount)?;
+        if quote.output_amount < min_output {
+            return Err(MarketMakerError::SlippageExceeded);
+        }
+        let pool = self.pool_info();
+        let (fee_in_a, total_shares) = (input_token == pool.token_a, pool.total_shares);
+        let fee_amount = if total_shares.is_zero() { Decimal::ZERO } else { quote.fee_amount };
+        let provider_shares = self.provider_shares();
+
+        self.execute_swap(input_token, input_amount - fee_amount, quote.output_amount)?;
+        self.fees_mut().credit(fee_in_a, fee_amount, provider_shares, total_shares);
+        Ok(quote)
+    }
+
+    /// Fees the provider has earned and not yet claimed.
+    fn earned_fees(&self, provider_id: Uuid) -> EarnedFees {
+        let shares = self.position(provider_id).map(|position| position.shares).unwrap_or_default();
+        self.fees().earned(self.pool_info().id, provider_id, shares)
+    }
+
+    /// Pays out the provider's earned fees without touching their shares.
+    fn claim_fees(&mut self, provider_id: Uuid) -> Result<FeeClaim, MarketMakerError> {
+        let shares = self.position(provider_id).map(|position| position.shares).unwrap_or_default();
+        let (pool_id, claimed_at) = (self.pool_info().id, self.oracle().now());
+        self.fees_mut().claim(pool_id, provider_id, shares, claimed_at)
+    }
+
+    /// The provider's past claims, oldest first.
+    fn fee_claims(&self, provider_id: Uuid) -> Vec<FeeClaim> {
+        self.fees().claims_by(provider_id)
+    }
+
+    /// Fees earned by shares no provider holds, as (token_a, token_b).
+    fn protocol_fees(&self) -> (Decimal, Decimal) {
+        self.fees().protocol_fees()
+    }
+
+    /// Pays out and resets the protocol's fees.
+    fn collect_protocol_fees(&mut self) -> (Decimal, Decimal) {
+        self.fees_mut().collect_protocol_fees()
+    }
+
+    /// Arithmetic mean price over the last `window`.
+    fn twap(&self, window: Duration) -> Result<TwapPrice, MarketMakerError> {
+        self.oracle().twap(window)
+    }
+
+    /// Geometric mean price over the last `window`.
+    fn geometric_twap(&self, window: Duration) -> Result<TwapPrice, MarketMakerError> {
+        self.oracle().geometric_twap(window)
+    }
+
+    fn calculate_fee(&self, amount: Decimal) -> Decimal {
+        amount * self.pool_info().fee_percentage
+    }
+
+    /// (input reserve, output reserve) when selling `input_token`.
+    fn reserves_for(&self, input_token: &str) -> Result<(Decimal, Decimal), MarketMakerError> {
+        let pool = self.pool_info();
+        if input_token == pool.token_a {
+            Ok((pool.reserve_a, pool.reserve_b))
+        } else if input_token == pool.token_b {
+            Ok((pool.reserve_b, pool.reserve_a))
+        } else {
+            Err(MarketMakerError::TokenNotInPool(input_token.to_string()))
+        }
+    }
+
+    /// The token received when selling `input_token`.
+    fn other_token(&self, input_token: &str) -> Result<&str, MarketMakerError> {
+        let pool = self.pool_info();
+        if input_token == pool.token_a {
+            Ok(&pool.token_b)
+        } else if input_token == pool.token_b {
+            Ok(&pool.token_a)
+        } else {
+            Err(MarketMakerError::TokenNotInPool(input_token.to_string()))
+        }
+    }
+}
+
+/// Swap fees held apart from the reserves and shared out per LP share.
+///
+/// Every swap adds the providers' cut of its fee, divided by their shares,
+/// to the growth of its input token. A provider earns their shares times
+/// the growth since their checkpoint, which pools settle before changing
+/// the provider's shares, so fees go to the shares outstanding when they
+/// were paid. The cut of the locked minimum and of seeded reserves goes
+/// to the protocol instead of being stranded.
+#[derive(Debug, Default)]
+pub struct FeeAccumulator {
+    growth_a: Decimal,
+    growth_b: Decimal,
+    protocol_a: Decimal,
+    protocol_b: Decimal,
+    accounts: HashMap<Uuid, FeeAccount>,
+    claims: Vec<FeeClaim>,
+}
+
+#[derive(Debug, Default)]
+struct FeeAccount {
+    growth_a_last: Decimal,
+    growth_b_last: Decimal,
+    owed_a: Decimal,
+    owed_b: Decimal,
+}
+
+impl FeeAccumulator {
+    /// Splits a fee between the providers' shares and the protocol in
+    /// proportion to `provider_shares` out of `total_shares`.
+    pub(crate) fn credit(&mut self, token_a: bool, fee_amount: Decimal, provider_shares: Decimal, total_shares: Decimal) {
+        if fee_amount.is_zero() || total_shares.is_zero() {
+            return;
+        }
+        let (growth, protocol) = if token_a {
+            (&mut self.growth_a, &mut self.protocol_a)
+        } else {
+            (&mut self.growth_b, &mut self.protocol_b)
+        };
+        let providers_cut = fee_amount * (provider_shares / total_shares);
+        if !provider_shares.is_zero() {
+            *growth += providers_cut / provider_shares;
+        }
+        *protocol += fee_amount - providers_cut;
+    }
+
+    pub(crate) fn protocol_fees(&self) -> (Decimal, Decimal) {
+        (round_down(self.protocol_a), round_down(self.protocol_b))
+    }
+
+    pub(crate) fn collect_protocol_fees(&mut self) -> (Decimal, Decimal) {
+        let collected = self.protocol_fees();
+        self.protocol_a -= collected.0;
+        self.protocol_b -= collected.1;
+        collected
+    }
+
+    /// Books what `shares` earned since the provider's checkpoint and moves
+    /// the checkpoint to now. Call before the provider's shares change.
+    pub(crate) fn settle(&mut self, provider_id: Uuid, shares: Decimal) {
+        let (growth_a, growth_b) = (self.growth_a, self.growth_b);
+        let account = self.accounts.entry(provider_id).or_insert_with(|| FeeAccount {
+            growth_a_last: growth_a,
+            growth_b_last: growth_b,
+            ..FeeAccount::default()
+        });
+        account.owed_a += shares * (growth_a - account.growth_a_last);
+        account.owed_b += shares * (growth_b - account.growth_b_last);
+        account.growth_a_last = growth_a;
+        account.growth_b_last = growth_b;
+    }
+
+    pub(crate) fn earned(&self, pool_id: Uuid, provider_id: Uuid, shares: Decimal) -> EarnedFees {
+        let (owed_a, owed_b) = self.accounts.get(&provider_id).map_or((Decimal::ZERO, Decimal::ZERO), |account| {
+            (
+                account.owed_a + shares * (self.growth_a - account.growth_a_last),
+                account.owed_b + shares * (self.growth_b - account.growth_b_last),
+            )
+        });
+        EarnedFees {
+            pool_id,
+            provider_id,
+            token_a_amount: round_down(owed_a),
+            token_b_amount: round_down(owed_b),
+        }
+    }
+
+    pub(crate) fn claim(
+        &mut self,
+        pool_id: Uuid,
+        provider_id: Uuid,
+        shares: Decimal,
+        claimed_at: DateTime<Utc>,
+    ) -> Result<FeeClaim, MarketMakerError> {
+        if !self.accounts.contains_key(&provider_id) {
+            return Err(MarketMakerError::NoFeesToClaim);
+        }
+        self.settle(provider_id, shares);
+        let account = self.accounts.entry(provider_id).or_default();
+        let (token_a_amount, token_b_amount) = (round_down(account.owed_a), round_down(account.owed_b));
+        if token_a_amount.is_zero() && token_b_amount.is_zero() {
+            return Err(MarketMakerError::NoFeesToClaim);
+        }
+        account.owed_a -= token_a_amount;
+        account.owed_b -= token_b_amount;
+
+        let claim = FeeClaim {
+            id: Uuid::new_v4(),
+            pool_id,
+            provider_id,
+            token_a_amount,
+            token_b_amount,
+            claimed_at,
+        };
+        self.claims.push(claim.clone());
+        Ok(claim)
+    }
+
+    pub(crate) fn claims_by(&self, provider_id: Uuid) -> Vec<FeeClaim> {
+        self.claims
+            .iter()
+            .filter(|claim| claim.provider_id == provider_id)
+            .cloned()
+            .collect()
+    }
+}
+
+/// What every two-token pool keeps apart from its curve parameters.
+pub struct PoolState {
+    pub(crate) pool: Pool,
+    /// LP shares held by each provider.
+    pub(crate) positions: HashMap<Uuid, Decimal>,
+    pub(crate) fees: FeeAccumulator,
+    pub(crate) oracle: PriceOracle,
+}
+
+impl PoolState {
+    /// State for a new pool. Its `total_shares` back any seeded reserves
+    /// and belong to no provider.
+    pub(crate) fn new(pool: Pool) -> Self {
+        Self {
+            pool,
+            positions: HashMap::new(),
+            fees: FeeAccumulator::default(),
+            oracle: PriceOracle::default(),
+        }
+    }
+
+    /// Burns shares for a proportional cut of both reserves, booking the
+    /// fees they earned first.
+    fn burn(&mut self, provider_id: Uuid, shares: Decimal) -> Result<LiquidityChange, MarketMakerError> {
+        if shares <= Decimal::ZERO {
+            return Err(MarketMakerError::InvalidAmount);
+        }
+        let held = self
+            .positions
+            .get_mut(&provider_id)
+            .ok_or(MarketMakerError::InsufficientLiquidity)?;
+        if shares > *held {
+            return Err(MarketMakerError::InsufficientLiquidity);
+        }
+        self.fees.settle(provider_id, *held);
+
+        let pool = &mut self.pool;
+        let (token_a_amount, token_b_amount) = share_of_reserves(pool, shares);
+        *held -= shares;
+        if held.is_zero() {
+            self.positions.remove(&provider_id);
+        }
+        pool.reserve_a -= token_a_amount;
+        pool.reserve_b -= token_b_amount;
+        pool.total_shares -= shares;
+
+        Ok(LiquidityChange {
+            pool_id: pool.id,
+            provider_id,
+            shares,
+            token_a_amount,
+            token_b_amount,
+            refund_a: Decimal::ZERO,
+            refund_b: Decimal::ZERO,
+        })
+    }
+}
+
+/// `shares`' proportional cut of both reserves, rounded in the pool's favour.
+fn share_of_reserves(pool: &Pool, shares: Decimal) -> (Decimal, Decimal) {
+    (
+        round_down(pool.reserve_a * shares / pool.total_shares),
+        round_down(pool.reserve_b * shares / pool.total_shares),
+    )
+}
+
+/// Rounds toward zero so amounts paid out of the pool never exceed what the
+/// math entitles.
+pub(crate) fn round_down(amount: Decimal) -> Decimal {
+    amount.round_dp_with_strategy(OUTPUT_DP, RoundingStrategy::ToZero)
+}
+
+pub(crate) fn round_up(amount: Decimal) -> Decimal {
+    amount.round_dp_with_strategy(OUTPUT_DP, RoundingStrategy::AwayFromZero)
+}
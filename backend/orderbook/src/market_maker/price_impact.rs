@@ -7,6 +7,12 @@ pub struct PriceImpactCalculator {
     impact_multiplier: Decimal,
 }
 
+impl Default for PriceImpactCalculator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PriceImpactCalculator {
     pub fn new() -> Self {
         Self {
@@ -28,11 +34,10 @@ impl PriceImpactCalculator {
             return Decimal::ZERO;
         }
 
-        let mut x = value;
         let mut x0;
         
         // Newton's method for square root with better initial guess
-        x = value / dec!(2); // Start with value/2 as initial guess
+        let mut x = value / dec!(2); // Start with value/2 as initial guess
         
         for _ in 0..20 {  // Usually converges in < 10 iterations
             x0 = x;
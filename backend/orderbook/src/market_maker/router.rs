@@ -0,0 +1,320 @@
+use rust_decimal::Decimal;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+use uuid::Uuid;
+
+use super::{
+    types::{MarketMakerError, PoolKey, RouteHop, RouteQuote},
+    AmmPool, AutomatedMarketMaker,
+};
+use crate::ledger::SwapLeg;
+
+pub const DEFAULT_MAX_HOPS: usize = 3;
+
+/// Routes swaps across pools of an `AutomatedMarketMaker`.
+///
+/// Candidate paths are priced with each pool's own curve (constant product
+/// or StableSwap) and the one paying the most is taken. Quoting read-locks
+/// only the pools on some candidate path. A routed swap write-locks every pool on its
+/// path before touching any of them, so it executes completely or not at
+/// all. Pools are always locked in key order so concurrent routes cannot
+/// deadlock. When the AMM has a ledger, every hop is posted to the trader's
+/// account before any pool moves, and routes without an account are
+/// refused.
+pub struct Router {
+    amm: Arc<AutomatedMarketMaker>,
+    max_hops: usize,
+}
+
+impl Router {
+    pub fn new(amm: Arc<AutomatedMarketMaker>) -> Self {
+        Self {
+            amm,
+            max_hops: DEFAULT_MAX_HOPS,
+        }
+    }
+
+    pub fn with_max_hops(mut self, max_hops: usize) -> Self {
+        self.max_hops = max_hops;
+        self
+    }
+
+    /// The path from `token_in` to `token_out` paying the most.
+    pub async fn quote(&self, token_in: &str, token_out: &str, amount_in: Decimal) -> Result<RouteQuote, MarketMakerError> {
+        let handles = self.amm.pool_handles().await;
+        let keys: Vec<&PoolKey> = handles.iter().map(|(key, _)| key).collect();
+        let paths = candidate_paths(&keys, token_in, token_out, self.max_hops);
+
+        // Handles come in key order, so ascending indices keep the lock order.
+        let on_path: BTreeSet<usize> = paths.iter().flatten().copied().collect();
+        let mut guards = HashMap::new();
+        for index in on_path {
+            guards.insert(index, handles[index].1.clone().read_owned().await);
+        }
+
+        paths
+            .into_iter()
+            .filter_map(|path| {
+                let path: Vec<(&PoolKey, &dyn AmmPool)> = path.into_iter().map(|index| (keys[index], &*guards[&index])).collect();
+                price_path(&path, token_in, amount_in).ok()
+            })
+            .fold(None, |best: Option<RouteQuote>, quote| match best {
+                Some(best) if best.amount_out >= quote.amount_out => Some(best),
+                _ => Some(quote),
+            })
+            .ok_or_else(|| MarketMakerError::NoRoute(token_in.to_string(), token_out.to_string()))
+    }
+
+    /// Swaps along the best path, failing without touching any pool if the
+    /// final output is below `min_output`.
+    pub async fn swap(
+        &self,
+        token_in: &str,
+        token_out: &str,
+        amount_in: Decimal,
+        min_output: Decimal,
+    ) -> Result<RouteQuote, MarketMakerError> {
+        if self.amm.ledger().is_some() {
+            return Err(MarketMakerError::AccountRequired);
+        }
+        self.execute(None, token_in, token_out, amount_in, min_output).await
+    }
+
+    /// Swaps along the best path on behalf of a trader, posting every hop
+    /// to the ledger as one unit.
+    pub async fn swap_for_account(
+        &self,
+        trader_id: Uuid,
+        token_in: &str,
+        token_out: &str,
+        amount_in: Decimal,
+        min_output: Decimal,
+    ) -> Result<RouteQuote, MarketMakerError> {
+        self.execute(Some(trader_id), token_in, token_out, amount_in, min_output).await
+    }
+
+    async fn execute(
+        &self,
+        trader_id: Option<Uuid>,
+        token_in: &str,
+        token_out: &str,
+        amount_in: Decimal,
+        min_output: Decimal,
+    ) -> Result<RouteQuote, MarketMakerError> {
+        let route = self.quote(token_in, token_out, amount_in).await?;
+
+        let mut locked = HashMap::new();
+        for (key, pool) in self.amm.pool_handles().await {
+            if route.hops.iter().any(|hop| hop.pool == key) {
+                locked.insert(key, pool.write_owned().await);
+            }
+        }
+        let mut path = Vec::with_capacity(route.hops.len());
+        for hop in &route.hops {
+            let pool = locked
+                .get(&hop.pool)
+                .ok_or_else(|| MarketMakerError::PoolNotFound(hop.pool.clone()))?;
+            path.push((&hop.pool, &**pool));
+        }
+
+        // Reserves may have moved since the quote; re-price on the locked
+        // pools before writing anything.
+        let route = price_path(&path, token_in, amount_in)?;
+        if route.amount_out < min_output {
+            return Err(MarketMakerError::SlippageExceeded);
+        }
+        if let (Some(ledger), Some(trader_id)) = (self.amm.ledger(), trader_id) {
+            let legs: Vec<SwapLeg> = path
+                .iter()
+                .zip(&route.hops)
+                .map(|((_, pool), hop)| SwapLeg {
+                    pool_id: pool.pool_info().id,
+                    asset_in: hop.token_in.clone(),
+                    amount_in: hop.amount_in,
+                    asset_out: hop.token_out.clone(),
+                    amount_out: hop.amount_out,
+                })
+                .collect();
+            ledger.record_swaps(trader_id, &legs)?;
+        }
+        for hop in &route.hops {
+            if let Some(pool) = locked.get_mut(&hop.pool) {
+                pool.swap_exact_input(&hop.token_in, hop.amount_in, hop.amount_out)?;
+            }
+        }
+        Ok(route)
+    }
+}
+
+/// Pool index sequences leading from `token_in` to `token_out` in at most
+/// `max_hops` pools without visiting a token twice.
+fn candidate_paths(keys: &[&PoolKey], token_in: &str, token_out: &str, max_hops: usize) -> Vec<Vec<usize>> {
+    let mut paths = Vec::new();
+    let mut stack = vec![(token_in, Vec::new(), vec![token_in])];
+    while let Some((token, path, visited)) = stack.pop() {
+        for (index, key) in keys.iter().enumerate() {
+            if !key.contains(token) {
+                continue;
+            }
+            let next = if key.token_0 == token { key.token_1.as_str() } else { key.token_0.as_str() };
+            if visited.contains(&next) {
+                continue;
+            }
+            let mut path = path.clone();
+            path.push(index);
+            if next == token_out {
+                paths.push(path);
+            } else if path.len() < max_hops {
+                let mut visited = visited.clone();
+                visited.push(next);
+                stack.push((next, path, visited));
+            }
+        }
+    }
+    paths
+}
+
+fn price_path(path: &[(&PoolKey, &dyn AmmPool)], token_in: &str, amount_in: Decimal) -> Result<RouteQuote, MarketMakerError> {
+    let mut hops = Vec::with_capacity(path.len());
+    let mut token = token_in.to_string();
+    let mut amount = amount_in;
+    for (key, pool) in path {
+        let result = pool.quote_exact_input(&token, amount)?;
+        let token_out = pool.other_token(&token)?.to_string();
+        hops.push(RouteHop {
+            pool: (*key).clone(),
+            token_in: token,
+            token_out: token_out.clone(),
+            amount_in: amount,
+            amount_out: result.output_amount,
+            fee_amount: result.fee_amount,
+        });
+        token = token_out;
+        amount = result.output_amount;
+    }
+    Ok(RouteQuote {
+        hops,
+        amount_in,
+        amount_out: amount,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::{Ledger, LedgerError};
+    use rust_decimal_macros::dec;
+
+    async fn three_pools() -> Arc<AutomatedMarketMaker> {
+        let amm = Arc::new(AutomatedMarketMaker::new());
+        amm.create_pool("USDC".to_string(), "ETH".to_string(), dec!(2000000), dec!(1000), dec!(0.003)).await.unwrap();
+        amm.create_pool("ETH".to_string(), "BTC".to_string(), dec!(2000), dec!(100), dec!(0.003)).await.unwrap();
+        // Thin direct pool: routing through ETH pays more.
+        amm.create_pool("USDC".to_string(), "BTC".to_string(), dec!(40000), dec!(1), dec!(0.003)).await.unwrap();
+        amm
+    }
+
+    #[tokio::test]
+    async fn test_best_route_goes_through_deeper_pools() {
+        let router = Router::new(three_pools().await);
+
+        let route = router.quote("USDC", "BTC", dec!(10000)).await.unwrap();
+        let tokens: Vec<&str> = route.hops.iter().map(|hop| hop.token_out.as_str()).collect();
+        assert_eq!(tokens, vec!["ETH", "BTC"]);
+        assert_eq!(route.hops[1].amount_in, route.hops[0].amount_out);
+
+        let direct = router.with_max_hops(1).quote("USDC", "BTC", dec!(10000)).await.unwrap();
+        assert_eq!(direct.hops.len(), 1);
+        assert!(direct.amount_out < route.amount_out);
+    }
+
+    #[tokio::test]
+    async fn test_routed_swap_is_all_or_nothing() {
+        let amm = three_pools().await;
+        let router = Router::new(amm.clone());
+        let usdc_eth = PoolKey::new("USDC", "ETH", dec!(0.003));
+        let eth_btc = PoolKey::new("ETH", "BTC", dec!(0.003));
+
+        let quote = router.quote("USDC", "BTC", dec!(10000)).await.unwrap();
+        assert!(matches!(
+            router.swap("USDC", "BTC", dec!(10000), quote.amount_out + dec!(0.00000001)).await,
+            Err(MarketMakerError::SlippageExceeded)
+        ));
+        assert_eq!(amm.get_pool_info(&usdc_eth).await.unwrap().reserve_a, dec!(2000000));
+
+        let route = router.swap("USDC", "BTC", dec!(10000), quote.amount_out).await.unwrap();
+        assert_eq!(route.amount_out, quote.amount_out);
+        assert_eq!(amm.get_pool_info(&usdc_eth).await.unwrap().reserve_a, dec!(2009970));
+        let eth_btc = amm.get_pool_info(&eth_btc).await.unwrap();
+        assert_eq!(eth_btc.reserve_b, dec!(100) - route.amount_out);
+    }
+
+    #[tokio::test]
+    async fn test_no_route_between_unconnected_tokens() {
+        let router = Router::new(three_pools().await);
+        assert!(matches!(
+            router.quote("USDC", "SOL", dec!(100)).await,
+            Err(MarketMakerError::NoRoute(_, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_routed_swap_posts_every_hop() {
+        let ledger = Arc::new(Ledger::new());
+        let amm = Arc::new(AutomatedMarketMaker::new().with_ledger(ledger.clone()));
+        let (usdc_eth, eth_btc) = (PoolKey::new("USDC", "ETH", dec!(0.003)), PoolKey::new("ETH", "BTC", dec!(0.003)));
+        amm.create_pool("USDC".to_string(), "ETH".to_string(), dec!(0), dec!(0), dec!(0.003)).await.unwrap();
+        amm.create_pool("ETH".to_string(), "BTC".to_string(), dec!(0), dec!(0), dec!(0.003)).await.unwrap();
+        let provider = Uuid::new_v4();
+        for (asset, amount) in [("USDC", dec!(20000)), ("ETH", dec!(30)), ("BTC", dec!(1))] {
+            ledger.deposit(provider, asset, amount).unwrap();
+        }
+        let first = amm.add_liquidity(&usdc_eth, provider, dec!(20000), dec!(10)).await.unwrap();
+        let second = amm.add_liquidity(&eth_btc, provider, dec!(20), dec!(1)).await.unwrap();
+        let router = Router::new(amm.clone());
+
+        assert!(matches!(
+            router.swap("USDC", "BTC", dec!(5000), Decimal::ZERO).await,
+            Err(MarketMakerError::AccountRequired)
+        ));
+
+        // A trader who can't fund the first leg leaves every pool untouched.
+        let trader = Uuid::new_v4();
+        ledger.deposit(trader, "USDC", dec!(4999)).unwrap();
+        assert!(matches!(
+            router.swap_for_account(trader, "USDC", "BTC", dec!(5000), Decimal::ZERO).await,
+            Err(MarketMakerError::Ledger(LedgerError::InsufficientBalance { .. }))
+        ));
+        assert_eq!(amm.get_pool_info(&usdc_eth).await.unwrap().reserve_a, dec!(20000));
+
+        ledger.deposit(trader, "USDC", dec!(1)).unwrap();
+        let route = router.swap_for_account(trader, "USDC", "BTC", dec!(5000), Decimal::ZERO).await.unwrap();
+        let account = ledger.account(trader);
+        assert_eq!(account.get_balance("USDC"), Decimal::ZERO);
+        assert_eq!(account.get_balance("ETH"), Decimal::ZERO);
+        assert_eq!(account.get_balance("BTC"), route.amount_out);
+
+        // The pools' ledger balances kept up with their reserves, so the LP
+        // can still withdraw everything.
+        amm.remove_liquidity(&usdc_eth, provider, first.shares).await.unwrap();
+        amm.remove_liquidity(&eth_btc, provider, second.shares).await.unwrap();
+        ledger.check_invariants().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_quote_skips_pools_off_every_path() {
+        let amm = three_pools().await;
+        amm.create_pool("SOL".to_string(), "DOGE".to_string(), dec!(1000), dec!(1000000), dec!(0.003)).await.unwrap();
+        let router = Router::new(amm.clone());
+
+        // A swap in flight on an unrelated pool doesn't hold up the quote.
+        let sol_doge = PoolKey::new("SOL", "DOGE", dec!(0.003));
+        let (_, pool) = amm.pool_handles().await.into_iter().find(|(key, _)| *key == sol_doge).unwrap();
+        let _busy = pool.write_owned().await;
+        let quote = tokio::time::timeout(std::time::Duration::from_secs(1), router.quote("USDC", "BTC", dec!(10000)))
+            .await
+            .expect("quote waited on a pool off its paths");
+        assert_eq!(quote.unwrap().hops.len(), 2);
+    }
+}
@@ -0,0 +1,377 @@
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use uuid::Uuid;
+
+use super::pool::{round_down, round_up, AmmPool, PoolState, MINIMUM_LIQUIDITY};
+use super::types::{LiquidityChange, MarketMakerError, Pool, PoolCurve, SwapResult, ZapResult};
+
+/// Number of coins in the pool, `n` in the StableSwap paper.
+const N: Decimal = Decimal::TWO;
+const MAX_ITERATIONS: usize = 255;
+const CONVERGENCE: Decimal = dec!(0.000000000001);
+
+/// Curve StableSwap pool for pegged pairs.
+///
+/// Balances satisfy `A*n^n*(x + y) + D = A*D*n^n + D^(n+1) / (n^n*x*y)`.
+/// Near the peg the curve is close to a constant sum, so swaps pay almost
+/// 1:1; a larger amplification `A` keeps it flat further from the peg. The
+/// fee is taken from the input like the constant product pool, and LP
+/// shares are denominated in `D`.
+///
+/// `D` cubed overflows `Decimal` for reserves in the billions, so the math
+/// only ever multiplies `D` by ratios of `D` to the balances, which stay
+/// small however large the pool is.
+pub struct StableSwapPool {
+    state: PoolState,
+    amplification: Decimal,
+}
+
+impl StableSwapPool {
+    pub fn new(
+        token_a: String,
+        token_b: String,
+        reserve_a: Decimal,
+        reserve_b: Decimal,
+        fee_percentage: Decimal,
+        amplification: Decimal,
+    ) -> Self {
+        let mut pool = Self {
+            state: PoolState::new(Pool {
+                id: Uuid::new_v4(),
+                token_a,
+                token_b,
+                reserve_a,
+                reserve_b,
+                fee_percentage,
+                total_shares: round_down(invariant(reserve_a, reserve_b, amplification)),
+                curve: PoolCurve::StableSwap { amplification },
+            }),
+            amplification,
+        };
+        pool.record_price();
+        pool
+    }
+
+    pub fn amplification(&self) -> Decimal {
+        self.amplification
+    }
+
+    /// The invariant `D` at the current balances.
+    pub fn invariant(&self) -> Decimal {
+        invariant(self.state.pool.reserve_a, self.state.pool.reserve_b, self.amplification)
+    }
+
+    /// Marginal price of `input_token` in units of the other token, or
+    /// `None` if it is out of `Decimal` range.
+    fn spot_price(&self, reserve_in: Decimal, reserve_out: Decimal) -> Option<Decimal> {
+        let ann = self.amplification * N;
+        let d = self.invariant();
+        // D^3 / (4 * x * y) divided by the balance of each side.
+        let d_p = (d / (reserve_in * N))
+            .checked_mul(d / (reserve_out * N))?
+            .checked_mul(d)?;
+        ann.checked_add(d_p / reserve_in)?
+            .checked_div(ann.checked_add(d_p / reserve_out)?)
+    }
+}
+
+impl AmmPool for StableSwapPool {
+    fn state(&self) -> &PoolState {
+        &self.state
+    }
+
+    fn state_mut(&mut self) -> &mut PoolState {
+        &mut self.state
+    }
+
+    /// Skips the observation when the price can't be computed, so the
+    /// oracle never blocks a trade or a deposit.
+    fn record_price(&mut self) {
+        if self.state.pool.reserve_a.is_zero() || self.state.pool.reserve_b.is_zero() {
+            return;
+        }
+        match self.spot_price(self.state.pool.reserve_a, self.state.pool.reserve_b) {
+            Some(price) => self.state.oracle.record(price),
+            None => log::warn!("skipped price observation for pool {}: spot price out of range", self.state.pool.id),
+        }
+    }
+
+    fn quote_exact_input(&self, input_token: &str, input_amount: Decimal) -> Result<SwapResult, MarketMakerError> {
+        if input_amount <= Decimal::ZERO {
+            return Err(MarketMakerError::InvalidAmount);
+        }
+        let (reserve_in, reserve_out) = self.reserves_for(input_token)?;
+        if reserve_in.is_zero() || reserve_out.is_zero() {
+            return Err(MarketMakerError::InsufficientLiquidity);
+        }
+
+        let fee_amount = self.calculate_fee(input_amount);
+        let in_after_fee = input_amount - fee_amount;
+        let d = self.invariant();
+        // Round the new balance up so the invariant never loses precision
+        // to the trader.
+        let new_out = round_up(balance_for(reserve_in + in_after_fee, d, self.amplification));
+        let output_amount = round_down(reserve_out - new_out).max(Decimal::ZERO);
+        if output_amount.is_zero() {
+            return Err(MarketMakerError::InvalidAmount);
+        }
+
+        let spot = self
+            .spot_price(reserve_in, reserve_out)
+            .ok_or(MarketMakerError::InvalidPoolParameters)?;
+        let price_impact = (Decimal::ONE - output_amount / (in_after_fee * spot)).max(Decimal::ZERO);
+
+        Ok(SwapResult {
+            input_amount,
+            output_amount,
+            price_impact,
+            fee_amount,
+        })
+    }
+
+    /// Deposits any mix of the two tokens.
+    ///
+    /// Shares are minted in proportion to the growth of `D`. Deposits that
+    /// move the balances away from their current proportions pay the swap
+    /// fee at half rate on the imbalance, as in Curve, so depositing one
+    /// side and withdrawing both is no cheaper than swapping. Like swap
+    /// fees, the imbalance fee is kept out of the reserves and credited to
+    /// the shares outstanding before the deposit.
+    fn add_liquidity(
+        &mut self,
+        provider_id: Uuid,
+        amount_a: Decimal,
+        amount_b: Decimal,
+    ) -> Result<LiquidityChange, MarketMakerError> {
+        if amount_a < Decimal::ZERO || amount_b < Decimal::ZERO || (amount_a + amount_b).is_zero() {
+            return Err(MarketMakerError::InvalidAmount);
+        }
+        let (old_a, old_b) = (self.state.pool.reserve_a, self.state.pool.reserve_b);
+        let (new_a, new_b) = (old_a + amount_a, old_b + amount_b);
+
+        let (mut fee_a, mut fee_b) = (Decimal::ZERO, Decimal::ZERO);
+        let shares = if self.state.pool.total_shares.is_zero() {
+            if amount_a.is_zero() || amount_b.is_zero() {
+                return Err(MarketMakerError::InvalidAmount);
+            }
+            let d = round_down(invariant(new_a, new_b, self.amplification));
+            if d <= MINIMUM_LIQUIDITY {
+                return Err(MarketMakerError::InsufficientLiquidity);
+            }
+            self.state.pool.total_shares = MINIMUM_LIQUIDITY;
+            d - MINIMUM_LIQUIDITY
+        } else {
+            let d0 = invariant(old_a, old_b, self.amplification);
+            let d1 = invariant(new_a, new_b, self.amplification);
+            let imbalance_fee = self.state.pool.fee_percentage * N / (Decimal::from(4) * (N - Decimal::ONE));
+            let charge = |old: Decimal, new: Decimal| round_up(imbalance_fee * (d1 / d0 * old - new).abs());
+            (fee_a, fee_b) = (charge(old_a, new_a), charge(old_b, new_b));
+            let d2 = invariant(new_a - fee_a, new_b - fee_b, self.amplification);
+            round_down(self.state.pool.total_shares * ((d2 - d0) / d0))
+        };
+        if shares <= Decimal::ZERO {
+            return Err(MarketMakerError::InvalidAmount);
+        }
+
+        let provider_shares = self.provider_shares();
+        self.state.fees.credit(true, fee_a, provider_shares, self.state.pool.total_shares);
+        self.state.fees.credit(false, fee_b, provider_shares, self.state.pool.total_shares);
+        self.state.pool.reserve_a = new_a - fee_a;
+        self.state.pool.reserve_b = new_b - fee_b;
+        self.state.pool.total_shares += shares;
+        let held = self.state.positions.entry(provider_id).or_default();
+        self.state.fees.settle(provider_id, *held);
+        *held += shares;
+
+        self.record_price();
+
+        Ok(LiquidityChange {
+            pool_id: self.state.pool.id,
+            provider_id,
+            shares,
+            token_a_amount: amount_a,
+            token_b_amount: amount_b,
+            refund_a: Decimal::ZERO,
+            refund_b: Decimal::ZERO,
+        })
+    }
+
+    /// Single-sided deposits need no swap on this curve; the imbalance fee
+    /// of `add_liquidity` covers them.
+    fn zap_in(&mut self, provider_id: Uuid, input_token: &str, amount: Decimal) -> Result<ZapResult, MarketMakerError> {
+        self.reserves_for(input_token)?;
+        if self.state.pool.total_shares.is_zero() {
+            return Err(MarketMakerError::InsufficientLiquidity);
+        }
+        let deposit = if input_token == self.state.pool.token_a {
+            self.add_liquidity(provider_id, amount, Decimal::ZERO)?
+        } else {
+            self.add_liquidity(provider_id, Decimal::ZERO, amount)?
+        };
+        Ok(ZapResult { swap: None, deposit })
+    }
+
+}
+
+/// Solves for `D` by Newton's method.
+fn invariant(x: Decimal, y: Decimal, amplification: Decimal) -> Decimal {
+    let sum = x + y;
+    if x.is_zero() || y.is_zero() {
+        return Decimal::ZERO;
+    }
+    let ann = amplification * N;
+    let mut d = sum;
+    for _ in 0..MAX_ITERATIONS {
+        // `D_P / D`, which Curve's Newton step is rewritten in terms of.
+        let ratio = d / (x * N) * (d / (y * N));
+        let previous = d;
+        d = (ann * sum / ratio + N * d) / ((ann - Decimal::ONE) / ratio + N + Decimal::ONE);
+        if (d - previous).abs() <= CONVERGENCE {
+            break;
+        }
+    }
+    d
+}
+
+/// Solves for the balance of one coin given the other's balance `x` and
+/// the invariant `d`.
+fn balance_for(x: Decimal, d: Decimal, amplification: Decimal) -> Decimal {
+    let ann = amplification * N;
+    let c_over_d = d / (x * N) * (d / (ann * N));
+    let b = x + d / ann;
+    let mut y = d;
+    for _ in 0..MAX_ITERATIONS {
+        let previous = y;
+        // `y = (y^2 + c) / (2y + b - D)` with both sides divided by `y`.
+        y = (y + c_over_d * (d / y)) / (N + (b - d) / y);
+        if (y - previous).abs() <= CONVERGENCE {
+            break;
+        }
+    }
+    y
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market_maker::LiquidityPool;
+    use proptest::prelude::*;
+
+    fn usdc_usdt(reserve: Decimal) -> StableSwapPool {
+        StableSwapPool::new("USDC".to_string(), "USDT".to_string(), reserve, reserve, dec!(0.0004), dec!(100))
+    }
+
+    #[test]
+    fn test_balanced_invariant_is_sum() {
+        let pool = usdc_usdt(dec!(1000000));
+        assert_eq!(pool.invariant().round_dp(6), dec!(2000000));
+        assert_eq!(pool.pool_info().total_shares, dec!(2000000));
+    }
+
+    #[test]
+    fn test_pegged_swap_beats_constant_product() {
+        let stable = usdc_usdt(dec!(1000000));
+        let constant_product = LiquidityPool::new("USDC".to_string(), "USDT".to_string(), dec!(1000000), dec!(1000000), dec!(0.0004));
+
+        let quote = stable.quote_exact_input("USDC", dec!(100000)).unwrap();
+        let reference = constant_product.quote_exact_input("USDC", dec!(100000)).unwrap();
+        assert!(quote.output_amount > dec!(99800));
+        assert!(reference.output_amount < dec!(91000));
+        assert!(quote.price_impact < dec!(0.0015));
+    }
+
+    #[test]
+    fn test_imbalanced_deposit_pays_fee() {
+        let mut pool = usdc_usdt(dec!(0));
+        pool.add_liquidity(Uuid::new_v4(), dec!(1000000), dec!(1000000)).unwrap();
+
+        let balanced = pool.add_liquidity(Uuid::new_v4(), dec!(5000), dec!(5000)).unwrap();
+        let provider_id = Uuid::new_v4();
+        let zap = pool.zap_in(provider_id, "USDT", dec!(10000)).unwrap();
+        assert!(zap.swap.is_none());
+        assert!(zap.deposit.shares < balanced.shares);
+        assert!(zap.deposit.shares > balanced.shares * dec!(0.999));
+
+        // Withdrawing right away returns less than was deposited.
+        let burned = pool.remove_liquidity(provider_id, zap.deposit.shares).unwrap();
+        assert!(burned.token_a_amount + burned.token_b_amount < dec!(10000));
+    }
+
+    #[test]
+    fn test_imbalance_fee_is_credited_to_existing_shares() {
+        let mut pool = usdc_usdt(dec!(0));
+        let first = Uuid::new_v4();
+        pool.add_liquidity(first, dec!(1000000), dec!(1000000)).unwrap();
+
+        let zap = pool.zap_in(Uuid::new_v4(), "USDT", dec!(10000)).unwrap();
+        let earned = pool.earned_fees(first);
+        assert!(earned.token_b_amount > Decimal::ZERO);
+        assert!(earned.token_a_amount > Decimal::ZERO);
+        // The fee left the reserves rather than backing the new shares.
+        assert!(pool.pool_info().reserve_b < dec!(1010000));
+        assert_eq!(pool.earned_fees(zap.deposit.provider_id).token_b_amount, Decimal::ZERO);
+        assert!((pool.invariant() - pool.pool_info().total_shares).abs() < dec!(0.01));
+    }
+
+    #[test]
+    fn test_near_max_reserves_do_not_overflow() {
+        let reserve = Decimal::from(u64::MAX);
+        let mut pool = usdc_usdt(reserve);
+        assert_eq!(pool.invariant().round(), reserve * N);
+
+        let quote = pool.swap_exact_input("USDC", reserve / dec!(10), Decimal::ZERO).unwrap();
+        assert!(quote.output_amount > reserve / dec!(11));
+        assert!(quote.output_amount < reserve / dec!(10));
+        pool.add_liquidity(Uuid::new_v4(), reserve, Decimal::ZERO).unwrap();
+    }
+
+    #[test]
+    fn test_large_pool_records_price_on_creation() {
+        for reserve in [dec!(5000000000), dec!(1000000000000), Decimal::from(u64::MAX)] {
+            let pool = usdc_usdt(reserve);
+            let observation = pool.oracle().observe().unwrap();
+            assert_eq!(observation.price.round_dp(8), Decimal::ONE);
+        }
+
+        let skewed = StableSwapPool::new(
+            "USDC".to_string(),
+            "USDT".to_string(),
+            Decimal::from(u64::MAX),
+            dec!(1000),
+            dec!(0.0004),
+            dec!(1),
+        );
+        assert!(skewed.oracle().observe().unwrap().price < Decimal::ONE);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_invariant_never_decreases(
+            reserve_a in 1_000u64..u64::MAX,
+            reserve_b in 1_000u64..u64::MAX,
+            amplification in 1u64..2_000,
+            swaps in prop::collection::vec((any::<bool>(), 1u64..u64::MAX / 4), 1..10),
+        ) {
+            let mut pool = StableSwapPool::new(
+                "A".to_string(),
+                "B".to_string(),
+                reserve_a.into(),
+                reserve_b.into(),
+                dec!(0.0004),
+                amplification.into(),
+            );
+
+            for (a_to_b, amount) in swaps {
+                let input_token = if a_to_b { "A" } else { "B" };
+                let d_before = pool.invariant();
+                let (_, reserve_out) = pool.reserves_for(input_token).unwrap();
+
+                let result = pool.swap_exact_input(input_token, amount.into(), Decimal::ZERO).unwrap();
+                prop_assert!(result.output_amount < reserve_out);
+                // Balances near `u64::MAX` leave `D` fewer decimal places.
+                let precision = CONVERGENCE.max(d_before * dec!(0.0000000000000000000001));
+                prop_assert!(pool.invariant() >= d_before - precision);
+            }
+        }
+    }
+}
@@ -1,7 +1,11 @@
+use chrono::{DateTime, Utc};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use uuid::Uuid;
 
+use crate::ledger::LedgerError;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Pool {
     pub id: Uuid,
@@ -10,17 +14,148 @@ pub struct Pool {
     pub reserve_a: Decimal,
     pub reserve_b: Decimal,
     pub fee_percentage: Decimal,
+    /// LP shares outstanding, including the locked minimum.
+    pub total_shares: Decimal,
+    pub curve: PoolCurve,
+}
+
+/// Invariant a two-token pool prices swaps with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PoolCurve {
+    /// `x * y = k`.
+    ConstantProduct,
+    /// Curve StableSwap with amplification coefficient `A`.
+    StableSwap { amplification: Decimal },
+}
+
+impl Pool {
+    pub fn key(&self) -> PoolKey {
+        PoolKey::new(&self.token_a, &self.token_b, self.fee_percentage)
+    }
+}
+
+/// Registry key of a pool: its token pair in sorted order and its fee tier,
+/// so either ordering of the pair finds the same pool.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct PoolKey {
+    pub token_0: String,
+    pub token_1: String,
+    pub fee_tier: Decimal,
+}
+
+impl PoolKey {
+    pub fn new(token_a: &str, token_b: &str, fee_tier: Decimal) -> Self {
+        let (token_0, token_1) = if token_a <= token_b { (token_a, token_b) } else { (token_b, token_a) };
+        Self {
+            token_0: token_0.to_string(),
+            token_1: token_1.to_string(),
+            fee_tier: fee_tier.normalize(),
+        }
+    }
+
+    pub fn contains(&self, token: &str) -> bool {
+        self.token_0 == token || self.token_1 == token
+    }
 }
 
+impl fmt::Display for PoolKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}@{}", self.token_0, self.token_1, self.fee_tier)
+    }
+}
+
+/// A provider's LP shares and what they currently redeem for.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PoolPosition {
     pub pool_id: Uuid,
     pub provider_id: Uuid,
+    pub shares: Decimal,
     pub token_a_amount: Decimal,
     pub token_b_amount: Decimal,
     pub share_percentage: Decimal,
 }
 
+/// Shares minted or burned by one deposit or withdrawal, with the token
+/// amounts that moved.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiquidityChange {
+    pub pool_id: Uuid,
+    pub provider_id: Uuid,
+    pub shares: Decimal,
+    pub token_a_amount: Decimal,
+    pub token_b_amount: Decimal,
+    /// Part of a deposit not taken because it exceeded the reserve ratio.
+    pub refund_a: Decimal,
+    pub refund_b: Decimal,
+}
+
+/// A single-token deposit: the internal swap, if the curve needs one, then
+/// the deposit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZapResult {
+    pub swap: Option<SwapResult>,
+    pub deposit: LiquidityChange,
+}
+
+/// Swap fees a provider has earned and not yet claimed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EarnedFees {
+    pub pool_id: Uuid,
+    pub provider_id: Uuid,
+    pub token_a_amount: Decimal,
+    pub token_b_amount: Decimal,
+}
+
+/// A payout of earned fees, kept as the provider's claim history.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeClaim {
+    pub id: Uuid,
+    pub pool_id: Uuid,
+    pub provider_id: Uuid,
+    pub token_a_amount: Decimal,
+    pub token_b_amount: Decimal,
+    pub claimed_at: DateTime<Utc>,
+}
+
+/// A pool's cumulative price accumulators at one instant.
+///
+/// Each accumulator sums a price times the seconds it was in effect;
+/// prices are of `token_a` in units of `token_b`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PriceObservation {
+    pub timestamp: DateTime<Utc>,
+    /// Spot price in effect from `timestamp` on.
+    pub price: Decimal,
+    pub price_a_cumulative: Decimal,
+    /// Accumulates `1 / price`, the price of `token_b` in `token_a`.
+    pub price_b_cumulative: Decimal,
+    /// Accumulates `ln(price)`, for the geometric mean.
+    pub log_price_cumulative: Decimal,
+}
+
+/// Time-weighted average prices over `[start, end]`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TwapPrice {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// `token_a` priced in `token_b`.
+    pub price_a: Decimal,
+    /// `token_b` priced in `token_a`.
+    pub price_b: Decimal,
+}
+
+/// How `AutomatedMarketMaker` prices swaps.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PricingMode {
+    /// Uniswap-v2 constant product: the fee is taken from the input and
+    /// `reserve_a * reserve_b` never decreases.
+    #[default]
+    ConstantProduct,
+    /// Constant product output reduced by the `PriceImpactCalculator`
+    /// penalty, with the fee deducted from the output.
+    ImpactAdjusted,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SwapResult {
     pub input_amount: Decimal,
@@ -29,6 +164,97 @@ pub struct SwapResult {
     pub fee_amount: Decimal,
 }
 
+/// A member of a weighted pool.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeightedToken {
+    pub token: String,
+    /// Normalized weight; the weights of a pool sum to one.
+    pub weight: Decimal,
+    pub balance: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeightedPoolInfo {
+    pub id: Uuid,
+    pub tokens: Vec<WeightedToken>,
+    pub fee_percentage: Decimal,
+    pub total_shares: Decimal,
+}
+
+/// Shares minted or burned by a weighted pool join or exit, with the
+/// amount of each token that moved.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeightedLiquidityChange {
+    pub pool_id: Uuid,
+    pub provider_id: Uuid,
+    pub shares: Decimal,
+    pub amounts: Vec<(String, Decimal)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConcentratedPoolInfo {
+    pub id: Uuid,
+    pub token_0: String,
+    pub token_1: String,
+    pub fee_percentage: Decimal,
+    pub tick_spacing: i32,
+    /// Square root of the price of `token_0` in `token_1`.
+    pub sqrt_price: Decimal,
+    /// Greatest tick at or below the current price.
+    pub tick: i32,
+    /// Liquidity of the positions whose range contains the current price.
+    pub liquidity: Decimal,
+    /// Fees earned per unit of liquidity since the pool opened, by token.
+    pub fee_growth_global_0: Decimal,
+    pub fee_growth_global_1: Decimal,
+}
+
+/// Liquidity a provider holds between two ticks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConcentratedPosition {
+    pub pool_id: Uuid,
+    pub provider_id: Uuid,
+    pub tick_lower: i32,
+    pub tick_upper: i32,
+    pub liquidity: Decimal,
+    pub fee_growth_inside_0_last: Decimal,
+    pub fee_growth_inside_1_last: Decimal,
+    /// Fees accrued and not yet collected.
+    pub tokens_owed_0: Decimal,
+    pub tokens_owed_1: Decimal,
+}
+
+/// Liquidity added to or removed from a range, with the token amounts that
+/// moved.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RangeLiquidityChange {
+    pub pool_id: Uuid,
+    pub provider_id: Uuid,
+    pub tick_lower: i32,
+    pub tick_upper: i32,
+    pub liquidity: Decimal,
+    pub amount_0: Decimal,
+    pub amount_1: Decimal,
+}
+
+/// One pool crossed by a routed swap.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteHop {
+    pub pool: PoolKey,
+    pub token_in: String,
+    pub token_out: String,
+    pub amount_in: Decimal,
+    pub amount_out: Decimal,
+    pub fee_amount: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteQuote {
+    pub hops: Vec<RouteHop>,
+    pub amount_in: Decimal,
+    pub amount_out: Decimal,
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum MarketMakerError {
     #[error("Insufficient liquidity")]
@@ -39,4 +265,26 @@ pub enum MarketMakerError {
     SlippageExceeded,
     #[error("Invalid pool parameters")]
     InvalidPoolParameters,
+    #[error("Pool not found: {0}")]
+    PoolNotFound(PoolKey),
+    #[error("Weighted pool not found: {0}")]
+    WeightedPoolNotFound(Uuid),
+    #[error("Concentrated pool not found: {0}")]
+    ConcentratedPoolNotFound(Uuid),
+    #[error("Invalid tick range {0}..{1}")]
+    InvalidTickRange(i32, i32),
+    #[error("Token {0} is not in the pool")]
+    TokenNotInPool(String),
+    #[error("No route from {0} to {1}")]
+    NoRoute(String, String),
+    #[error("Invalid amount")]
+    InvalidAmount,
+    #[error("No fees to claim")]
+    NoFeesToClaim,
+    #[error("Not enough price history for the window")]
+    InsufficientPriceHistory,
+    #[error("Ledger error: {0}")]
+    Ledger(#[from] LedgerError),
+    #[error("Pools with a ledger attached only swap for a trader account")]
+    AccountRequired,
 } 
\ No newline at end of file
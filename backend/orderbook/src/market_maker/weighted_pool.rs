@@ -0,0 +1,471 @@
+use rust_decimal::{Decimal, MathematicalOps};
+use rust_decimal_macros::dec;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+use super::pool::{round_down, round_up, MINIMUM_LIQUIDITY};
+use super::types::{MarketMakerError, SwapResult, WeightedLiquidityChange, WeightedPoolInfo, WeightedToken};
+
+pub const MAX_TOKENS: usize = 8;
+pub const MIN_WEIGHT: Decimal = dec!(0.01);
+/// How far the weights may sum from one, so that weights like 1/3 that
+/// have no exact decimal form are accepted. They are normalized on creation.
+pub const WEIGHT_TOLERANCE: Decimal = dec!(0.000001);
+/// Largest trade as a fraction of the balance it draws on or adds to, as in
+/// Balancer; beyond it prices become extreme.
+pub const MAX_RATIO: Decimal = dec!(0.3);
+
+/// Balancer-style pool of two to `MAX_TOKENS` tokens with fixed weights.
+///
+/// Balances keep the weighted product `V = prod(balance_i ^ weight_i)`
+/// constant across swaps, so each token holds its weight's share of the
+/// pool's value. Any two members can be swapped, and LPs can join or exit
+/// with every token or with a single one. The swap fee is taken from the
+/// input; single-token joins and exits pay it on the part of the amount
+/// that is implicitly swapped into the other tokens.
+///
+/// It is not an `AmmPool`: that trait, and the router, ledger journaling
+/// and price oracle built on it, describe a pair with one reserve per side
+/// and a single price. A pool of up to `MAX_TOKENS` has neither, so the AMM
+/// exposes it through its own `*_weighted` methods instead.
+pub struct WeightedPool {
+    info: WeightedPoolInfo,
+    /// LP shares held by each provider.
+    positions: HashMap<Uuid, Decimal>,
+}
+
+impl WeightedPool {
+    pub fn new(mut tokens: Vec<WeightedToken>, fee_percentage: Decimal) -> Result<Self, MarketMakerError> {
+        let distinct: HashSet<&str> = tokens.iter().map(|token| token.token.as_str()).collect();
+        let weight_sum = tokens.iter().map(|token| token.weight).sum::<Decimal>();
+        if tokens.len() < 2
+            || tokens.len() > MAX_TOKENS
+            || distinct.len() != tokens.len()
+            || tokens.iter().any(|token| token.weight < MIN_WEIGHT || token.balance < Decimal::ZERO)
+            || (weight_sum - Decimal::ONE).abs() > WEIGHT_TOLERANCE
+            || fee_percentage < Decimal::ZERO
+            || fee_percentage >= Decimal::ONE
+        {
+            return Err(MarketMakerError::InvalidPoolParameters);
+        }
+        let seeded = tokens.iter().filter(|token| !token.balance.is_zero()).count();
+        if seeded != 0 && seeded != tokens.len() {
+            return Err(MarketMakerError::InvalidPoolParameters);
+        }
+        for token in &mut tokens {
+            token.weight /= weight_sum;
+        }
+
+        let mut pool = Self {
+            info: WeightedPoolInfo {
+                id: Uuid::new_v4(),
+                tokens,
+                fee_percentage,
+                total_shares: Decimal::ZERO,
+            },
+            positions: HashMap::new(),
+        };
+        // Shares minted for the seeded balances stay unowned.
+        pool.info.total_shares = round_down(pool.invariant());
+        Ok(pool)
+    }
+
+    pub fn pool_info(&self) -> &WeightedPoolInfo {
+        &self.info
+    }
+
+    pub fn shares(&self, provider_id: Uuid) -> Decimal {
+        self.positions.get(&provider_id).copied().unwrap_or_default()
+    }
+
+    /// The weighted product of the balances.
+    pub fn invariant(&self) -> Decimal {
+        if self.info.tokens.iter().any(|token| token.balance.is_zero()) {
+            return Decimal::ZERO;
+        }
+        self.info
+            .tokens
+            .iter()
+            .map(|token| token.balance.powd(token.weight))
+            .product()
+    }
+
+    /// Units of `token_in` paid per unit of `token_out` at the margin,
+    /// before fees.
+    pub fn spot_price(&self, token_in: &str, token_out: &str) -> Result<Decimal, MarketMakerError> {
+        let (token_in, token_out) = (self.member(token_in)?, self.member(token_out)?);
+        if token_out.balance.is_zero() {
+            return Err(MarketMakerError::InsufficientLiquidity);
+        }
+        Ok((token_in.balance / token_in.weight) / (token_out.balance / token_out.weight))
+    }
+
+    /// `out = balance_out * (1 - (balance_in / (balance_in + in_after_fee)) ^ (weight_in / weight_out))`
+    pub fn quote_exact_input(&self, token_in: &str, token_out: &str, input_amount: Decimal) -> Result<SwapResult, MarketMakerError> {
+        if input_amount <= Decimal::ZERO {
+            return Err(MarketMakerError::InvalidAmount);
+        }
+        if token_in == token_out {
+            return Err(MarketMakerError::InvalidPoolParameters);
+        }
+        let (member_in, member_out) = (self.member(token_in)?, self.member(token_out)?);
+        if member_in.balance.is_zero() || member_out.balance.is_zero() {
+            return Err(MarketMakerError::InsufficientLiquidity);
+        }
+        if input_amount > member_in.balance * MAX_RATIO {
+            return Err(MarketMakerError::InsufficientLiquidity);
+        }
+
+        let fee_amount = input_amount * self.info.fee_percentage;
+        let in_after_fee = input_amount - fee_amount;
+        let base = member_in.balance / (member_in.balance + in_after_fee);
+        let output_amount = round_down(member_out.balance * (Decimal::ONE - base.powd(member_in.weight / member_out.weight)));
+
+        let spot = self.spot_price(token_in, token_out)?;
+        let price_impact = (Decimal::ONE - output_amount * spot / in_after_fee).max(Decimal::ZERO);
+
+        Ok(SwapResult {
+            input_amount,
+            output_amount,
+            price_impact,
+            fee_amount,
+        })
+    }
+
+    pub fn swap_exact_input(
+        &mut self,
+        token_in: &str,
+        token_out: &str,
+        input_amount: Decimal,
+        min_output: Decimal,
+    ) -> Result<SwapResult, MarketMakerError> {
+        let quote = self.quote_exact_input(token_in, token_out, input_amount)?;
+        if quote.output_amount < min_output {
+            return Err(MarketMakerError::SlippageExceeded);
+        }
+        self.member_mut(token_in)?.balance += input_amount;
+        self.member_mut(token_out)?.balance -= quote.output_amount;
+        Ok(quote)
+    }
+
+    /// Joins with every token, `amounts` in the pool's token order.
+    ///
+    /// The first join sets the balances and mints the invariant, less the
+    /// locked `MINIMUM_LIQUIDITY`. Later joins are taken in proportion to
+    /// the balances, limited by the scarcest token; the rest is not taken.
+    pub fn join(&mut self, provider_id: Uuid, amounts: &[Decimal]) -> Result<WeightedLiquidityChange, MarketMakerError> {
+        if amounts.len() != self.info.tokens.len() || amounts.iter().any(|amount| *amount <= Decimal::ZERO) {
+            return Err(MarketMakerError::InvalidAmount);
+        }
+
+        let (shares, taken) = if self.info.total_shares.is_zero() {
+            let invariant: Decimal = self
+                .info
+                .tokens
+                .iter()
+                .zip(amounts)
+                .map(|(token, amount)| amount.powd(token.weight))
+                .product();
+            let invariant = round_down(invariant);
+            if invariant <= MINIMUM_LIQUIDITY {
+                return Err(MarketMakerError::InsufficientLiquidity);
+            }
+            self.info.total_shares = MINIMUM_LIQUIDITY;
+            (invariant - MINIMUM_LIQUIDITY, amounts.to_vec())
+        } else {
+            let ratio = self
+                .info
+                .tokens
+                .iter()
+                .zip(amounts)
+                .map(|(token, amount)| *amount / token.balance)
+                .min()
+                .unwrap_or_default();
+            // Round what is taken up, in the pool's favour.
+            let taken = self
+                .info
+                .tokens
+                .iter()
+                .zip(amounts)
+                .map(|(token, amount)| round_up(token.balance * ratio).min(*amount))
+                .collect();
+            (round_down(self.info.total_shares * ratio), taken)
+        };
+        if shares.is_zero() {
+            return Err(MarketMakerError::InvalidAmount);
+        }
+
+        for (token, amount) in self.info.tokens.iter_mut().zip(&taken) {
+            token.balance += *amount;
+        }
+        self.mint(provider_id, shares);
+        Ok(self.change(provider_id, shares, taken))
+    }
+
+    /// Joins with one token, minting
+    /// `supply * ((1 + amount_after_fee / balance) ^ weight - 1)` shares.
+    pub fn join_single(&mut self, provider_id: Uuid, token: &str, amount: Decimal) -> Result<WeightedLiquidityChange, MarketMakerError> {
+        if amount <= Decimal::ZERO {
+            return Err(MarketMakerError::InvalidAmount);
+        }
+        let index = self.index(token)?;
+        let member = &self.info.tokens[index];
+        if self.info.total_shares.is_zero() {
+            return Err(MarketMakerError::InsufficientLiquidity);
+        }
+        if amount > member.balance * MAX_RATIO {
+            return Err(MarketMakerError::InsufficientLiquidity);
+        }
+
+        let after_fee = amount * (Decimal::ONE - (Decimal::ONE - member.weight) * self.info.fee_percentage);
+        let growth = ((member.balance + after_fee) / member.balance).powd(member.weight);
+        let shares = round_down(self.info.total_shares * (growth - Decimal::ONE));
+        if shares <= Decimal::ZERO {
+            return Err(MarketMakerError::InvalidAmount);
+        }
+
+        self.info.tokens[index].balance += amount;
+        self.mint(provider_id, shares);
+        Ok(self.change(provider_id, shares, self.single(index, amount)))
+    }
+
+    /// Burns shares for the same fraction of every balance.
+    pub fn exit(&mut self, provider_id: Uuid, shares: Decimal) -> Result<WeightedLiquidityChange, MarketMakerError> {
+        let total = self.info.total_shares;
+        self.burn(provider_id, shares)?;
+        let amounts: Vec<Decimal> = self
+            .info
+            .tokens
+            .iter_mut()
+            .map(|token| {
+                let amount = round_down(token.balance * shares / total);
+                token.balance -= amount;
+                amount
+            })
+            .collect();
+        Ok(self.change(provider_id, shares, amounts))
+    }
+
+    /// Burns shares for one token, paying
+    /// `balance * (1 - (1 - shares / supply) ^ (1 / weight))` less the fee.
+    pub fn exit_single(&mut self, provider_id: Uuid, token: &str, shares: Decimal) -> Result<WeightedLiquidityChange, MarketMakerError> {
+        let index = self.index(token)?;
+        if shares <= Decimal::ZERO || shares > self.shares(provider_id) {
+            return Err(MarketMakerError::InsufficientLiquidity);
+        }
+        let member = &self.info.tokens[index];
+        let remaining = (self.info.total_shares - shares) / self.info.total_shares;
+        let before_fee = member.balance * (Decimal::ONE - remaining.powd(Decimal::ONE / member.weight));
+        let amount = round_down(before_fee * (Decimal::ONE - (Decimal::ONE - member.weight) * self.info.fee_percentage));
+        if amount > member.balance * MAX_RATIO {
+            return Err(MarketMakerError::InsufficientLiquidity);
+        }
+
+        self.burn(provider_id, shares)?;
+        self.info.tokens[index].balance -= amount;
+        Ok(self.change(provider_id, shares, self.single(index, amount)))
+    }
+
+    fn index(&self, token: &str) -> Result<usize, MarketMakerError> {
+        self.info
+            .tokens
+            .iter()
+            .position(|member| member.token == token)
+            .ok_or_else(|| MarketMakerError::TokenNotInPool(token.to_string()))
+    }
+
+    fn member(&self, token: &str) -> Result<&WeightedToken, MarketMakerError> {
+        Ok(&self.info.tokens[self.index(token)?])
+    }
+
+    fn member_mut(&mut self, token: &str) -> Result<&mut WeightedToken, MarketMakerError> {
+        let index = self.index(token)?;
+        Ok(&mut self.info.tokens[index])
+    }
+
+    fn mint(&mut self, provider_id: Uuid, shares: Decimal) {
+        self.info.total_shares += shares;
+        *self.positions.entry(provider_id).or_default() += shares;
+    }
+
+    fn burn(&mut self, provider_id: Uuid, shares: Decimal) -> Result<(), MarketMakerError> {
+        if shares <= Decimal::ZERO {
+            return Err(MarketMakerError::InvalidAmount);
+        }
+        let held = self.positions
+            .get_mut(&provider_id)
+            .ok_or(MarketMakerError::InsufficientLiquidity)?;
+        if shares > *held {
+            return Err(MarketMakerError::InsufficientLiquidity);
+        }
+        *held -= shares;
+        if held.is_zero() {
+            self.positions.remove(&provider_id);
+        }
+        self.info.total_shares -= shares;
+        Ok(())
+    }
+
+    /// `amount` of the token at `index` and zero of the others.
+    fn single(&self, index: usize, amount: Decimal) -> Vec<Decimal> {
+        (0..self.info.tokens.len())
+            .map(|i| if i == index { amount } else { Decimal::ZERO })
+            .collect()
+    }
+
+    fn change(&self, provider_id: Uuid, shares: Decimal, amounts: Vec<Decimal>) -> WeightedLiquidityChange {
+        WeightedLiquidityChange {
+            pool_id: self.info.id,
+            provider_id,
+            shares,
+            amounts: self
+                .info
+                .tokens
+                .iter()
+                .map(|token| token.token.clone())
+                .zip(amounts)
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn token(token: &str, weight: Decimal, balance: Decimal) -> WeightedToken {
+        WeightedToken {
+            token: token.to_string(),
+            weight,
+            balance,
+        }
+    }
+
+    fn third() -> Decimal {
+        Decimal::ONE / dec!(3)
+    }
+
+    fn index_pool() -> WeightedPool {
+        WeightedPool::new(
+            vec![
+                token("BTC", third(), dec!(34)),
+                token("ETH", third(), dec!(660)),
+                token("SOL", third(), dec!(16500)),
+            ],
+            dec!(0.003),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_pool_validation() {
+        let unbalanced = vec![token("BAL", dec!(0.8), dec!(1)), token("WETH", dec!(0.3), dec!(1))];
+        assert!(WeightedPool::new(unbalanced, dec!(0.003)).is_err());
+        let duplicate = vec![token("BAL", dec!(0.5), dec!(1)), token("BAL", dec!(0.5), dec!(1))];
+        assert!(WeightedPool::new(duplicate, dec!(0.003)).is_err());
+        let partly_seeded = vec![token("BAL", dec!(0.5), dec!(1)), token("WETH", dec!(0.5), dec!(0))];
+        assert!(WeightedPool::new(partly_seeded, dec!(0.003)).is_err());
+        let short = vec![token("BTC", dec!(0.34), dec!(1)), token("ETH", dec!(0.33), dec!(1)), token("SOL", dec!(0.32), dec!(1))];
+        assert!(WeightedPool::new(short, dec!(0.003)).is_err());
+    }
+
+    #[test]
+    fn test_weights_within_tolerance_are_normalized() {
+        let pool = index_pool();
+        let weights: Vec<Decimal> = pool.pool_info().tokens.iter().map(|token| token.weight).collect();
+        assert!(weights.iter().all(|weight| *weight == weights[0]));
+        assert!((weights.iter().sum::<Decimal>() - Decimal::ONE).abs() < dec!(0.0000000000000000000001));
+
+        let nearly_half = vec![token("BAL", dec!(0.5000004), dec!(1)), token("WETH", dec!(0.5), dec!(1))];
+        let pool = WeightedPool::new(nearly_half, dec!(0.003)).unwrap();
+        assert!(pool.pool_info().tokens[0].weight < dec!(0.5000004));
+        assert!(pool.pool_info().tokens[0].weight > dec!(0.5));
+    }
+
+    #[test]
+    fn test_spot_price_reflects_weights() {
+        // 80/20: BAL holds four times the value of WETH.
+        let pool = WeightedPool::new(
+            vec![token("BAL", dec!(0.8), dec!(8000)), token("WETH", dec!(0.2), dec!(10))],
+            dec!(0.003),
+        )
+        .unwrap();
+        assert_eq!(pool.spot_price("BAL", "WETH").unwrap(), dec!(200));
+
+        let quote = pool.quote_exact_input("BAL", "WETH", dec!(100)).unwrap();
+        // Slightly under 100 / 200 after the fee and impact.
+        assert!(quote.output_amount < dec!(0.4985) && quote.output_amount > dec!(0.47));
+    }
+
+    #[test]
+    fn test_swap_between_any_members() {
+        let mut pool = index_pool();
+        let invariant = pool.invariant();
+
+        let swap = pool.swap_exact_input("SOL", "ETH", dec!(500), dec!(19)).unwrap();
+        let balances: Vec<Decimal> = pool.pool_info().tokens.iter().map(|token| token.balance).collect();
+        assert_eq!(balances, vec![dec!(34), dec!(660) - swap.output_amount, dec!(17000)]);
+        assert!(pool.invariant() >= invariant);
+
+        assert!(matches!(
+            pool.quote_exact_input("SOL", "DOGE", dec!(1)),
+            Err(MarketMakerError::TokenNotInPool(_))
+        ));
+        assert!(matches!(
+            pool.quote_exact_input("BTC", "ETH", dec!(11)),
+            Err(MarketMakerError::InsufficientLiquidity)
+        ));
+    }
+
+    #[test]
+    fn test_single_asset_join_and_exit() {
+        let mut pool = WeightedPool::new(
+            vec![
+                token("BTC", third(), dec!(0)),
+                token("ETH", third(), dec!(0)),
+                token("SOL", third(), dec!(0)),
+            ],
+            dec!(0.003),
+        )
+        .unwrap();
+        let founder = Uuid::new_v4();
+        pool.join(founder, &[dec!(34), dec!(660), dec!(16500)]).unwrap();
+
+        let provider_id = Uuid::new_v4();
+        let joined = pool.join_single(provider_id, "ETH", dec!(66)).unwrap();
+        assert_eq!(joined.amounts[1], ("ETH".to_string(), dec!(66)));
+        assert_eq!(pool.shares(provider_id), joined.shares);
+
+        let exited = pool.exit_single(provider_id, "ETH", joined.shares).unwrap();
+        assert!(pool.shares(provider_id).is_zero());
+        // Both legs paid the fee on the implicitly swapped part.
+        assert!(exited.amounts[1].1 < dec!(66));
+        assert!(exited.amounts[1].1 > dec!(65.5));
+
+        let half = pool.shares(founder) / dec!(2);
+        let exited = pool.exit(founder, half).unwrap();
+        assert!(exited.amounts.iter().all(|(_, amount)| *amount > Decimal::ZERO));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_invariant_never_decreases(
+            swaps in prop::collection::vec((0usize..3, 1usize..3, 1u64..1_000_000), 1..20),
+        ) {
+            let mut pool = index_pool();
+            let tokens = ["BTC", "ETH", "SOL"];
+
+            for (from, step, micros) in swaps {
+                let (token_in, token_out) = (tokens[from], tokens[(from + step) % 3]);
+                let balance = pool.pool_info().tokens[from].balance;
+                let amount = balance * MAX_RATIO * Decimal::new(micros as i64, 6);
+                let invariant = pool.invariant();
+
+                if pool.swap_exact_input(token_in, token_out, amount, Decimal::ZERO).is_ok() {
+                    prop_assert!(pool.invariant() >= invariant);
+                }
+            }
+        }
+    }
+}
@@ -4,20 +4,20 @@ use uuid::Uuid;
 use std::collections::HashMap;
 use rust_decimal::prelude::Zero;
 
+/// Read-only view of a user's holdings, derived from the ledger journal.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Account {
     pub user_id: Uuid,
-    pub balance: Decimal,
+    pub balances: HashMap<String, Decimal>, // Asset -> Amount
     pub positions: HashMap<String, Decimal>, // Symbol -> Quantity
 }
 
 impl Account {
-    pub fn get_position(&self, symbol: &str) -> Decimal {
-        *self.positions.get(symbol).unwrap_or(&Decimal::zero())
+    pub fn get_balance(&self, asset: &str) -> Decimal {
+        *self.balances.get(asset).unwrap_or(&Decimal::zero())
     }
 
-    pub fn update_position(&mut self, symbol: &str, quantity: Decimal) {
-        let position = self.positions.entry(symbol.to_string()).or_insert(Decimal::zero());
-        *position = quantity;
+    pub fn get_position(&self, symbol: &str) -> Decimal {
+        *self.positions.get(symbol).unwrap_or(&Decimal::zero())
     }
-} 
\ No newline at end of file
+}
@@ -0,0 +1,47 @@
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::order::Order;
+
+/// Why the engine took an order off the book on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExecutionKind {
+    /// A good-till-date order reached its expiry time.
+    Expired,
+    /// The open quantity was cut, e.g. a reduce-only order after the
+    /// position shrank.
+    Restated,
+    /// Cancelled by the engine rather than by the owner.
+    Cancelled,
+}
+
+/// Status update for an order that did not come from a trade.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecutionReport {
+    pub order_id: Uuid,
+    pub client_order_id: Option<String>,
+    pub user_id: Uuid,
+    pub symbol: String,
+    pub kind: ExecutionKind,
+    pub filled_quantity: Decimal,
+    /// Quantity that was still open when the order left the book.
+    pub leaves_quantity: Decimal,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl ExecutionReport {
+    pub fn new(order: &Order, kind: ExecutionKind, timestamp: DateTime<Utc>) -> Self {
+        Self {
+            order_id: order.id,
+            client_order_id: order.client_order_id.clone(),
+            user_id: order.user_id,
+            symbol: order.symbol.clone(),
+            kind,
+            filled_quantity: order.filled_quantity,
+            leaves_quantity: order.remaining_quantity(),
+            timestamp,
+        }
+    }
+}
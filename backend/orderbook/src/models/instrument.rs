@@ -0,0 +1,198 @@
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+
+use super::order::{Order, OrderType};
+use crate::utils::error::{OrderBookError, OrderBookResult};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InstrumentStatus {
+    Trading,
+    Suspended,
+    Delisted,
+}
+
+/// Trading rules for a listed pair.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Instrument {
+    pub symbol: String,
+    pub base_asset: String,
+    pub quote_asset: String,
+    pub tick_size: Decimal,
+    pub lot_size: Decimal,
+    pub min_quantity: Decimal,
+    pub max_quantity: Decimal,
+    pub min_notional: Decimal,
+    pub price_precision: u32,
+    pub status: InstrumentStatus,
+}
+
+impl Instrument {
+    /// A trading instrument with permissive defaults (8 decimal places, no
+    /// size or notional limits).
+    pub fn new(base_asset: &str, quote_asset: &str) -> Self {
+        Self {
+            symbol: format!("{}/{}", base_asset, quote_asset),
+            base_asset: base_asset.to_string(),
+            quote_asset: quote_asset.to_string(),
+            tick_size: dec!(0.00000001),
+            lot_size: dec!(0.00000001),
+            min_quantity: dec!(0.00000001),
+            max_quantity: Decimal::MAX,
+            min_notional: Decimal::ZERO,
+            price_precision: 8,
+            status: InstrumentStatus::Trading,
+        }
+    }
+
+    /// Builds a default instrument from a `BASE/QUOTE` symbol, failing if
+    /// either asset is missing.
+    pub fn from_symbol(symbol: &str) -> OrderBookResult<Self> {
+        match symbol.split_once('/') {
+            Some((base_asset, quote_asset)) if !base_asset.is_empty() && !quote_asset.is_empty() => Ok(Self {
+                symbol: symbol.to_string(),
+                ..Self::new(base_asset, quote_asset)
+            }),
+            _ => Err(OrderBookError::InvalidInstrument(symbol.to_string())),
+        }
+    }
+
+    /// Checks that the definition itself is usable.
+    pub fn validate(&self) -> OrderBookResult<()> {
+        if self.tick_size <= Decimal::ZERO
+            || self.lot_size <= Decimal::ZERO
+            || self.min_quantity <= Decimal::ZERO
+            || self.min_quantity > self.max_quantity
+            || self.min_notional < Decimal::ZERO
+        {
+            return Err(OrderBookError::InvalidInstrument(self.symbol.clone()));
+        }
+        Ok(())
+    }
+
+    /// Checks an inbound order against the instrument's trading rules.
+    pub fn validate_order(&self, order: &Order) -> OrderBookResult<()> {
+        if order.symbol != self.symbol {
+            return Err(OrderBookError::SymbolMismatch(order.symbol.clone()));
+        }
+        if self.status != InstrumentStatus::Trading {
+            return Err(OrderBookError::InstrumentNotTrading(self.symbol.clone()));
+        }
+
+        if order.quantity < self.min_quantity {
+            return Err(OrderBookError::QuantityBelowMinimum(self.min_quantity));
+        }
+        if order.quantity > self.max_quantity {
+            return Err(OrderBookError::QuantityAboveMaximum(self.max_quantity));
+        }
+        if !(order.quantity % self.lot_size).is_zero() {
+            return Err(OrderBookError::InvalidLotSize(self.lot_size));
+        }
+
+        // Market orders carry no meaningful price; pegged prices are set by the book.
+        if order.order_type == OrderType::Market || order.peg.is_some() {
+            return Ok(());
+        }
+
+        if order.price <= Decimal::ZERO {
+            return Err(OrderBookError::InvalidPrice);
+        }
+        if order.price.normalize().scale() > self.price_precision {
+            return Err(OrderBookError::PricePrecisionExceeded(self.price_precision));
+        }
+        if !(order.price % self.tick_size).is_zero() {
+            return Err(OrderBookError::InvalidTickSize(self.tick_size));
+        }
+        if order.price * order.quantity < self.min_notional {
+            return Err(OrderBookError::NotionalBelowMinimum(self.min_notional));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::order::Side;
+    use uuid::Uuid;
+
+    fn btc_usd() -> Instrument {
+        Instrument {
+            tick_size: dec!(0.5),
+            lot_size: dec!(0.001),
+            min_quantity: dec!(0.001),
+            max_quantity: dec!(100),
+            min_notional: dec!(10),
+            price_precision: 1,
+            ..Instrument::new("BTC", "USD")
+        }
+    }
+
+    fn limit(price: Decimal, quantity: Decimal) -> Order {
+        Order::new(Uuid::new_v4(), "BTC/USD".to_string(), Side::Buy, OrderType::Limit, price, quantity)
+    }
+
+    #[test]
+    fn test_from_symbol() {
+        let instrument = Instrument::from_symbol("ETH/USDC").unwrap();
+        assert_eq!(instrument.base_asset, "ETH");
+        assert_eq!(instrument.quote_asset, "USDC");
+        assert!(instrument.validate().is_ok());
+
+        for symbol in ["ETHUSDC", "ETH/", "/USDC", ""] {
+            assert!(matches!(
+                Instrument::from_symbol(symbol),
+                Err(OrderBookError::InvalidInstrument(s)) if s == symbol
+            ));
+        }
+    }
+
+    #[test]
+    fn test_valid_order_accepted() {
+        assert!(btc_usd().validate_order(&limit(dec!(50000.5), dec!(0.01))).is_ok());
+    }
+
+    #[test]
+    fn test_order_rules_rejected() {
+        let instrument = btc_usd();
+
+        assert!(matches!(
+            instrument.validate_order(&limit(dec!(50000.25), dec!(0.01))),
+            Err(OrderBookError::PricePrecisionExceeded(1))
+        ));
+        assert!(matches!(
+            instrument.validate_order(&limit(dec!(50000.3), dec!(0.01))),
+            Err(OrderBookError::InvalidTickSize(_))
+        ));
+        assert!(matches!(
+            instrument.validate_order(&limit(dec!(50000), dec!(0.0015))),
+            Err(OrderBookError::InvalidLotSize(_))
+        ));
+        assert!(matches!(
+            instrument.validate_order(&limit(dec!(50000), dec!(0.0001))),
+            Err(OrderBookError::QuantityBelowMinimum(_))
+        ));
+        assert!(matches!(
+            instrument.validate_order(&limit(dec!(50000), dec!(101))),
+            Err(OrderBookError::QuantityAboveMaximum(_))
+        ));
+        assert!(matches!(
+            instrument.validate_order(&limit(dec!(1), dec!(1))),
+            Err(OrderBookError::NotionalBelowMinimum(_))
+        ));
+    }
+
+    #[test]
+    fn test_suspended_instrument_rejects_orders() {
+        let instrument = Instrument {
+            status: InstrumentStatus::Suspended,
+            ..btc_usd()
+        };
+
+        assert!(matches!(
+            instrument.validate_order(&limit(dec!(50000), dec!(1))),
+            Err(OrderBookError::InstrumentNotTrading(_))
+        ));
+    }
+}
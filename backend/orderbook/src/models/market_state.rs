@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Trading phase of a single order book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MarketState {
+    /// Continuous matching.
+    Open,
+    /// Only cancellations are accepted.
+    CancelOnly,
+    /// Book is frozen: no new orders and no cancellations.
+    Halted,
+    /// Call phase of an auction: limit orders rest without matching.
+    PreOpen,
+    /// Outside trading hours: cancellations only.
+    Closed,
+}
+
+impl MarketState {
+    pub fn accepts_orders(&self) -> bool {
+        matches!(self, MarketState::Open | MarketState::PreOpen)
+    }
+
+    pub fn accepts_cancels(&self) -> bool {
+        !matches!(self, MarketState::Halted)
+    }
+
+    pub fn matches_orders(&self) -> bool {
+        matches!(self, MarketState::Open)
+    }
+}
+
+impl fmt::Display for MarketState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            MarketState::Open => "Open",
+            MarketState::CancelOnly => "CancelOnly",
+            MarketState::Halted => "Halted",
+            MarketState::PreOpen => "PreOpen",
+            MarketState::Closed => "Closed",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_permissions() {
+        assert!(MarketState::Open.accepts_orders() && MarketState::Open.matches_orders());
+        assert!(MarketState::PreOpen.accepts_orders() && !MarketState::PreOpen.matches_orders());
+        assert!(!MarketState::CancelOnly.accepts_orders() && MarketState::CancelOnly.accepts_cancels());
+        assert!(!MarketState::Halted.accepts_orders() && !MarketState::Halted.accepts_cancels());
+        assert!(!MarketState::Closed.accepts_orders() && MarketState::Closed.accepts_cancels());
+    }
+}
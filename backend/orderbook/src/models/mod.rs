@@ -1,3 +1,6 @@
 pub mod order;
 pub mod trade;
-pub mod account;
\ No newline at end of file
+pub mod account;
+pub mod instrument;
+pub mod market_state;
+pub mod execution_report;
@@ -4,7 +4,7 @@ use rust_decimal::prelude::Zero;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Side {
     Buy,
     Sell,
@@ -16,6 +16,24 @@ pub enum OrderType {
     Market,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PegReference {
+    BestBid,
+    BestOffer,
+    /// Hidden; trades only at the midpoint of the best bid and offer.
+    Midpoint,
+}
+
+/// Makes a limit order follow the top of book instead of a fixed price.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Peg {
+    pub reference: PegReference,
+    /// Added to the reference price; unused by midpoint pegs.
+    pub offset: Decimal,
+    /// Highest price a buy (lowest a sell) will be pegged to.
+    pub limit: Option<Decimal>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Order {
     pub id: Uuid,
@@ -26,6 +44,16 @@ pub struct Order {
     pub price: Decimal,
     pub quantity: Decimal,
     pub filled_quantity: Decimal,
+    /// Caller-assigned id, unique per user; resubmitting it replays the
+    /// original result instead of placing a second order.
+    pub client_order_id: Option<String>,
+    /// Good-till-date: the order is expired off the book at this time.
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Pegged orders are repriced by the book; `price` holds the current peg.
+    pub peg: Option<Peg>,
+    /// Only ever reduces the owner's position: capped to it on entry and
+    /// shrunk or cancelled by the book as the position shrinks.
+    pub reduce_only: bool,
     pub timestamp: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
@@ -50,12 +78,59 @@ impl Order {
             price,
             quantity,
             filled_quantity: Decimal::zero(),
+            client_order_id: None,
+            expires_at: None,
+            peg: None,
+            reduce_only: false,
             timestamp: now,
             created_at: now,
             updated_at: now,
         }
     }
 
+    pub fn with_client_order_id(mut self, client_order_id: impl Into<String>) -> Self {
+        self.client_order_id = Some(client_order_id.into());
+        self
+    }
+
+    pub fn with_expiry(mut self, expires_at: DateTime<Utc>) -> Self {
+        self.expires_at = Some(expires_at);
+        self
+    }
+
+    pub fn with_peg(mut self, peg: Peg) -> Self {
+        self.peg = Some(peg);
+        self
+    }
+
+    pub fn reduce_only(mut self) -> Self {
+        self.reduce_only = true;
+        self
+    }
+
+    /// Midpoint pegs rest outside the visible book.
+    pub fn is_hidden(&self) -> bool {
+        self.peg.is_some_and(|peg| peg.reference == PegReference::Midpoint)
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Whether `other` asks for the same order, ignoring server-assigned fields.
+    pub fn same_request(&self, other: &Order) -> bool {
+        self.user_id == other.user_id
+            && self.symbol == other.symbol
+            && self.side == other.side
+            && self.order_type == other.order_type
+            && self.price == other.price
+            && self.quantity == other.quantity
+            && self.client_order_id == other.client_order_id
+            && self.expires_at == other.expires_at
+            && self.peg == other.peg
+            && self.reduce_only == other.reduce_only
+    }
+
     pub fn remaining_quantity(&self) -> Decimal {
         self.quantity - self.filled_quantity
     }
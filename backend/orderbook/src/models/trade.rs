@@ -8,22 +8,57 @@ use super::order::{Order, Side};
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Trade {
     pub id: Uuid,
+    pub symbol: String,
     pub order_id: Uuid,
+    pub taker_order_id: Uuid,
+    pub maker_user_id: Uuid,
+    pub taker_user_id: Uuid,
+    pub taker_side: Side,
     pub price: Decimal,
     pub quantity: Decimal,
+    /// Fee charged to the maker; negative for a rebate.
+    pub maker_fee: Decimal,
+    pub taker_fee: Decimal,
+    pub fee_asset: Option<String>,
     pub created_at: DateTime<Utc>,
 }
 
 impl Trade {
-    pub fn new(maker_order: &Order, _taker_order: &Order, quantity: Decimal) -> Self {
+    pub fn new(maker_order: &Order, taker_order: &Order, quantity: Decimal) -> Self {
         Self {
             id: Uuid::new_v4(),
+            symbol: maker_order.symbol.clone(),
             order_id: maker_order.id,
+            taker_order_id: taker_order.id,
+            maker_user_id: maker_order.user_id,
+            taker_user_id: taker_order.user_id,
+            taker_side: taker_order.side,
             price: maker_order.price,
             quantity,
+            maker_fee: Decimal::ZERO,
+            taker_fee: Decimal::ZERO,
+            fee_asset: None,
             created_at: Utc::now(),
         }
     }
+
+    pub fn buyer_id(&self) -> Uuid {
+        match self.taker_side {
+            Side::Buy => self.taker_user_id,
+            Side::Sell => self.maker_user_id,
+        }
+    }
+
+    pub fn seller_id(&self) -> Uuid {
+        match self.taker_side {
+            Side::Buy => self.maker_user_id,
+            Side::Sell => self.taker_user_id,
+        }
+    }
+
+    pub fn notional(&self) -> Decimal {
+        self.price * self.quantity
+    }
 }
 
 #[cfg(test)]
@@ -57,5 +92,8 @@ mod tests {
         assert_eq!(trade.order_id, maker_order.id);
         assert_eq!(trade.price, dec!(50000));
         assert_eq!(trade.quantity, dec!(1));
+        assert_eq!(trade.taker_order_id, taker_order.id);
+        assert_eq!(trade.buyer_id(), taker_order.user_id);
+        assert_eq!(trade.seller_id(), maker_order.user_id);
     }
 }
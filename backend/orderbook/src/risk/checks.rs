@@ -0,0 +1,229 @@
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use uuid::Uuid;
+
+use super::types::{RiskCheck, RiskContext, RiskLimits, RiskViolation};
+use crate::models::order::{Order, OrderType, Side};
+
+pub struct MaxOrderSize;
+
+impl RiskCheck for MaxOrderSize {
+    fn name(&self) -> &'static str {
+        "max_order_size"
+    }
+
+    fn check(&self, order: &Order, limits: &RiskLimits, _context: &RiskContext) -> Result<(), RiskViolation> {
+        match limits.max_order_quantity {
+            Some(limit) if order.quantity > limit => Err(RiskViolation::OrderSizeExceeded { limit }),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub struct MaxNotional;
+
+impl RiskCheck for MaxNotional {
+    fn name(&self) -> &'static str {
+        "max_notional"
+    }
+
+    fn check(&self, order: &Order, limits: &RiskLimits, context: &RiskContext) -> Result<(), RiskViolation> {
+        let Some(limit) = limits.max_notional else {
+            return Ok(());
+        };
+        // Market orders are valued at the reference price when there is one.
+        let price = match order.order_type {
+            OrderType::Market => context.reference_price,
+            _ => Some(order.price),
+        };
+        match price {
+            Some(price) if price * order.quantity > limit => Err(RiskViolation::NotionalExceeded { limit }),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub struct MaxOpenOrders;
+
+impl RiskCheck for MaxOpenOrders {
+    fn name(&self) -> &'static str {
+        "max_open_orders"
+    }
+
+    fn check(&self, _order: &Order, limits: &RiskLimits, context: &RiskContext) -> Result<(), RiskViolation> {
+        match limits.max_open_orders {
+            Some(limit) if context.open_orders >= limit => Err(RiskViolation::TooManyOpenOrders { limit }),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub struct PositionLimit;
+
+impl RiskCheck for PositionLimit {
+    fn name(&self) -> &'static str {
+        "position_limit"
+    }
+
+    fn check(&self, order: &Order, limits: &RiskLimits, context: &RiskContext) -> Result<(), RiskViolation> {
+        let Some(limit) = limits.position_limits.get(&order.symbol).copied() else {
+            return Ok(());
+        };
+        let projected = match order.side {
+            Side::Buy => context.position + order.quantity,
+            Side::Sell => context.position - order.quantity,
+        };
+        if projected.abs() > limit {
+            Err(RiskViolation::PositionLimitExceeded { limit, projected })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+pub struct FatFingerPrice;
+
+impl RiskCheck for FatFingerPrice {
+    fn name(&self) -> &'static str {
+        "fat_finger_price"
+    }
+
+    fn check(&self, order: &Order, limits: &RiskLimits, context: &RiskContext) -> Result<(), RiskViolation> {
+        let (Some(band), Some(reference)) = (limits.fat_finger_band, context.reference_price) else {
+            return Ok(());
+        };
+        if order.order_type == OrderType::Market || reference.is_zero() {
+            return Ok(());
+        }
+        if ((order.price - reference) / reference).abs() > band {
+            Err(RiskViolation::FatFingerPrice {
+                price: order.price,
+                reference,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Sliding-window limit on order submissions per user.
+#[derive(Default)]
+pub struct OrderRateLimit {
+    submissions: Mutex<HashMap<Uuid, VecDeque<DateTime<Utc>>>>,
+}
+
+impl OrderRateLimit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl RiskCheck for OrderRateLimit {
+    fn name(&self) -> &'static str {
+        "order_rate_limit"
+    }
+
+    fn check(&self, order: &Order, limits: &RiskLimits, context: &RiskContext) -> Result<(), RiskViolation> {
+        let Some(limit) = limits.max_orders_per_window else {
+            return Ok(());
+        };
+        let cutoff = context.now - limits.rate_window;
+        let mut submissions = self.submissions.lock();
+        let history = submissions.entry(order.user_id).or_default();
+        while matches!(history.front(), Some(at) if *at <= cutoff) {
+            history.pop_front();
+        }
+
+        if history.len() >= limit {
+            return Err(RiskViolation::RateLimited { limit });
+        }
+        history.push_back(context.now);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    fn context() -> RiskContext {
+        RiskContext {
+            open_orders: 0,
+            position: Decimal::ZERO,
+            reference_price: Some(dec!(100)),
+            now: Utc::now(),
+        }
+    }
+
+    fn order(side: Side, price: Decimal, quantity: Decimal) -> Order {
+        Order::new(Uuid::new_v4(), "BTC/USD".to_string(), side, OrderType::Limit, price, quantity)
+    }
+
+    #[test]
+    fn test_size_and_notional() {
+        let limits = RiskLimits {
+            max_order_quantity: Some(dec!(10)),
+            max_notional: Some(dec!(500)),
+            ..RiskLimits::default()
+        };
+
+        assert!(MaxOrderSize.check(&order(Side::Buy, dec!(1), dec!(11)), &limits, &context()).is_err());
+        assert!(MaxNotional.check(&order(Side::Buy, dec!(100), dec!(6)), &limits, &context()).is_err());
+        assert!(MaxNotional.check(&order(Side::Buy, dec!(100), dec!(5)), &limits, &context()).is_ok());
+    }
+
+    #[test]
+    fn test_position_limit_uses_projected_position() {
+        let mut limits = RiskLimits::default();
+        limits.position_limits.insert("BTC/USD".to_string(), dec!(5));
+        let long = RiskContext {
+            position: dec!(4),
+            ..context()
+        };
+
+        assert!(matches!(
+            PositionLimit.check(&order(Side::Buy, dec!(100), dec!(2)), &limits, &long),
+            Err(RiskViolation::PositionLimitExceeded { projected, .. }) if projected == dec!(6)
+        ));
+        assert!(PositionLimit.check(&order(Side::Sell, dec!(100), dec!(8)), &limits, &long).is_ok());
+    }
+
+    #[test]
+    fn test_fat_finger() {
+        let limits = RiskLimits {
+            fat_finger_band: Some(dec!(0.2)),
+            ..RiskLimits::default()
+        };
+
+        assert!(FatFingerPrice.check(&order(Side::Buy, dec!(1000), dec!(1)), &limits, &context()).is_err());
+        assert!(FatFingerPrice.check(&order(Side::Buy, dec!(110), dec!(1)), &limits, &context()).is_ok());
+    }
+
+    #[test]
+    fn test_rate_limit_window() {
+        let check = OrderRateLimit::new();
+        let limits = RiskLimits {
+            max_orders_per_window: Some(2),
+            ..RiskLimits::default()
+        };
+        let user_order = order(Side::Buy, dec!(100), dec!(1));
+        let ctx = context();
+
+        assert!(check.check(&user_order, &limits, &ctx).is_ok());
+        assert!(check.check(&user_order, &limits, &ctx).is_ok());
+        assert!(matches!(
+            check.check(&user_order, &limits, &ctx),
+            Err(RiskViolation::RateLimited { limit: 2 })
+        ));
+
+        let later = RiskContext {
+            now: ctx.now + Duration::seconds(2),
+            ..ctx
+        };
+        assert!(check.check(&user_order, &limits, &later).is_ok());
+    }
+}
@@ -0,0 +1,174 @@
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use super::checks::{FatFingerPrice, MaxNotional, MaxOpenOrders, MaxOrderSize, OrderRateLimit, PositionLimit};
+use super::types::{RiskCheck, RiskContext, RiskLimits, RiskRejection, RiskViolation};
+use crate::models::order::Order;
+use crate::utils::clock::{Clock, SystemClock};
+
+/// Pre-trade risk layer run before an order reaches the book.
+///
+/// Checks run in registration order and the first violation rejects the
+/// order. Every rejection is logged and kept for compliance review.
+pub struct RiskEngine {
+    checks: Vec<Box<dyn RiskCheck>>,
+    default_limits: RiskLimits,
+    account_limits: DashMap<Uuid, RiskLimits>,
+    rejections: Mutex<Vec<RiskRejection>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for RiskEngine {
+    fn default() -> Self {
+        Self::new(RiskLimits::default())
+    }
+}
+
+impl RiskEngine {
+    /// Engine with the standard checks; the rate limit runs last so rejected
+    /// orders do not consume rate.
+    pub fn new(default_limits: RiskLimits) -> Self {
+        Self::with_checks(
+            default_limits,
+            vec![
+                Box::new(MaxOrderSize),
+                Box::new(MaxNotional),
+                Box::new(MaxOpenOrders),
+                Box::new(PositionLimit),
+                Box::new(FatFingerPrice),
+                Box::new(OrderRateLimit::new()),
+            ],
+        )
+    }
+
+    pub fn with_checks(default_limits: RiskLimits, checks: Vec<Box<dyn RiskCheck>>) -> Self {
+        Self {
+            checks,
+            default_limits,
+            account_limits: DashMap::new(),
+            rejections: Mutex::new(Vec::new()),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Appends a custom check after the existing ones.
+    pub fn add_check(&mut self, check: Box<dyn RiskCheck>) {
+        self.checks.push(check);
+    }
+
+    pub fn set_limits(&self, user_id: Uuid, limits: RiskLimits) {
+        self.account_limits.insert(user_id, limits);
+    }
+
+    pub fn limits(&self, user_id: Uuid) -> RiskLimits {
+        self.account_limits
+            .get(&user_id)
+            .map(|limits| limits.clone())
+            .unwrap_or_else(|| self.default_limits.clone())
+    }
+
+    pub fn now(&self) -> chrono::DateTime<chrono::Utc> {
+        self.clock.now()
+    }
+
+    pub fn check(&self, order: &Order, context: &RiskContext) -> Result<(), RiskViolation> {
+        let limits = self.limits(order.user_id);
+        for check in &self.checks {
+            if let Err(violation) = check.check(order, &limits, context) {
+                log::warn!(
+                    "risk: rejected order {} for user {} on {} by {}: {}",
+                    order.id, order.user_id, order.symbol, check.name(), violation
+                );
+                self.rejections.lock().push(RiskRejection {
+                    order_id: order.id,
+                    user_id: order.user_id,
+                    symbol: order.symbol.clone(),
+                    check: check.name().to_string(),
+                    reason: violation.to_string(),
+                    rejected_at: context.now,
+                });
+                return Err(violation);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn rejections(&self) -> Vec<RiskRejection> {
+        self.rejections.lock().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::order::{OrderType, Side};
+    use chrono::Utc;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    struct NoShorts;
+
+    impl RiskCheck for NoShorts {
+        fn name(&self) -> &'static str {
+            "no_shorts"
+        }
+
+        fn check(&self, order: &Order, _limits: &RiskLimits, context: &RiskContext) -> Result<(), RiskViolation> {
+            if order.side == Side::Sell && context.position < order.quantity {
+                Err(RiskViolation::PositionLimitExceeded {
+                    limit: Decimal::ZERO,
+                    projected: context.position - order.quantity,
+                })
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    fn context() -> RiskContext {
+        RiskContext {
+            open_orders: 0,
+            position: Decimal::ZERO,
+            reference_price: None,
+            now: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_per_account_limits() {
+        let engine = RiskEngine::default();
+        let user_id = Uuid::new_v4();
+        engine.set_limits(user_id, RiskLimits {
+            max_order_quantity: Some(dec!(1)),
+            ..RiskLimits::default()
+        });
+
+        let big = Order::new(user_id, "BTC/USD".to_string(), Side::Buy, OrderType::Limit, dec!(100), dec!(2));
+        let other = Order { user_id: Uuid::new_v4(), ..big.clone() };
+
+        assert!(matches!(engine.check(&big, &context()), Err(RiskViolation::OrderSizeExceeded { .. })));
+        assert!(engine.check(&other, &context()).is_ok());
+
+        let rejections = engine.rejections();
+        assert_eq!(rejections.len(), 1);
+        assert_eq!(rejections[0].order_id, big.id);
+        assert_eq!(rejections[0].check, "max_order_size");
+    }
+
+    #[test]
+    fn test_custom_check() {
+        let mut engine = RiskEngine::default();
+        engine.add_check(Box::new(NoShorts));
+
+        let sell = Order::new(Uuid::new_v4(), "BTC/USD".to_string(), Side::Sell, OrderType::Limit, dec!(100), dec!(1));
+        assert!(engine.check(&sell, &context()).is_err());
+        assert_eq!(engine.rejections()[0].check, "no_shorts");
+    }
+}
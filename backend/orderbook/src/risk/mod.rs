@@ -0,0 +1,6 @@
+pub mod checks;
+pub mod engine;
+pub mod types;
+
+pub use engine::RiskEngine;
+pub use types::{RiskCheck, RiskContext, RiskLimits, RiskViolation};
@@ -0,0 +1,83 @@
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::models::order::Order;
+
+/// Per-account pre-trade limits. `None` disables the corresponding check.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RiskLimits {
+    pub max_order_quantity: Option<Decimal>,
+    pub max_notional: Option<Decimal>,
+    pub max_open_orders: Option<usize>,
+    /// Symbol -> maximum absolute position after the order fills.
+    pub position_limits: HashMap<String, Decimal>,
+    /// Maximum distance of a limit price from the reference, e.g. `0.1` for 10%.
+    pub fat_finger_band: Option<Decimal>,
+    pub max_orders_per_window: Option<usize>,
+    pub rate_window: Duration,
+}
+
+impl Default for RiskLimits {
+    fn default() -> Self {
+        Self {
+            max_order_quantity: None,
+            max_notional: None,
+            max_open_orders: None,
+            position_limits: HashMap::new(),
+            fat_finger_band: None,
+            max_orders_per_window: None,
+            rate_window: Duration::seconds(1),
+        }
+    }
+}
+
+/// Book and account state a check may need.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RiskContext {
+    pub open_orders: usize,
+    pub position: Decimal,
+    pub reference_price: Option<Decimal>,
+    pub now: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum RiskViolation {
+    #[error("Order quantity exceeds limit {limit}")]
+    OrderSizeExceeded { limit: Decimal },
+    #[error("Order notional exceeds limit {limit}")]
+    NotionalExceeded { limit: Decimal },
+    #[error("Open order limit {limit} reached")]
+    TooManyOpenOrders { limit: usize },
+    #[error("Position would reach {projected}, limit {limit}")]
+    PositionLimitExceeded { limit: Decimal, projected: Decimal },
+    #[error("Price {price} is too far from reference {reference}")]
+    FatFingerPrice { price: Decimal, reference: Decimal },
+    #[error("Order rate limit {limit} reached")]
+    RateLimited { limit: usize },
+}
+
+/// A single pluggable pre-trade check.
+pub trait RiskCheck: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn check(
+        &self,
+        order: &Order,
+        limits: &RiskLimits,
+        context: &RiskContext,
+    ) -> Result<(), RiskViolation>;
+}
+
+/// Compliance log entry for a rejected order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RiskRejection {
+    pub order_id: Uuid,
+    pub user_id: Uuid,
+    pub symbol: String,
+    pub check: String,
+    pub reason: String,
+    pub rejected_at: DateTime<Utc>,
+}
@@ -0,0 +1,62 @@
+use chrono::{DateTime, Duration, Utc};
+use parking_lot::Mutex;
+
+/// Source of the current time, injectable so time-based logic is testable.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Clock that only moves when told to.
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+impl ManualClock {
+    pub fn new(start: DateTime<Utc>) -> Self {
+        Self {
+            now: Mutex::new(start),
+        }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock() = now;
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let mut now = self.now.lock();
+        *now += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock_advance() {
+        let start = Utc::now();
+        let clock = ManualClock::new(start);
+
+        clock.advance(Duration::seconds(30));
+        assert_eq!(clock.now(), start + Duration::seconds(30));
+
+        clock.set(start);
+        assert_eq!(clock.now(), start);
+    }
+}
@@ -1,11 +1,39 @@
+use rust_decimal::Decimal;
 use std::error::Error;
 use std::fmt;
+use uuid::Uuid;
+
+use crate::ledger::LedgerError;
+use crate::models::market_state::MarketState;
+use crate::risk::RiskViolation;
 
 #[derive(Debug)]
 pub enum OrderBookError {
     OrderNotFound,
     InsufficientQuantity,
     InvalidPrice,
+    Settlement(LedgerError),
+    UnknownInstrument(String),
+    InstrumentAlreadyListed(String),
+    InvalidInstrument(String),
+    InstrumentNotTrading(String),
+    SymbolMismatch(String),
+    InvalidTickSize(Decimal),
+    InvalidLotSize(Decimal),
+    QuantityBelowMinimum(Decimal),
+    QuantityAboveMaximum(Decimal),
+    NotionalBelowMinimum(Decimal),
+    PricePrecisionExceeded(u32),
+    MarketStateRejected(MarketState),
+    PriceOutsideBand { lower: Decimal, upper: Decimal },
+    RiskRejected(RiskViolation),
+    UnknownSession(Uuid),
+    BatchRejected { index: usize, reason: Box<OrderBookError> },
+    DuplicateClientOrderId(String),
+    OrderExpired,
+    UnknownOrderGroup(Uuid),
+    NoPegReference,
+    ReduceOnlyRejected,
 }
 
 impl fmt::Display for OrderBookError {
@@ -14,10 +42,44 @@ impl fmt::Display for OrderBookError {
             OrderBookError::OrderNotFound => write!(f, "Order not found"),
             OrderBookError::InsufficientQuantity => write!(f, "Insufficient quantity"),
             OrderBookError::InvalidPrice => write!(f, "Invalid price"),
+            OrderBookError::Settlement(err) => write!(f, "Settlement failed: {}", err),
+            OrderBookError::UnknownInstrument(symbol) => write!(f, "Unknown instrument {}", symbol),
+            OrderBookError::InstrumentAlreadyListed(symbol) => write!(f, "Instrument {} is already listed", symbol),
+            OrderBookError::InvalidInstrument(symbol) => write!(f, "Invalid instrument definition for {}", symbol),
+            OrderBookError::InstrumentNotTrading(symbol) => write!(f, "Instrument {} is not trading", symbol),
+            OrderBookError::SymbolMismatch(symbol) => write!(f, "Order symbol {} does not match the book", symbol),
+            OrderBookError::InvalidTickSize(tick) => write!(f, "Price is not a multiple of tick size {}", tick),
+            OrderBookError::InvalidLotSize(lot) => write!(f, "Quantity is not a multiple of lot size {}", lot),
+            OrderBookError::QuantityBelowMinimum(min) => write!(f, "Quantity below minimum {}", min),
+            OrderBookError::QuantityAboveMaximum(max) => write!(f, "Quantity above maximum {}", max),
+            OrderBookError::NotionalBelowMinimum(min) => write!(f, "Notional below minimum {}", min),
+            OrderBookError::PricePrecisionExceeded(dp) => write!(f, "Price has more than {} decimal places", dp),
+            OrderBookError::MarketStateRejected(state) => write!(f, "Request not accepted while market is {}", state),
+            OrderBookError::PriceOutsideBand { lower, upper } => write!(f, "Price outside band [{}, {}]", lower, upper),
+            OrderBookError::RiskRejected(violation) => write!(f, "Rejected by risk checks: {}", violation),
+            OrderBookError::UnknownSession(session_id) => write!(f, "Unknown session {}", session_id),
+            OrderBookError::BatchRejected { index, reason } => write!(f, "Batch rejected at operation {}: {}", index, reason),
+            OrderBookError::DuplicateClientOrderId(id) => write!(f, "Client order id {} already used for a different order", id),
+            OrderBookError::OrderExpired => write!(f, "Order expiry is not in the future"),
+            OrderBookError::UnknownOrderGroup(group_id) => write!(f, "Unknown order group {}", group_id),
+            OrderBookError::NoPegReference => write!(f, "No top of book to peg to"),
+            OrderBookError::ReduceOnlyRejected => write!(f, "Reduce-only order would not reduce the position"),
         }
     }
 }
 
 impl Error for OrderBookError {}
 
+impl From<LedgerError> for OrderBookError {
+    fn from(err: LedgerError) -> Self {
+        OrderBookError::Settlement(err)
+    }
+}
+
+impl From<RiskViolation> for OrderBookError {
+    fn from(violation: RiskViolation) -> Self {
+        OrderBookError::RiskRejected(violation)
+    }
+}
+
 pub type OrderBookResult<T> = Result<T, OrderBookError>;